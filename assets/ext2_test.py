@@ -0,0 +1,32 @@
+import os
+import shutil
+import subprocess
+import sys
+import tempfile
+
+# Regenerates ext2_test.img, the image mounted by the ext2 kernel tests.
+# Needs mke2fs and debugfs from e2fsprogs; the UUID, hash seed and timestamps are fixed so the output is reproducible.
+IMAGE = os.path.join(os.path.dirname(os.path.abspath(__file__)), "ext2_test.img")
+UUID = "c1ea0000-0000-4000-8000-000000000e72"
+TIMESTAMP = "1688947200"  # 2023-07-10 00:00:00 UTC
+
+if shutil.which("mke2fs") is None or shutil.which("debugfs") is None:
+    print("mke2fs or debugfs not found, install e2fsprogs")
+    sys.exit(1)
+
+with tempfile.TemporaryDirectory() as seed:
+    with open(os.path.join(seed, "hello.txt"), "w") as f:
+        f.write("hello ext2")
+    for path in [os.path.join(seed, "hello.txt"), seed]:
+        os.utime(path, (int(TIMESTAMP), int(TIMESTAMP)))
+    # mke2fs reuses an existing file, so start from an empty one
+    open(IMAGE, "wb").close()
+    env = dict(os.environ, E2FSPROGS_FAKE_TIME=TIMESTAMP)
+    subprocess.run(["mke2fs", "-q", "-t", "ext2", "-b", "1024", "-N", "32", "-m", "0",
+                    "-O", "^resize_inode,^dir_index,^ext_attr", "-U", UUID, "-E", f"hash_seed={UUID}",
+                    "-d", seed, "-L", "test", IMAGE, "256"], check=True, env=env)
+    # mke2fs copies the ctime of the seed file, which utime cannot set
+    subprocess.run(["debugfs", "-w", "-R", f"set_inode_field /hello.txt ctime @{TIMESTAMP}", IMAGE],
+                   check=True, env=env, stdout=subprocess.DEVNULL, stderr=subprocess.DEVNULL)
+
+print("Generated " + IMAGE)
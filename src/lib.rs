@@ -39,14 +39,22 @@ pub fn init(bootinfo: &'static BootInfo) {
     println!("\n\nInitializing the memory...\n");
     syskrnl::memory::init(bootinfo);
 
-    // 启用各类IO设备
-    syskrnl::io::ahci::init();
+    // 时钟要在IO设备之前启用：驱动等待磁盘时用到`nanowait`
     syskrnl::time::init();
+
+    // 启用各类IO设备
+    syskrnl::io::pci::init();
+    syskrnl::io::ata::init();
+    syskrnl::fs::init();
+    syskrnl::net::init();
     syskrnl::task::keyboard::init();
-    syskrnl::io::mouse::init();
+    syskrnl::io::uart::init();
 
-    // 最后，再启动gui
-    syskrnl::gui::init();
+    // 最后，再启动鼠标和gui；无头模式下没有图形界面，输出只走串口和VGA文本模式
+    if !syskrnl::io::HEADLESS {
+        syskrnl::io::mouse::init();
+        syskrnl::gui::init();
+    }
 }
 
 pub fn hlt_loop() -> ! {
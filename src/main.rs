@@ -15,9 +15,12 @@ use bootloader::{BootInfo, entry_point};
 use x86::int;
 
 use cinea_os::{debugln, hlt_loop, println, syskrnl};
+use cinea_os::syskrnl::fs::cache::flush_handler;
+use cinea_os::syskrnl::net::net_handler;
 use cinea_os::syskrnl::task::executor::Executor;
 use cinea_os::syskrnl::task::keyboard::key_presses_handler;
 use cinea_os::syskrnl::task::mouse::mouse_handler;
+use cinea_os::syskrnl::task::serial::serial_input_handler;
 use cinea_os::syskrnl::task::Task;
 
 #[cfg(not(test))]
@@ -68,7 +71,12 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let mut executor = Executor::new();
     //executor.spawn(Task::new(example_task()));
     executor.spawn(Task::new(key_presses_handler()));
-    executor.spawn(Task::new(mouse_handler()));
+    if !syskrnl::io::HEADLESS {
+        executor.spawn(Task::new(mouse_handler()));
+    }
+    executor.spawn(Task::new(serial_input_handler()));
+    executor.spawn(Task::new(flush_handler()));
+    executor.spawn(Task::new(net_handler()));
     executor.run();
 }
 
@@ -75,6 +75,19 @@ pub const READ: usize = 0x24;
 pub const WRITE_PATH: usize = 0x25;
 pub const READ_PATH: usize = 0x26;
 pub const SPAWN_FROM_PATH: usize = 0x27;
+/// write from the position of a handle, may write less than the buffer
+pub const WRITE: usize = 0x28;
+/// move the position of a handle
+pub const SEEK: usize = 0x29;
+/// truncate or extend the file of a handle
+pub const FTRUNCATE: usize = 0x2A;
+/// read a whole file, the buffer is sized from its metadata
+pub const READ_ALL: usize = 0x2B;
+pub const CREATE: usize = 0x2C;
+pub const MKDIR: usize = 0x2D;
+pub const RENAME: usize = 0x2E;
+/// truncate or extend the file of a path
+pub const TRUNCATE: usize = 0x2F;
 pub const CREATE_WINDOW: usize = 0x30;
 pub const DISPLAY_FONT_STRING: usize = 0x31;
 pub const LOAD_FONT: usize = 0x32;
@@ -82,6 +95,41 @@ pub const REMOVE_FONT: usize = 0x33;
 pub const DESTROY_WINDOW: usize = 0x34;
 pub const GUI_SUBSCRIBE_TIME_UPDATE: usize = 0x35;
 pub const GUI_SUBSCRIBE_KEYBOARD: usize = 0x36;
+/// change the working directory of the current process
+pub const CHDIR: usize = 0x40;
+/// get the working directory of the current process
+pub const GETCWD: usize = 0x41;
+/// get an environment variable of the current process
+pub const GETENV: usize = 0x42;
+/// set or remove an environment variable of the current process
+pub const SETENV: usize = 0x43;
+/// get all environment variables of the current process
+pub const ENVIRON: usize = 0x44;
+/// apply or release an advisory lock on an opened file
+pub const FLOCK: usize = 0x50;
+/// open a directory for reading its entries in batches
+pub const OPENDIR: usize = 0x51;
+/// read the next batch of entries: a0-postcarded (handle, count) ret-postcarded Vec-FE
+pub const READDIR: usize = 0x52;
+pub const CLOSEDIR: usize = 0x53;
+/// write every dirty block in the kernel block cache back to its disk: ret-postcarded Result<(), FileError>
+pub const SYNC: usize = 0x54;
+/// create a socket: a0-postcarded SocketType ret-postcarded Result<handle, NetError>
+pub const SOCKET: usize = 0x55;
+/// bind a UDP socket to a local port: a0-postcarded (handle, port)
+pub const BIND: usize = 0x56;
+/// make a TCP socket listen on a local port: a0-postcarded (handle, port)
+pub const LISTEN: usize = 0x57;
+/// start connecting a TCP socket, does not wait: a0-postcarded (handle, SocketAddr)
+pub const CONNECT: usize = 0x58;
+/// queue data without waiting: a0-postcarded (handle, Vec-u8, Option-SocketAddr) ret-postcarded Result<usize, NetError>
+pub const SEND: usize = 0x59;
+/// take received data without waiting: a0-postcarded (handle, max_len) ret-postcarded Result<(Vec-u8, Option-SocketAddr), NetError>
+pub const RECV: usize = 0x5A;
+/// query the state of a socket: a0-postcarded handle ret-postcarded Result<SocketStatus, NetError>
+pub const SOCKET_STATUS: usize = 0x5B;
+/// close a socket, a TCP connection is closed in the background: a0-postcarded handle
+pub const CLOSE_SOCKET: usize = 0x5C;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SysCallResult {
@@ -0,0 +1,31 @@
+//! Environment variables of the current process.
+//!
+//! A process inherits the environment of its parent when spawned, unless an explicit map is
+//! given to [`crate::fs::spawn_from_path_with_env`].
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+
+use crate::call::*;
+
+/// Returns the value of the environment variable `key`, if it is set.
+pub fn var(key: &str) -> Option<String> {
+    let ret: Result<Option<String>, _> = syscall_with_serdeser!(GETENV, String::from(key));
+    ret.unwrap_or(None)
+}
+
+/// Returns all the environment variables of the current process.
+pub fn vars() -> BTreeMap<String, String> {
+    let ret: Result<BTreeMap<String, String>, _> = syscall_with_deserialize!(ENVIRON);
+    ret.unwrap_or_default()
+}
+
+/// Set the environment variable `key` to `val`.
+pub fn set_var(key: &str, val: &str) {
+    syscall_with_serialize!(SETENV, (String::from(key), Some(String::from(val))));
+}
+
+/// Remove the environment variable `key`.
+pub fn remove_var(key: &str) {
+    syscall_with_serialize!(SETENV, (String::from(key), Option::<String>::None));
+}
@@ -1,5 +1,5 @@
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::string::String;
-use alloc::vec;
 use alloc::vec::Vec;
 use core::fmt::Debug;
 
@@ -10,12 +10,26 @@ use ufmt::uDebug;
 use FileError::BadRelatePathError;
 
 use crate::call::*;
-use crate::fs::FileError::NotAFileError;
 use crate::time::{Date, DateTime};
 
 pub trait FileIO: Send + Sync {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()>;
     fn write(&mut self, buf: &[u8]) -> Result<usize, ()>;
+
+    /// Reads at a byte offset. Character devices have no position and ignore the offset.
+    fn read_at(&mut self, _offset: u64, buf: &mut [u8]) -> Result<usize, ()> {
+        self.read(buf)
+    }
+
+    /// Writes at a byte offset. Character devices have no position and ignore the offset.
+    fn write_at(&mut self, _offset: u64, buf: &[u8]) -> Result<usize, ()> {
+        self.write(buf)
+    }
+
+    /// Size in bytes, `0` for devices without a size.
+    fn size(&self) -> u64 {
+        0
+    }
 }
 
 /// Returns the directory component of a pathname.
@@ -157,6 +171,18 @@ pub enum FileError {
     OpenMethodError,
     /// Returned when a device I/O error occurs.
     DeviceIOError,
+    /// Returned when the target of a rename already exists.
+    AlreadyExistsError,
+    /// Returned when the filesystem does not support the operation, e.g. creating files under `/dev`.
+    UnsupportedError,
+    /// Returned when writing through a handle opened without [`OpenFlags::WRITE`], or to a filesystem mounted read-only.
+    ReadOnlyError,
+    /// Returned when reading through a handle opened without [`OpenFlags::READ`].
+    WriteOnlyError,
+    /// Returned when the [`OpenFlags`] passed to `open` do not make sense together.
+    InvalidFlagsError,
+    /// Returned when there are no free blocks or inodes left on the disk.
+    NoSpaceError,
     /// Returned for miscellaneous OS errors.
     OSError,
 }
@@ -174,11 +200,74 @@ impl uDebug for FileError {
             FileError::FileBusyError => w.write_str("FileBusyError"),
             FileError::OpenMethodError => w.write_str("OpenMethodError"),
             FileError::DeviceIOError => w.write_str("DeviceIOError"),
+            FileError::AlreadyExistsError => w.write_str("AlreadyExistsError"),
+            FileError::UnsupportedError => w.write_str("UnsupportedError"),
+            FileError::ReadOnlyError => w.write_str("ReadOnlyError"),
+            FileError::WriteOnlyError => w.write_str("WriteOnlyError"),
+            FileError::InvalidFlagsError => w.write_str("InvalidFlagsError"),
+            FileError::NoSpaceError => w.write_str("NoSpaceError"),
             FileError::OSError => w.write_str("OSError"),
         }
     }
 }
 
+/// Enumeration of possible methods to seek within an opened file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SeekFrom {
+    /// Sets the position to the provided number of bytes.
+    Start(u64),
+    /// Sets the position to the size of the file plus the specified number of bytes.
+    End(i64),
+    /// Sets the position to the current position plus the specified number of bytes.
+    Current(i64),
+}
+
+/// Advisory lock operations for [`flock`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LockMode {
+    /// A shared lock, can be held by several handles at the same time.
+    Shared,
+    /// An exclusive lock, can be held by only one handle.
+    Exclusive,
+    /// Release the lock held by the handle.
+    Unlock,
+}
+
+bitflags! {
+    /// Modes for opening a file.
+    ///
+    /// At least one of `READ` and `WRITE` is required. `APPEND` and `TRUNC` need `WRITE`, and `EXCL` needs `CREATE`.
+    #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct OpenFlags: u8 {
+        /// Open for reading.
+        const READ   = 0x01;
+        /// Open for writing.
+        const WRITE  = 0x02;
+        /// Create the file if it does not exist.
+        const CREATE = 0x04;
+        /// With `CREATE`, fail with `AlreadyExistsError` if the file exists.
+        const EXCL   = 0x08;
+        /// Every write goes to the end of the file.
+        const APPEND = 0x10;
+        /// Truncate the file to 0 bytes once opened.
+        const TRUNC  = 0x20;
+    }
+}
+
+impl OpenFlags {
+    /// Check that the flags make sense together.
+    pub fn validate(self) -> Result<(), FileError> {
+        let no_access = !self.intersects(Self::READ | Self::WRITE);
+        let write_only_flags = self.intersects(Self::APPEND | Self::TRUNC) && !self.contains(Self::WRITE);
+        let lonely_excl = self.contains(Self::EXCL) && !self.contains(Self::CREATE);
+        if no_access || write_only_flags || lonely_excl {
+            Err(FileError::InvalidFlagsError)
+        } else {
+            Ok(())
+        }
+    }
+}
+
 bitflags! {
     /// A FAT file attributes.
     #[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
@@ -213,6 +302,8 @@ bitflags! {
 ///         created: DateTime::now(),
 ///         accessed: Date::today(),
 ///         modified: DateTime::now(),
+///         inode: 0,
+///         mode: 0,
 ///     };
 ///
 ///     println!("Short file name: {}", metadata.short_file_name());
@@ -247,6 +338,10 @@ pub struct Metadata {
     accessed: Date,
     /// Last modification date and time
     modified: DateTime,
+    /// Inode number, 0 if the filesystem has no inodes
+    inode: u64,
+    /// Unix file type and permission bits, 0 if the filesystem has none
+    mode: u16,
 }
 
 impl Metadata {
@@ -264,9 +359,50 @@ impl Metadata {
             created: DateTime::from_fatfs(&entry.created()),
             accessed: Date::from_fatfs(&entry.accessed()),
             modified: DateTime::from_fatfs(&entry.modified()),
+            inode: 0,
+            mode: 0,
+        }
+    }
+
+    /// Initialize the object for entries that are not backed by FAT, such as devices.
+    ///
+    /// All the timestamps are set to `time`, and the short file name equals the file name.
+    pub fn new(path: &str, is_dir: bool, len: u64, time: DateTime) -> Self {
+        let name = String::from(filename(path));
+        let attributes = if is_dir { FileAttributes::DIRECTORY } else { FileAttributes::empty() };
+        Self {
+            path: String::from(path),
+            short_file_name: name.clone(),
+            file_name: name,
+            attributes,
+            is_dir,
+            is_file: !is_dir,
+            len,
+            created: time,
+            accessed: time.date,
+            modified: time,
+            inode: 0,
+            mode: 0,
         }
     }
 
+    /// Attach the inode number and Unix mode, for filesystems that have them such as ext2.
+    pub fn with_unix(mut self, inode: u64, mode: u16) -> Self {
+        self.inode = inode;
+        self.mode = mode;
+        self
+    }
+
+    /// Returns the absolute path.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Replace the path, used when an entry is seen through a mount point.
+    pub fn set_path(&mut self, path: &str) {
+        self.path = String::from(path);
+    }
+
     /// Returns the short file name.
     pub fn short_file_name(&self) -> &str {
         &self.short_file_name
@@ -311,6 +447,16 @@ impl Metadata {
     pub fn modified(&self) -> DateTime {
         self.modified
     }
+
+    /// Returns the inode number, or 0 if the filesystem has no inodes.
+    pub fn inode(&self) -> u64 {
+        self.inode
+    }
+
+    /// Returns the Unix file type and permission bits, or 0 if the filesystem has none.
+    pub fn mode(&self) -> u16 {
+        self.mode
+    }
 }
 
 pub fn process_relative_path(splited_path: &mut Vec<&str>) -> Result<(), FileError> {
@@ -338,6 +484,38 @@ pub fn path_standardize(path: &str) -> Result<String, FileError> {
     Ok(spilted_path.join("/"))
 }
 
+/// Resolve `path` against `current_dir` with [`realpath`] and standardize it.
+///
+/// Unlike [`path_standardize`], the root directory is returned as `/` instead of an empty string.
+pub fn absolute_path(path: &str, current_dir: &str) -> Result<String, FileError> {
+    let path = path_standardize(realpath(path, current_dir).as_str())?;
+    if path.is_empty() {
+        Ok(String::from("/"))
+    } else {
+        Ok(path)
+    }
+}
+
+/// Resolve `path` against the working directory of the current process, the same way the kernel does.
+pub fn absolute(path: &str) -> Result<String, FileError> {
+    absolute_path(path, getcwd().as_str())
+}
+
+/// Returns the working directory of the current process.
+pub fn getcwd() -> String {
+    let ret: Result<String, _> = syscall_with_deserialize!(GETCWD);
+    ret.unwrap_or_else(|_| String::from("/"))
+}
+
+/// Change the working directory of the current process, children spawned later inherit it.
+pub fn chdir(path: &str) -> Result<(), FileError> {
+    let ret: Result<Result<(), FileError>, _> = syscall_with_serdeser!(CHDIR, String::from(path));
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FileDevice(usize);
 
@@ -370,6 +548,21 @@ impl FileEntry {
         Self::Device(FileDevice(device))
     }
 
+    /// Returns the metadata of files and directories.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        match self {
+            FileEntry::Dir(meta) | FileEntry::File(meta) => Some(meta),
+            FileEntry::Device(_) => None,
+        }
+    }
+
+    pub fn metadata_mut(&mut self) -> Option<&mut Metadata> {
+        match self {
+            FileEntry::Dir(meta) | FileEntry::File(meta) => Some(meta),
+            FileEntry::Device(_) => None,
+        }
+    }
+
     pub fn list(&mut self) -> Result<Vec<Self>, FileError> {
         match self {
             FileEntry::Dir(dir) => {
@@ -385,6 +578,79 @@ impl FileEntry {
     }
 }
 
+/// Number of entries [`ReadDir`] fetches from the kernel at a time.
+const READ_DIR_BATCH: usize = 16;
+
+/// Open a directory for reading its entries in batches, see [`read_dir`] for an iterator.
+pub fn opendir(path: &str) -> Result<usize, FileError> {
+    let ret: Result<Result<usize, FileError>, _> = syscall_with_serdeser!(OPENDIR, String::from(path));
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Read at most `count` next entries of an opened directory, an empty result means the end.
+pub fn readdir(handle: usize, count: usize) -> Result<Vec<FileEntry>, FileError> {
+    let ret: Result<Result<Vec<FileEntry>, FileError>, _> = syscall_with_serdeser!(READDIR, (handle, count));
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+pub fn closedir(handle: usize) -> Result<(), FileError> {
+    let ret: Result<Result<(), FileError>, _> = syscall_with_serdeser!(CLOSEDIR, handle);
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Iterator over the entries of a directory, fetching a few entries at a time.
+///
+/// The directory is closed when the iterator is dropped.
+pub struct ReadDir {
+    handle: usize,
+    buffer: VecDeque<FileEntry>,
+    done: bool,
+}
+
+impl Iterator for ReadDir {
+    type Item = Result<FileEntry, FileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() && !self.done {
+            match readdir(self.handle, READ_DIR_BATCH) {
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+                Ok(entries) => {
+                    self.done = entries.len() < READ_DIR_BATCH;
+                    self.buffer.extend(entries);
+                }
+            }
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+}
+
+impl Drop for ReadDir {
+    fn drop(&mut self) {
+        let _ = closedir(self.handle);
+    }
+}
+
+/// Returns an iterator over the entries of a directory, with their full [`Metadata`].
+pub fn read_dir(path: &str) -> Result<ReadDir, FileError> {
+    Ok(ReadDir {
+        handle: opendir(path)?,
+        buffer: VecDeque::new(),
+        done: false,
+    })
+}
+
 pub fn list(path: &str) -> Result<Vec<FileEntry>, FileError> {
     // 调用系统调用查询
     let ret: Result<Result<Vec<FileEntry>, FileError>, _> = syscall_with_serdeser!(LIST, path);
@@ -394,8 +660,112 @@ pub fn list(path: &str) -> Result<Vec<FileEntry>, FileError> {
     }
 }
 
+/// Open an existing file for reading, and also for writing if `write` is true.
 pub fn open(path: &str, write: bool) -> Result<usize, FileError> {
-    let ret: Result<Result<usize, FileError>, _> = syscall_with_serdeser!(OPEN, (String::from(path), write));
+    let flags = if write { OpenFlags::READ | OpenFlags::WRITE } else { OpenFlags::READ };
+    open_with_flags(path, flags)
+}
+
+/// Open a file for writing in append mode: every write goes to the end of the file.
+pub fn open_append(path: &str) -> Result<usize, FileError> {
+    open_with_flags(path, OpenFlags::WRITE | OpenFlags::APPEND)
+}
+
+/// Open a file with the given [`OpenFlags`].
+pub fn open_with_flags(path: &str, flags: OpenFlags) -> Result<usize, FileError> {
+    let ret: Result<Result<usize, FileError>, _> = syscall_with_serdeser!(OPEN, (String::from(path), flags));
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Close a handle. Handles are also closed when the process exits.
+pub fn close(handle: usize) -> Result<(), FileError> {
+    let ret: Result<Result<(), FileError>, _> = syscall_with_serdeser!(CLOSE, handle);
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Apply or release an advisory lock on the file of a handle.
+///
+/// It never blocks: `FileBusyError` is returned if the lock is held by another handle.
+pub fn flock(handle: usize, mode: LockMode) -> Result<(), FileError> {
+    let ret: Result<Result<(), FileError>, _> = syscall_with_serdeser!(FLOCK, (handle, mode));
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Write all the data cached by the kernel back to the disks.
+pub fn sync() -> Result<(), FileError> {
+    let ret: Result<Result<(), FileError>, _> = syscall_with_deserialize!(SYNC);
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Write at the position of the handle and advance it, returns the number of bytes written.
+pub fn write(handle: usize, buf: &[u8]) -> Result<usize, FileError> {
+    let ret: Result<Result<usize, FileError>, _> = syscall_with_serdeser!(WRITE, (handle, Vec::from(buf)));
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Move the position of the handle, returns the new position from the start of the file.
+pub fn seek(handle: usize, pos: SeekFrom) -> Result<u64, FileError> {
+    let ret: Result<Result<u64, FileError>, _> = syscall_with_serdeser!(SEEK, (handle, pos));
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Truncate or zero-extend the file of the handle to `len` bytes.
+pub fn ftruncate(handle: usize, len: u64) -> Result<(), FileError> {
+    let ret: Result<Result<(), FileError>, _> = syscall_with_serdeser!(FTRUNCATE, (handle, len));
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Create an empty file, an existing file is left untouched.
+pub fn create(path: &str) -> Result<(), FileError> {
+    let ret: Result<Result<(), FileError>, _> = syscall_with_serdeser!(CREATE, String::from(path));
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Create a directory, the parent directory must exist.
+pub fn mkdir(path: &str) -> Result<(), FileError> {
+    let ret: Result<Result<(), FileError>, _> = syscall_with_serdeser!(MKDIR, String::from(path));
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Rename or move a file or directory, both paths must be on the same filesystem.
+pub fn rename(from: &str, to: &str) -> Result<(), FileError> {
+    let ret: Result<Result<(), FileError>, _> = syscall_with_serdeser!(RENAME, (String::from(from), String::from(to)));
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Truncate or zero-extend a file to `len` bytes.
+pub fn truncate(path: &str, len: u64) -> Result<(), FileError> {
+    let ret: Result<Result<(), FileError>, _> = syscall_with_serdeser!(TRUNCATE, (String::from(path), len));
     match ret {
         Err(_) => Err(FileError::OSError),
         Ok(ret) => ret
@@ -410,10 +780,18 @@ pub fn write_all(handle: usize, buf: &[u8]) -> Result<usize, FileError> {
     }
 }
 
+/// Read at the position of the handle and advance it, never more than `buf.len()` bytes.
 pub fn read(handle: usize, buf: &mut [u8]) -> Result<usize, FileError> {
+    read_with_remaining(handle, buf).map(|(len, _)| len)
+}
+
+/// Same as [`read`], also returns the number of bytes left after the new position.
+///
+/// Devices have no length, so the remaining is always 0 for them.
+pub fn read_with_remaining(handle: usize, buf: &mut [u8]) -> Result<(usize, u64), FileError> {
     let ptr = buf.as_ptr() as usize;
     let len = buf.len();
-    let ret: Result<Result<usize, FileError>, _> = syscall_with_serdeser!(READ, (handle, ptr, len));
+    let ret: Result<Result<(usize, u64), FileError>, _> = syscall_with_serdeser!(READ, (handle, ptr, len));
     match ret {
         Err(_) => Err(FileError::OSError),
         Ok(ret) => ret
@@ -446,17 +824,27 @@ pub fn info(path: &str) -> Result<Metadata, FileError> {
     }
 }
 
+/// Read a whole file, the kernel sizes the buffer from its metadata.
 pub fn read_all_from_path(path: &str) -> Result<Vec<u8>, FileError> {
-    let metadata = info(path)?;
-    if !metadata.is_file() { return Err(NotAFileError); }
-    let handle = open(path, false)?;
-    let mut buf = vec![0u8; metadata.len() as usize];
-    read(handle, buf.as_mut_slice())?;
-    return Ok(buf);
+    let ret: Result<Result<Vec<u8>, FileError>, _> = syscall_with_serdeser!(READ_ALL, String::from(path));
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
 }
 
+/// Spawn a program from a file, the child inherits the environment of the current process.
 pub fn spawn_from_path(path: &str, args: Vec<String>) -> bool {
-    let ret:Result<bool,_> = syscall_with_serdeser!(SPAWN_FROM_PATH,(String::from(path),args));
+    spawn_from_path_env(path, args, None)
+}
+
+/// Spawn a program from a file, with `env` instead of the environment of the current process.
+pub fn spawn_from_path_with_env(path: &str, args: Vec<String>, env: BTreeMap<String, String>) -> bool {
+    spawn_from_path_env(path, args, Some(env))
+}
+
+fn spawn_from_path_env(path: &str, args: Vec<String>, env: Option<BTreeMap<String, String>>) -> bool {
+    let ret:Result<bool,_> = syscall_with_serdeser!(SPAWN_FROM_PATH,(String::from(path),args,env));
     match ret {
         Ok(true) => true,
         _ => false
@@ -43,7 +43,9 @@ pub mod call;
 pub mod event;
 
 pub mod allocator;
+pub mod env;
 pub mod fs;
+pub mod net;
 pub mod syscall;
 pub mod time;
 pub mod stdin;
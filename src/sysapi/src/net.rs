@@ -0,0 +1,322 @@
+//! Sockets over the kernel's TCP/IP stack.
+//!
+//! The system calls never block: [`send`] and [`recv`] return [`NetError::WouldBlockError`] when the socket is not ready.
+//! [`TcpStream`], [`TcpListener`] and [`UdpSocket`] wait by sleeping between attempts, so other processes keep
+//! running meanwhile.
+//!
+//! Under QEMU's user-mode networking the guest is `10.0.2.15` and `10.0.2.2` reaches the host's loopback.
+
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+use ufmt::uDebug;
+
+use crate::call::*;
+use crate::syscall::sleep;
+
+/// Seconds to sleep between two attempts of a blocking operation.
+const POLL_INTERVAL: f64 = 0.01;
+/// Seconds [`TcpStream::connect`] waits for the handshake.
+const CONNECT_TIMEOUT: f64 = 10.0;
+
+/// An IPv4 address and a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SocketAddr {
+    pub ip: [u8; 4],
+    pub port: u16,
+}
+
+impl SocketAddr {
+    pub const fn new(ip: [u8; 4], port: u16) -> Self {
+        Self { ip, port }
+    }
+
+    /// Parse `a.b.c.d:port`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (ip, port) = s.split_once(':')?;
+        Some(Self::new(parse_ipv4(ip)?, port.parse().ok()?))
+    }
+}
+
+/// Parse a dotted IPv4 address such as `10.0.2.2`.
+pub fn parse_ipv4(s: &str) -> Option<[u8; 4]> {
+    let mut ip = [0u8; 4];
+    let mut parts = s.split('.');
+    for byte in ip.iter_mut() {
+        *byte = parts.next()?.parse().ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(ip)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SocketType {
+    Tcp,
+    Udp,
+}
+
+/// Connection state of a TCP socket, UDP sockets are always `Closed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    /// The handshake is in progress.
+    Connecting,
+    Established,
+    /// The peer or this side has started closing the connection.
+    Closing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SocketStatus {
+    pub state: TcpState,
+    /// There is room in the send buffer.
+    pub can_send: bool,
+    /// There is data waiting in the receive buffer.
+    pub can_recv: bool,
+    /// More data may still arrive, false after the peer closed its side.
+    pub may_recv: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NetError {
+    /// Returned when there is no network card.
+    NoDeviceError,
+    /// Returned when the handle is not a socket of the current process.
+    InvalidHandleError,
+    /// Returned when the operation does not fit the socket, e.g. `connect` on a UDP socket or a socket already in use.
+    InvalidStateError,
+    /// Returned when the address or port is invalid.
+    UnaddressableError,
+    /// Returned when the operation would have to wait.
+    WouldBlockError,
+    /// Returned when the connection was refused, reset or closed.
+    ConnectionClosedError,
+    /// Returned when a blocking operation gave up waiting.
+    TimedOutError,
+    /// Returned for miscellaneous OS errors.
+    OSError,
+}
+
+impl uDebug for NetError {
+    fn fmt<W>(&self, w: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+        where W: ufmt::uWrite + ?Sized {
+        match self {
+            NetError::NoDeviceError => w.write_str("NoDeviceError"),
+            NetError::InvalidHandleError => w.write_str("InvalidHandleError"),
+            NetError::InvalidStateError => w.write_str("InvalidStateError"),
+            NetError::UnaddressableError => w.write_str("UnaddressableError"),
+            NetError::WouldBlockError => w.write_str("WouldBlockError"),
+            NetError::ConnectionClosedError => w.write_str("ConnectionClosedError"),
+            NetError::TimedOutError => w.write_str("TimedOutError"),
+            NetError::OSError => w.write_str("OSError"),
+        }
+    }
+}
+
+/// Create a socket, returns its handle.
+pub fn socket(kind: SocketType) -> Result<usize, NetError> {
+    let ret: Result<Result<usize, NetError>, _> = syscall_with_serdeser!(SOCKET, kind);
+    match ret {
+        Err(_) => Err(NetError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Bind a UDP socket to a local port.
+pub fn bind(handle: usize, port: u16) -> Result<(), NetError> {
+    let ret: Result<Result<(), NetError>, _> = syscall_with_serdeser!(BIND, (handle, port));
+    match ret {
+        Err(_) => Err(NetError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Make a TCP socket wait for a connection on a local port. The socket itself becomes the connection.
+pub fn listen(handle: usize, port: u16) -> Result<(), NetError> {
+    let ret: Result<Result<(), NetError>, _> = syscall_with_serdeser!(LISTEN, (handle, port));
+    match ret {
+        Err(_) => Err(NetError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Start connecting a TCP socket, use [`status`] to see when it is established.
+pub fn connect(handle: usize, addr: SocketAddr) -> Result<(), NetError> {
+    let ret: Result<Result<(), NetError>, _> = syscall_with_serdeser!(CONNECT, (handle, addr));
+    match ret {
+        Err(_) => Err(NetError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Queue data for sending, returns how much was taken. UDP sockets need the destination `to`.
+pub fn send(handle: usize, buf: &[u8], to: Option<SocketAddr>) -> Result<usize, NetError> {
+    let ret: Result<Result<usize, NetError>, _> = syscall_with_serdeser!(SEND, (handle, Vec::from(buf), to));
+    match ret {
+        Err(_) => Err(NetError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Take at most `buf.len()` received bytes, returns the length and, for UDP, the sender.
+///
+/// A TCP socket whose peer has closed the connection returns `Ok((0, None))`.
+pub fn recv(handle: usize, buf: &mut [u8]) -> Result<(usize, Option<SocketAddr>), NetError> {
+    let ret: Result<Result<(Vec<u8>, Option<SocketAddr>), NetError>, _> = syscall_with_serdeser!(RECV, (handle, buf.len()));
+    match ret {
+        Err(_) => Err(NetError::OSError),
+        Ok(Err(e)) => Err(e),
+        Ok(Ok((data, from))) => {
+            buf[..data.len()].copy_from_slice(&data);
+            Ok((data.len(), from))
+        }
+    }
+}
+
+pub fn status(handle: usize) -> Result<SocketStatus, NetError> {
+    let ret: Result<Result<SocketStatus, NetError>, _> = syscall_with_serdeser!(SOCKET_STATUS, handle);
+    match ret {
+        Err(_) => Err(NetError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Close the socket. A TCP connection is closed gracefully in the background.
+pub fn close(handle: usize) -> Result<(), NetError> {
+    let ret: Result<Result<(), NetError>, _> = syscall_with_serdeser!(CLOSE_SOCKET, handle);
+    match ret {
+        Err(_) => Err(NetError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Retry `f` until it stops returning [`NetError::WouldBlockError`].
+fn block_on<T>(mut f: impl FnMut() -> Result<T, NetError>) -> Result<T, NetError> {
+    loop {
+        match f() {
+            Err(NetError::WouldBlockError) => sleep(POLL_INTERVAL),
+            result => return result,
+        }
+    }
+}
+
+/// A TCP connection. It is closed when dropped.
+pub struct TcpStream {
+    handle: usize,
+}
+
+impl TcpStream {
+    /// Connect to `addr`, waiting for the handshake to finish.
+    pub fn connect(addr: SocketAddr) -> Result<Self, NetError> {
+        let stream = Self { handle: socket(SocketType::Tcp)? };
+        connect(stream.handle, addr)?;
+        let mut waited = 0.0;
+        loop {
+            match status(stream.handle)?.state {
+                TcpState::Established => return Ok(stream),
+                TcpState::Connecting => {}
+                _ => return Err(NetError::ConnectionClosedError),
+            }
+            if waited >= CONNECT_TIMEOUT {
+                return Err(NetError::TimedOutError);
+            }
+            sleep(POLL_INTERVAL);
+            waited += POLL_INTERVAL;
+        }
+    }
+
+    /// Read some bytes, waiting until at least one arrives. Returns 0 once the peer has closed the connection.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, NetError> {
+        block_on(|| recv(self.handle, buf).map(|(len, _)| len))
+    }
+
+    /// Write some bytes, waiting until there is room in the send buffer.
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize, NetError> {
+        block_on(|| send(self.handle, buf, None))
+    }
+
+    pub fn write_all(&mut self, mut buf: &[u8]) -> Result<(), NetError> {
+        while !buf.is_empty() {
+            let len = self.write(buf)?;
+            buf = &buf[len..];
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        let _ = close(self.handle);
+    }
+}
+
+/// Accepts TCP connections on a local port.
+pub struct TcpListener {
+    handle: usize,
+    port: u16,
+}
+
+impl TcpListener {
+    pub fn bind(port: u16) -> Result<Self, NetError> {
+        let listener = Self { handle: socket(SocketType::Tcp)?, port };
+        listen(listener.handle, port)?;
+        Ok(listener)
+    }
+
+    /// Wait for a connection.
+    ///
+    /// The listening socket turns into the connection, so a fresh one takes its place for the next call.
+    pub fn accept(&mut self) -> Result<TcpStream, NetError> {
+        loop {
+            match status(self.handle)?.state {
+                TcpState::Listen | TcpState::Connecting => sleep(POLL_INTERVAL),
+                _ => break,
+            }
+        }
+        let next = socket(SocketType::Tcp)?;
+        listen(next, self.port)?;
+        let stream = TcpStream { handle: self.handle };
+        self.handle = next;
+        Ok(stream)
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let _ = close(self.handle);
+    }
+}
+
+/// A UDP socket bound to a local port. It is closed when dropped.
+pub struct UdpSocket {
+    handle: usize,
+}
+
+impl UdpSocket {
+    pub fn bind(port: u16) -> Result<Self, NetError> {
+        let socket = Self { handle: socket(SocketType::Udp)? };
+        bind(socket.handle, port)?;
+        Ok(socket)
+    }
+
+    pub fn send_to(&self, buf: &[u8], to: SocketAddr) -> Result<usize, NetError> {
+        block_on(|| send(self.handle, buf, Some(to)))
+    }
+
+    /// Wait for a datagram, returns its length and sender. A datagram longer than `buf` is truncated.
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr), NetError> {
+        block_on(|| match recv(self.handle, buf)? {
+            (len, Some(from)) => Ok((len, from)),
+            (_, None) => Err(NetError::OSError),
+        })
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let _ = close(self.handle);
+    }
+}
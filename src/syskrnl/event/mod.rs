@@ -5,7 +5,7 @@ use lazy_static::lazy_static;
 use spin::Mutex;
 
 pub use call::dispatcher;
-pub use service::GUI_EID_START;
+pub use service::{GUI_EID_START, KERNEL_EID_START};
 
 use crate::syskrnl;
 use crate::syskrnl::proc::SCHEDULER;
@@ -10,10 +10,12 @@ use crate::syskrnl::proc;
 // 0..1_000_000 - 裸EID
 // 1_000_000..2_000_000 - Sleep
 // 2_000_000..3_000_000 - GUI
+// 3_000_000..4_000_000 - 内核（等待锁、磁盘命令）
 //
 
 const SLEEP_EID_START: usize = 1_000_000;
 pub const GUI_EID_START: usize = 2_000_000;
+pub const KERNEL_EID_START: usize = 3_000_000;
 
 pub fn keyboard_input() -> usize {
     EVENT_QUEUE.lock().wait_for(KEYBOARD_INPUT)
@@ -0,0 +1,213 @@
+//! 块设备读写器：在已注册到块缓存的设备上提供`fatfs::ReadWriteSeek`接口
+//!
+//! 读写都经过[`super::cache`]，按字节的读写在这里拆成整块；不满一块的写入先读出整块再修改。
+//! 写入只进入缓存，`flush`时才写回设备。
+//!
+//! [`BlockDeviceFile`]把整块磁盘注册为`/dev`下的设备文件，按字节偏移读写。
+
+use alloc::vec;
+
+use fatfs::{IoBase, Read, Seek, SeekFrom, Write};
+
+use cinea_os_sysapi::fs::FileIO;
+
+use super::cache::{self, DeviceId};
+use super::partition::SharedDisk;
+
+pub struct BlockDeviceReader {
+    device: DeviceId,
+    block_size: usize,
+    /// 设备总字节数
+    len: u64,
+    position: u64,
+}
+
+impl BlockDeviceReader {
+    /// `device`必须已经通过[`cache::register`]注册
+    pub fn new(device: DeviceId) -> Self {
+        let (block_size, block_count) = cache::geometry(device).expect("block device not registered");
+        Self {
+            device,
+            block_size,
+            len: block_count * block_size as u64,
+            position: 0,
+        }
+    }
+
+    /// 块缓存中的设备号
+    pub fn device(&self) -> DeviceId {
+        self.device
+    }
+
+    /// 设备总字节数
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// 当前位置在块内的偏移和本次能读写的字节数，第一块和最后一块可能不完整
+    fn span(&self, remaining: usize) -> (u64, usize, usize) {
+        let block = self.position / self.block_size as u64;
+        let offset = (self.position % self.block_size as u64) as usize;
+        let count = if offset == 0 && remaining >= self.block_size {
+            remaining / self.block_size * self.block_size
+        } else {
+            (self.block_size - offset).min(remaining)
+        };
+        (block, offset, count)
+    }
+}
+
+impl IoBase for BlockDeviceReader {
+    type Error = ();
+}
+
+impl Read for BlockDeviceReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let len = (buf.len() as u64).min(self.len - self.position) as usize;
+        let mut done = 0usize;
+        while done < len {
+            let (block, offset, count) = self.span(len - done);
+            if offset == 0 && count % self.block_size == 0 {
+                cache::read(self.device, block, &mut buf[done..done + count])?;
+            } else {
+                let mut data = vec![0u8; self.block_size];
+                cache::read(self.device, block, &mut data)?;
+                buf[done..done + count].copy_from_slice(&data[offset..offset + count]);
+            }
+            self.position += count as u64;
+            done += count;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for BlockDeviceReader {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let len = (buf.len() as u64).min(self.len - self.position) as usize;
+        let mut done = 0usize;
+        while done < len {
+            let (block, offset, count) = self.span(len - done);
+            if offset == 0 && count % self.block_size == 0 {
+                cache::write(self.device, block, &buf[done..done + count])?;
+            } else {
+                let mut data = vec![0u8; self.block_size];
+                cache::read(self.device, block, &mut data)?;
+                data[offset..offset + count].copy_from_slice(&buf[done..done + count]);
+                cache::write(self.device, block, &data)?;
+            }
+            self.position += count as u64;
+            done += count;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        cache::flush(self.device)
+    }
+}
+
+impl Seek for BlockDeviceReader {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let position = match pos {
+            SeekFrom::Start(pos) => pos as i64,
+            SeekFrom::End(pos) => self.len as i64 + pos,
+            SeekFrom::Current(pos) => self.position as i64 + pos,
+        };
+        if position < 0 || position as u64 > self.len {
+            return Err(());
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+/// 块设备文件，如`/dev/sda`
+///
+/// 与挂载在这块磁盘上的文件系统读写同一个块缓存，因此数据是一致的。
+pub struct BlockDeviceFile {
+    disk: SharedDisk<BlockDeviceReader>,
+}
+
+impl BlockDeviceFile {
+    pub fn new(disk: SharedDisk<BlockDeviceReader>) -> Self {
+        Self { disk }
+    }
+}
+
+impl FileIO for BlockDeviceFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        self.read_at(0, buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        self.write_at(0, buf)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, ()> {
+        let mut disk = self.disk.lock();
+        if offset >= disk.len() {
+            return Ok(0);
+        }
+        disk.seek(SeekFrom::Start(offset))?;
+        disk.read(buf)
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> Result<usize, ()> {
+        let mut disk = self.disk.lock();
+        if offset >= disk.len() {
+            return Ok(0);
+        }
+        disk.seek(SeekFrom::Start(offset))?;
+        disk.write(buf)
+    }
+
+    fn size(&self) -> u64 {
+        self.disk.lock().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use fatfs::{Read, Seek, SeekFrom, Write};
+    use spin::Mutex;
+
+    use crate::syskrnl::fs::cache;
+    use crate::syskrnl::io::block::RamDisk;
+
+    use super::BlockDeviceReader;
+
+    #[test_case]
+    fn test_block_device_reader() {
+        let data = Arc::new(Mutex::new(vec![0u8; 160 * 512]));
+        let id = cache::register(Box::new(RamDisk::new(data.clone())));
+        let mut reader = BlockDeviceReader::new(id);
+
+        // 首尾都不对齐块边界的写入，先留在缓存里
+        let pattern: Vec<u8> = (0..1000).map(|x| x as u8).collect();
+        reader.seek(SeekFrom::Start(32 * 1024 - 500)).unwrap();
+        reader.write_all(&pattern).unwrap();
+        assert_eq!(data.lock()[32 * 1024 - 500], 0);
+
+        let mut buf = vec![0u8; 1000];
+        reader.seek(SeekFrom::Start(32 * 1024 - 500)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, pattern);
+
+        // flush之后写入设备，块内其余字节不变
+        reader.flush().unwrap();
+        assert_eq!(&data.lock()[32 * 1024 - 500..32 * 1024 + 500], pattern.as_slice());
+        assert_eq!(data.lock()[32 * 1024 - 501], 0);
+
+        // 读写不会越过设备末尾
+        reader.seek(SeekFrom::End(-4)).unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 4);
+        assert_eq!(reader.write(&pattern).unwrap(), 0);
+        reader.seek(SeekFrom::End(1)).unwrap_err();
+        println!("[ok]  FileSystem block device reader")
+    }
+}
@@ -0,0 +1,376 @@
+//! 块缓存：每个块设备一个LRU写回缓存
+//!
+//! 缓存以块号为键，每项是一整块。写入只修改缓存并标记为脏，
+//! 脏块在被淘汰、调用[`sync`]或定期刷新时写回设备。定期刷新由时钟中断标记，
+//! 由0号进程执行器中的[`flush_handler`]任务写回：时钟中断里不能等待磁盘中断。
+//!
+//! 每个设备的缓存有自己的锁，一个进程等待某个磁盘时，其他进程仍然可以读写别的设备。
+//!
+//! 命中和未命中次数可以从`/proc/blockcache`读到。
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::Poll;
+
+use futures_util::future::poll_fn;
+use futures_util::task::AtomicWaker;
+use lazy_static::lazy_static;
+use x86_64::instructions::interrupts;
+
+use crate::debugln;
+use crate::syskrnl::io::block::{self, BlockDevice};
+use crate::syskrnl::sync::Mutex;
+use crate::syskrnl::time;
+
+/// 每个设备缓存的块数，512字节的块共2MB
+const CACHE_BLOCKS: usize = 4096;
+/// 未命中时连同后面的块一起读入，最多读这么多块
+const READAHEAD_BLOCKS: usize = 64;
+/// 定期刷新的间隔（时钟节拍）
+const FLUSH_INTERVAL_TICKS: usize = 500;
+
+pub type DeviceId = usize;
+
+/// 缓存统计
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// 因淘汰或刷新写回设备的块数
+    pub writebacks: u64,
+    pub cached: usize,
+    pub dirty: usize,
+}
+
+struct CacheEntry {
+    data: Vec<u8>,
+    dirty: bool,
+    /// 最近一次访问的时间戳，同时是`lru`中的键
+    stamp: u64,
+}
+
+pub struct BlockCache {
+    capacity: usize,
+    device: Box<dyn BlockDevice>,
+    entries: BTreeMap<u64, CacheEntry>,
+    /// 时间戳到块号的映射，第一项是最久未使用的块
+    lru: BTreeMap<u64, u64>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+impl BlockCache {
+    pub fn new(device: Box<dyn BlockDevice>, capacity: usize) -> Self {
+        Self {
+            capacity,
+            device,
+            entries: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// 更新块的时间戳
+    fn touch(&mut self, key: u64) {
+        self.clock += 1;
+        let stamp = self.clock;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            self.lru.remove(&entry.stamp);
+            entry.stamp = stamp;
+            self.lru.insert(stamp, key);
+        }
+    }
+
+    /// 把块放入缓存，缓存满时先淘汰最久未使用的块
+    fn insert(&mut self, key: u64, data: Vec<u8>, dirty: bool) -> Result<(), ()> {
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.data = data;
+            entry.dirty |= dirty;
+            self.touch(key);
+            return Ok(());
+        }
+        while self.entries.len() >= self.capacity {
+            self.evict()?;
+        }
+        self.clock += 1;
+        self.lru.insert(self.clock, key);
+        self.entries.insert(
+            key,
+            CacheEntry {
+                data,
+                dirty,
+                stamp: self.clock,
+            },
+        );
+        Ok(())
+    }
+
+    fn evict(&mut self) -> Result<(), ()> {
+        let (&stamp, &key) = self.lru.iter().next().ok_or(())?;
+        self.write_back(key)?;
+        self.lru.remove(&stamp);
+        self.entries.remove(&key);
+        Ok(())
+    }
+
+    /// 把脏块写回设备
+    fn write_back(&mut self, key: u64) -> Result<(), ()> {
+        let entry = match self.entries.get_mut(&key) {
+            Some(entry) if entry.dirty => entry,
+            _ => return Ok(()),
+        };
+        self.device.write_blocks(key, &entry.data)?;
+        entry.dirty = false;
+        self.stats.writebacks += 1;
+        Ok(())
+    }
+
+    /// 从`start`块开始读满`buf`
+    pub fn read(&mut self, start: u64, buf: &mut [u8]) -> Result<(), ()> {
+        let block_size = self.device.block_size();
+        block::check_range(self.device.as_ref(), start, buf.len())?;
+        let count = buf.len() / block_size;
+        let mut i = 0;
+        while i < count {
+            let key = start + i as u64;
+            if let Some(entry) = self.entries.get(&key) {
+                buf[i * block_size..(i + 1) * block_size].copy_from_slice(&entry.data);
+                self.stats.hits += 1;
+                self.touch(key);
+                i += 1;
+                continue;
+            }
+
+            // 未命中：一次读入连续的未缓存块，顺带预读后面的块
+            self.stats.misses += 1;
+            let first = start + i as u64;
+            let mut run = 1;
+            while run < READAHEAD_BLOCKS.max(count - i)
+                && first + (run as u64) < self.device.block_count()
+                && !self.entries.contains_key(&(first + run as u64))
+            {
+                run += 1;
+            }
+            let mut data = vec![0u8; run * block_size];
+            self.device.read_blocks(first, &mut data)?;
+
+            let wanted = run.min(count - i);
+            buf[i * block_size..(i + wanted) * block_size].copy_from_slice(&data[..wanted * block_size]);
+            // 数据已经复制到`buf`：腾不出位置（淘汰的脏块写回失败）时不放入缓存即可，脏块留在缓存中
+            for (j, chunk) in data.chunks(block_size).enumerate() {
+                let _ = self.insert(first + j as u64, Vec::from(chunk), false);
+            }
+            i += wanted;
+        }
+        Ok(())
+    }
+
+    /// 把`buf`写入从`start`块开始的缓存，不立即写回设备
+    pub fn write(&mut self, start: u64, buf: &[u8]) -> Result<(), ()> {
+        let block_size = self.device.block_size();
+        block::check_range(self.device.as_ref(), start, buf.len())?;
+        for (i, chunk) in buf.chunks(block_size).enumerate() {
+            self.insert(start + i as u64, Vec::from(chunk), true)?;
+        }
+        Ok(())
+    }
+
+    /// 写回所有脏块
+    pub fn flush(&mut self) -> Result<(), ()> {
+        let dirty: Vec<u64> = self.entries.iter().filter(|(_, entry)| entry.dirty).map(|(key, _)| *key).collect();
+        let mut result = Ok(());
+        // 键按块号排序，相邻的块依次写回
+        for key in dirty {
+            if self.write_back(key).is_err() {
+                result = Err(());
+            }
+        }
+        if self.device.flush().is_err() {
+            result = Err(());
+        }
+        result
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            cached: self.entries.len(),
+            dirty: self.entries.values().filter(|x| x.dirty).count(),
+            ..self.stats
+        }
+    }
+}
+
+/// 注册过的设备，块大小和块数不用等缓存的锁就能查到
+struct Registered {
+    block_size: usize,
+    block_count: u64,
+    cache: Arc<Mutex<BlockCache>>,
+}
+
+lazy_static! {
+    /// 设备号是在表中的下标
+    ///
+    /// 表只在查找时短暂持有，不跨越磁盘读写；0号进程也会查找，持有时关闭中断。
+    static ref DEVICES: spin::Mutex<Vec<Registered>> = spin::Mutex::new(Vec::new());
+}
+
+static LAST_FLUSH: AtomicUsize = AtomicUsize::new(0);
+/// 时钟中断标记需要定期刷新
+static FLUSH_DUE: AtomicBool = AtomicBool::new(false);
+static FLUSH_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// 为设备建立块缓存，之后通过返回的设备号读写
+pub fn register(device: Box<dyn BlockDevice>) -> DeviceId {
+    let registered = Registered {
+        block_size: device.block_size(),
+        block_count: device.block_count(),
+        cache: Arc::new(Mutex::new(BlockCache::new(device, CACHE_BLOCKS))),
+    };
+    interrupts::without_interrupts(|| {
+        let mut devices = DEVICES.lock();
+        devices.push(registered);
+        devices.len() - 1
+    })
+}
+
+/// 设备的块大小和块数
+pub fn geometry(id: DeviceId) -> Option<(usize, u64)> {
+    interrupts::without_interrupts(|| DEVICES.lock().get(id).map(|x| (x.block_size, x.block_count)))
+}
+
+fn cache(id: DeviceId) -> Result<Arc<Mutex<BlockCache>>, ()> {
+    interrupts::without_interrupts(|| DEVICES.lock().get(id).map(|x| x.cache.clone()).ok_or(()))
+}
+
+fn caches() -> Vec<Arc<Mutex<BlockCache>>> {
+    interrupts::without_interrupts(|| DEVICES.lock().iter().map(|x| x.cache.clone()).collect())
+}
+
+pub fn read(id: DeviceId, start: u64, buf: &mut [u8]) -> Result<(), ()> {
+    cache(id)?.lock().read(start, buf)
+}
+
+pub fn write(id: DeviceId, start: u64, buf: &[u8]) -> Result<(), ()> {
+    cache(id)?.lock().write(start, buf)
+}
+
+/// 写回某个设备的脏块
+pub fn flush(id: DeviceId) -> Result<(), ()> {
+    cache(id)?.lock().flush()
+}
+
+/// 写回所有脏块
+pub fn sync() -> Result<(), ()> {
+    LAST_FLUSH.store(time::ticks(), Ordering::SeqCst);
+    let mut result = Ok(());
+    for cache in caches() {
+        if cache.lock().flush().is_err() {
+            result = Err(());
+        }
+    }
+    result
+}
+
+/// 距上次刷新超过间隔时标记需要刷新，在时钟中断中调用
+pub fn tick() {
+    if time::ticks() - LAST_FLUSH.load(Ordering::SeqCst) >= FLUSH_INTERVAL_TICKS && !FLUSH_DUE.swap(true, Ordering::SeqCst) {
+        FLUSH_WAKER.wake();
+    }
+}
+
+/// 定期写回所有脏块的内核任务
+///
+/// 0号进程不能在锁上等待：有设备正被系统调用使用时不更新刷新时间，下一个时钟节拍重试。
+pub async fn flush_handler() {
+    loop {
+        poll_fn(|cx| {
+            FLUSH_WAKER.register(cx.waker());
+            if FLUSH_DUE.swap(false, Ordering::SeqCst) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        let now = time::ticks();
+        let mut busy = false;
+        for cache in caches() {
+            match cache.try_lock() {
+                Some(mut cache) => {
+                    if cache.flush().is_err() {
+                        debugln!("Periodic block cache flush failed");
+                    }
+                }
+                None => busy = true,
+            }
+        }
+        if !busy {
+            LAST_FLUSH.store(now, Ordering::SeqCst);
+        }
+    }
+}
+
+/// 所有设备的缓存统计之和
+pub fn stats() -> CacheStats {
+    caches().iter().fold(CacheStats::default(), |total, cache| {
+        let stats = cache.lock().stats();
+        CacheStats {
+            hits: total.hits + stats.hits,
+            misses: total.misses + stats.misses,
+            writebacks: total.writebacks + stats.writebacks,
+            cached: total.cached + stats.cached,
+            dirty: total.dirty + stats.dirty,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::sync::Arc;
+    use alloc::vec;
+
+    use spin::Mutex;
+
+    use crate::syskrnl::io::block::RamDisk;
+
+    use super::BlockCache;
+
+    #[test_case]
+    fn test_block_cache() {
+        let data = Arc::new(Mutex::new(vec![0u8; 256 * 512]));
+        let mut cache = BlockCache::new(Box::new(RamDisk::new(data.clone())), 80);
+
+        // 写入只进缓存
+        cache.write(3, &[7u8; 1024]).unwrap();
+        assert_eq!(data.lock()[3 * 512], 0);
+        let mut buf = vec![0u8; 1024];
+        cache.read(3, &mut buf).unwrap();
+        assert!(buf.iter().all(|x| *x == 7));
+        assert_eq!(cache.stats().dirty, 2);
+
+        // 未命中时预读，之后的读取命中
+        let misses = cache.stats().misses;
+        cache.read(100, &mut buf[..512]).unwrap();
+        cache.read(120, &mut buf[..512]).unwrap();
+        assert_eq!(cache.stats().misses, misses + 1);
+
+        // 读满缓存后，最久未使用的脏块被淘汰并写回
+        cache.read(200, &mut buf[..512]).unwrap();
+        assert!(cache.stats().cached <= 80);
+        assert_eq!(data.lock()[3 * 512], 7);
+        assert_eq!(cache.stats().writebacks, 2);
+
+        // 刷新写回剩下的脏块
+        cache.write(10, &[9u8; 512]).unwrap();
+        cache.flush().unwrap();
+        assert_eq!(data.lock()[10 * 512], 9);
+        assert_eq!(cache.stats().dirty, 0);
+        println!("[ok]  FileSystem block cache")
+    }
+}
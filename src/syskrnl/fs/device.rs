@@ -1,46 +1,151 @@
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::string::String;
+use alloc::vec::Vec;
 
 use lazy_static::lazy_static;
-use spin::Mutex;
 
-use cinea_os_sysapi::fs::{FileError, FileIO};
+use cinea_os_sysapi::fs::{path_combine, FileEntry, FileError, FileIO, Metadata};
 
-lazy_static! {
-    static ref DEVICE_TABLE: Mutex<BTreeMap<String, Box::<dyn FileIO>>> = {
-        let mut m: BTreeMap<String, Box<dyn FileIO>> = BTreeMap::new();
+use crate::syskrnl::sync::Mutex;
 
-        m.insert(String::from("/dev/stdout"), Box::new(crate::syskrnl::io::StdOutDevice));
-        m.insert(String::from("/dev/uptime"), Box::new(crate::syskrnl::time::UpTimeDevice));
+use super::time::now;
+use super::vfs::FileSystem;
 
-        Mutex::new(m)
-    };
+lazy_static! {
+    static ref DEVICE_TABLE: Mutex<BTreeMap<String, Box::<dyn FileIO>>> = Mutex::new(BTreeMap::new());
 }
 
-pub fn is_device(path: &str) -> bool {
-    DEVICE_TABLE.lock().contains_key(path)
+/// 注册设备，之后可以通过`/dev/<name>`访问
+///
+/// 驱动应在自己的`init`中调用，同名设备已存在时返回`FileBusyError`。
+pub fn register_device(name: &str, device: Box<dyn FileIO>) -> Result<(), FileError> {
+    let mut table = DEVICE_TABLE.lock();
+    if table.contains_key(name) {
+        return Err(FileError::FileBusyError);
+    }
+    table.insert(String::from(name), device);
+    Ok(())
 }
 
-pub fn read(path: &str, buf: &mut [u8]) -> Result<usize, FileError> {
-    let mut lock = DEVICE_TABLE.lock();
-    match lock.get_mut(path) {
+/// 注销设备
+pub fn unregister_device(name: &str) -> Result<(), FileError> {
+    match DEVICE_TABLE.lock().remove(name) {
+        Some(_) => Ok(()),
         None => Err(FileError::NotFoundError),
-        Some(device) => match device.read(buf) {
-            Ok(len) => Ok(len),
-            Err(()) => Err(FileError::DeviceIOError),
-        },
     }
 }
 
-pub fn write(path: &str, buf: &[u8]) -> Result<usize, FileError> {
-    let mut lock = DEVICE_TABLE.lock();
-    match lock.get_mut(path) {
-        None => Err(FileError::NotFoundError),
-        Some(device) => match device.write(buf) {
-            Ok(len) => Ok(len),
-            Err(()) => Err(FileError::DeviceIOError),
-        },
+/// 注册内核自带的设备
+pub fn init() {
+    register_device("stdout", Box::new(crate::syskrnl::io::StdOutDevice)).unwrap();
+    register_device("serial0", Box::new(crate::syskrnl::io::qemu::SerialDevice)).unwrap();
+    register_device("null", Box::new(NullDevice)).unwrap();
+    register_device("zero", Box::new(ZeroDevice)).unwrap();
+    register_device("random", Box::new(crate::syskrnl::random::RandomDevice)).unwrap();
+}
+
+/// 空设备，读取总是返回0字节，写入的数据全部丢弃
+pub struct NullDevice;
+
+impl FileIO for NullDevice {
+    fn read(&mut self, _buf: &mut [u8]) -> Result<usize, ()> {
+        Ok(0)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        Ok(buf.len())
+    }
+}
+
+/// 零设备，读取总是填满0，写入的数据全部丢弃
+pub struct ZeroDevice;
+
+impl FileIO for ZeroDevice {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        Ok(buf.len())
+    }
+}
+
+/// 设备文件系统，挂载在`/dev`，每个设备是根目录下的一个文件
+pub struct DeviceFileSystem;
+
+/// 挂载点内路径转设备名
+fn device_name(path: &str) -> &str {
+    path.trim_start_matches('/')
+}
+
+impl FileSystem for DeviceFileSystem {
+    fn metadata(&self, path: &str) -> Result<Metadata, FileError> {
+        if path == "/" {
+            Ok(Metadata::new(path, true, 0, now()))
+        } else {
+            match DEVICE_TABLE.lock().get(device_name(path)) {
+                Some(device) => Ok(Metadata::new(path, false, device.size(), now())),
+                None => Err(FileError::NotFoundError),
+            }
+        }
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<FileEntry>, FileError> {
+        if path != "/" {
+            return Err(FileError::NotADirError);
+        }
+        let time = now();
+        let result = DEVICE_TABLE
+            .lock()
+            .iter()
+            .map(|(name, device)| FileEntry::File(Metadata::new(path_combine(path, name.as_str()).as_str(), false, device.size(), time)))
+            .collect();
+        Ok(result)
+    }
+
+    fn read(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, FileError> {
+        let mut lock = DEVICE_TABLE.lock();
+        match lock.get_mut(device_name(path)) {
+            None => Err(FileError::NotFoundError),
+            Some(device) => match device.read_at(offset, buf) {
+                Ok(len) => Ok(len),
+                Err(()) => Err(FileError::DeviceIOError),
+            },
+        }
+    }
+
+    fn write(&self, path: &str, offset: u64, buf: &[u8]) -> Result<usize, FileError> {
+        let mut lock = DEVICE_TABLE.lock();
+        match lock.get_mut(device_name(path)) {
+            None => Err(FileError::NotFoundError),
+            Some(device) => match device.write_at(offset, buf) {
+                Ok(len) => Ok(len),
+                Err(()) => Err(FileError::DeviceIOError),
+            },
+        }
+    }
+
+    fn truncate(&self, path: &str, _len: u64) -> Result<(), FileError> {
+        // 设备没有长度，截断总是成功
+        self.metadata(path).map(|_| ())
+    }
+
+    fn create(&self, _path: &str) -> Result<(), FileError> {
+        Err(FileError::UnsupportedError)
+    }
+
+    fn create_dir(&self, _path: &str) -> Result<(), FileError> {
+        Err(FileError::UnsupportedError)
+    }
+
+    fn rename(&self, _from: &str, _to: &str) -> Result<(), FileError> {
+        Err(FileError::UnsupportedError)
+    }
+
+    fn concurrent_write(&self) -> bool {
+        true
     }
 }
 
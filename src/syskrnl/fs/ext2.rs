@@ -0,0 +1,1194 @@
+//! ext2文件系统
+//!
+//! 与FAT一样建立在`fatfs::ReadWriteSeek`的块设备接口之上。支持修订版0和1、任意块大小、
+//! 一至三级间接块和符号链接，可以创建、写入、截断和重命名文件与目录。
+//!
+//! 不兼容特性只支持`filetype`；遇到不认识的只读兼容特性时以只读方式挂载。
+//! 符号链接的绝对路径目标以挂载点为根解析。
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use byteorder::{ByteOrder, LittleEndian};
+use fatfs::SeekFrom;
+
+use cinea_os_sysapi::fs::{dirname, filename, path_combine, FileEntry, FileError, Metadata};
+use cinea_os_sysapi::time::{Date, DateTime, Time};
+use FileError::{AlreadyExistsError, BadRelatePathError, DeviceIOError, NotADirError, NotAFileError, NotFoundError, ReadOnlyError, RootDirError};
+
+use crate::syskrnl::sync::Mutex;
+
+use super::time::now;
+use super::vfs::FileSystem;
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const EXT2_MAGIC: u16 = 0xEF53;
+const ROOT_INODE: u32 = 2;
+const DIRECT_BLOCKS: u64 = 12;
+const GROUP_DESC_SIZE: usize = 32;
+const MAX_NAME_LEN: usize = 255;
+/// 解析一个路径时最多跟随的符号链接数，超过视为循环
+const MAX_SYMLINKS: usize = 8;
+/// 最大块大小64KB
+const MAX_LOG_BLOCK_SIZE: u32 = 6;
+
+// 超级块字段偏移
+const SB_INODES_COUNT: usize = 0;
+const SB_BLOCKS_COUNT: usize = 4;
+const SB_FREE_BLOCKS: usize = 12;
+const SB_FREE_INODES: usize = 16;
+const SB_FIRST_DATA_BLOCK: usize = 20;
+const SB_LOG_BLOCK_SIZE: usize = 24;
+const SB_BLOCKS_PER_GROUP: usize = 32;
+const SB_INODES_PER_GROUP: usize = 40;
+const SB_MAGIC: usize = 56;
+const SB_REV_LEVEL: usize = 76;
+const SB_INODE_SIZE: usize = 88;
+const SB_FEATURE_INCOMPAT: usize = 96;
+const SB_FEATURE_RO_COMPAT: usize = 100;
+
+// 块组描述符字段偏移
+const GD_BLOCK_BITMAP: usize = 0;
+const GD_INODE_BITMAP: usize = 4;
+const GD_INODE_TABLE: usize = 8;
+const GD_FREE_BLOCKS: usize = 12;
+const GD_FREE_INODES: usize = 14;
+const GD_USED_DIRS: usize = 16;
+
+const FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+/// sparse_super和large_file，两者都不影响读写
+const FEATURE_RO_COMPAT_KNOWN: u32 = 0x0001 | 0x0002;
+
+const S_IFMT: u16 = 0xF000;
+const S_IFLNK: u16 = 0xA000;
+const S_IFREG: u16 = 0x8000;
+const S_IFDIR: u16 = 0x4000;
+
+/// 目录带有htree索引，本驱动不维护索引，修改目录时清除该标志
+const EXT2_INDEX_FL: u32 = 0x1000;
+
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_SYMLINK: u8 = 7;
+
+fn le16(buf: &[u8], offset: usize) -> u16 {
+    LittleEndian::read_u16(&buf[offset..])
+}
+
+fn le32(buf: &[u8], offset: usize) -> u32 {
+    LittleEndian::read_u32(&buf[offset..])
+}
+
+fn set_le16(buf: &mut [u8], offset: usize, value: u16) {
+    LittleEndian::write_u16(&mut buf[offset..], value)
+}
+
+fn set_le32(buf: &mut [u8], offset: usize, value: u32) {
+    LittleEndian::write_u32(&mut buf[offset..], value)
+}
+
+fn read_at<IO: fatfs::ReadWriteSeek>(disk: &mut IO, pos: u64, buf: &mut [u8]) -> Result<(), FileError> {
+    disk.seek(SeekFrom::Start(pos)).map_err(|_| DeviceIOError)?;
+    disk.read_exact(buf).map_err(|_| DeviceIOError)
+}
+
+fn write_at<IO: fatfs::ReadWriteSeek>(disk: &mut IO, pos: u64, buf: &[u8]) -> Result<(), FileError> {
+    disk.seek(SeekFrom::Start(pos)).map_err(|_| DeviceIOError)?;
+    disk.write_all(buf).map_err(|_| DeviceIOError)
+}
+
+/// 检查磁盘上是否是ext2文件系统，检查后磁盘位置回到开头
+pub fn probe<IO: fatfs::ReadWriteSeek>(disk: &mut IO) -> bool {
+    let mut magic = [0u8; 2];
+    let result = read_at(disk, SUPERBLOCK_OFFSET + SB_MAGIC as u64, &mut magic).is_ok() && LittleEndian::read_u16(&magic) == EXT2_MAGIC;
+    let _ = disk.seek(SeekFrom::Start(0));
+    result
+}
+
+/// Unix时间戳转日期时间
+fn datetime(time: u32) -> DateTime {
+    let days = (time / 86400) as i64;
+    let secs = time % 86400;
+    // 按公历400年一个周期换算，见 http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    DateTime::new(
+        Date::new(year as u16, month as u16, day as u16),
+        Time::new((secs / 3600) as u16, (secs / 60 % 60) as u16, (secs % 60) as u16, 0),
+    )
+}
+
+/// 日期时间转Unix时间戳，早于1970年的时间记为0
+fn timestamp(datetime: DateTime) -> u32 {
+    let month = datetime.date.month as i64;
+    let year = datetime.date.year as i64 - if month <= 2 { 1 } else { 0 };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + datetime.date.day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let secs = days * 86400 + datetime.time.hour as i64 * 3600 + datetime.time.min as i64 * 60 + datetime.time.sec as i64;
+    secs.clamp(0, u32::MAX as i64) as u32
+}
+
+/// 目录项在磁盘上占用的长度（4字节对齐）
+fn entry_len(name_len: usize) -> usize {
+    (8 + name_len + 3) & !3
+}
+
+/// 在位图的前`limit`位中找到一个空闲位并置位
+fn take_free_bit(bitmap: &mut [u8], limit: usize) -> Option<usize> {
+    let bit = (0..limit).find(|bit| bitmap[bit / 8] & (1 << (bit % 8)) == 0)?;
+    bitmap[bit / 8] |= 1 << (bit % 8);
+    Some(bit)
+}
+
+/// 磁盘上的inode，保留原始字节，写回时不会丢失不认识的字段
+struct Inode {
+    raw: Vec<u8>,
+}
+
+impl Inode {
+    fn new(size: usize, mode: u16, links: u16) -> Self {
+        let mut inode = Self { raw: vec![0u8; size] };
+        let time = timestamp(now());
+        set_le16(&mut inode.raw, 0, mode);
+        set_le32(&mut inode.raw, 8, time);
+        set_le32(&mut inode.raw, 12, time);
+        set_le32(&mut inode.raw, 16, time);
+        inode.set_links(links);
+        inode
+    }
+
+    fn mode(&self) -> u16 {
+        le16(&self.raw, 0)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.mode() & S_IFMT == S_IFDIR
+    }
+
+    fn is_file(&self) -> bool {
+        self.mode() & S_IFMT == S_IFREG
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.mode() & S_IFMT == S_IFLNK
+    }
+
+    /// 目录项中记录的文件类型
+    fn file_type(&self) -> u8 {
+        match self.mode() & S_IFMT {
+            S_IFREG => FT_REG_FILE,
+            S_IFDIR => FT_DIR,
+            S_IFLNK => FT_SYMLINK,
+            _ => 0,
+        }
+    }
+
+    /// 文件长度，普通文件的高32位存在`i_dir_acl`里
+    fn size(&self) -> u64 {
+        let high = if self.is_file() { le32(&self.raw, 108) as u64 } else { 0 };
+        high << 32 | le32(&self.raw, 4) as u64
+    }
+
+    fn set_size(&mut self, size: u64) {
+        set_le32(&mut self.raw, 4, size as u32);
+        if self.is_file() {
+            set_le32(&mut self.raw, 108, (size >> 32) as u32);
+        }
+    }
+
+    fn mtime(&self) -> u32 {
+        le32(&self.raw, 16)
+    }
+
+    /// 内容被修改，更新ctime和mtime
+    fn touch(&mut self) {
+        let time = timestamp(now());
+        set_le32(&mut self.raw, 12, time);
+        set_le32(&mut self.raw, 16, time);
+    }
+
+    fn links(&self) -> u16 {
+        le16(&self.raw, 26)
+    }
+
+    fn set_links(&mut self, links: u16) {
+        set_le16(&mut self.raw, 26, links)
+    }
+
+    /// 占用的512字节扇区数，包括间接块
+    fn sectors(&self) -> u32 {
+        le32(&self.raw, 28)
+    }
+
+    fn add_sectors(&mut self, count: u32) {
+        let sectors = self.sectors().wrapping_add(count);
+        set_le32(&mut self.raw, 28, sectors)
+    }
+
+    fn sub_sectors(&mut self, count: u32) {
+        let sectors = self.sectors().saturating_sub(count);
+        set_le32(&mut self.raw, 28, sectors)
+    }
+
+    fn clear_index(&mut self) {
+        let flags = le32(&self.raw, 32) & !EXT2_INDEX_FL;
+        set_le32(&mut self.raw, 32, flags)
+    }
+
+    fn file_acl(&self) -> u32 {
+        le32(&self.raw, 104)
+    }
+
+    fn block(&self, slot: usize) -> u32 {
+        le32(&self.raw, 40 + slot * 4)
+    }
+
+    fn set_block(&mut self, slot: usize, block: u32) {
+        set_le32(&mut self.raw, 40 + slot * 4, block)
+    }
+}
+
+/// 目录项
+struct DirEntry {
+    inode: u32,
+    name: String,
+}
+
+/// 目录项在磁盘上的位置：所在块、块内偏移、同一块中前一项的偏移
+struct EntryPos {
+    block: u32,
+    pos: usize,
+    prev: Option<usize>,
+}
+
+struct Ext2<IO: fatfs::ReadWriteSeek> {
+    disk: IO,
+    superblock: Vec<u8>,
+    /// 块组描述符表
+    groups: Vec<u8>,
+    block_size: u64,
+    inode_size: usize,
+    filetype: bool,
+    read_only: bool,
+}
+
+/// 挂载在VFS上的ext2文件系统
+pub struct Ext2FileSystem<IO: fatfs::ReadWriteSeek> {
+    inner: Mutex<Ext2<IO>>,
+}
+
+impl<IO: fatfs::ReadWriteSeek> Ext2FileSystem<IO> {
+    pub fn new(mut disk: IO) -> Result<Self, FileError> {
+        let mut superblock = vec![0u8; SUPERBLOCK_SIZE];
+        read_at(&mut disk, SUPERBLOCK_OFFSET, &mut superblock)?;
+        if le16(&superblock, SB_MAGIC) != EXT2_MAGIC {
+            return Err(DeviceIOError);
+        }
+
+        // 修订版0没有特性字段，inode固定为128字节
+        let dynamic = le32(&superblock, SB_REV_LEVEL) >= 1;
+        let incompat = if dynamic { le32(&superblock, SB_FEATURE_INCOMPAT) } else { 0 };
+        let ro_compat = if dynamic { le32(&superblock, SB_FEATURE_RO_COMPAT) } else { 0 };
+        if incompat & !FEATURE_INCOMPAT_FILETYPE != 0 {
+            return Err(FileError::UnsupportedError);
+        }
+        let inode_size = if dynamic { le16(&superblock, SB_INODE_SIZE) as usize } else { 128 };
+        let log_block_size = le32(&superblock, SB_LOG_BLOCK_SIZE);
+        if log_block_size > MAX_LOG_BLOCK_SIZE {
+            return Err(FileError::UnsupportedError);
+        }
+        let block_size = 1024u64 << log_block_size;
+
+        // 超级块来自磁盘，不合理的字段会在后面的计算中溢出或除零
+        let blocks_count = le32(&superblock, SB_BLOCKS_COUNT);
+        let first_data_block = le32(&superblock, SB_FIRST_DATA_BLOCK);
+        let blocks_per_group = le32(&superblock, SB_BLOCKS_PER_GROUP);
+        let inodes_per_group = le32(&superblock, SB_INODES_PER_GROUP);
+        // 每个块组的位图只占一块
+        let bits_per_block = block_size * 8;
+        if blocks_count <= first_data_block
+            || blocks_per_group == 0
+            || blocks_per_group as u64 > bits_per_block
+            || inodes_per_group == 0
+            || inodes_per_group as u64 > bits_per_block
+            || inode_size < 128
+            || inode_size as u64 > block_size
+            || !inode_size.is_power_of_two()
+        {
+            return Err(FileError::UnsupportedError);
+        }
+        let disk_len = disk.seek(SeekFrom::End(0)).map_err(|_| DeviceIOError)?;
+        if blocks_count as u64 * block_size > disk_len {
+            return Err(FileError::UnsupportedError);
+        }
+
+        let data_blocks = blocks_count - first_data_block;
+        let group_count = (data_blocks - 1) / blocks_per_group + 1;
+        let mut groups = vec![0u8; group_count as usize * GROUP_DESC_SIZE];
+        let table_block = first_data_block as u64 + 1;
+        read_at(&mut disk, table_block * block_size, &mut groups)?;
+
+        let fs = Ext2 {
+            disk,
+            superblock,
+            groups,
+            block_size,
+            inode_size,
+            filetype: incompat & FEATURE_INCOMPAT_FILETYPE != 0,
+            read_only: ro_compat & !FEATURE_RO_COMPAT_KNOWN != 0,
+        };
+        Ok(Self { inner: Mutex::new(fs) })
+    }
+}
+
+impl<IO: fatfs::ReadWriteSeek> Ext2<IO> {
+    fn sb(&self, field: usize) -> u32 {
+        le32(&self.superblock, field)
+    }
+
+    fn group_count(&self) -> u32 {
+        (self.groups.len() / GROUP_DESC_SIZE) as u32
+    }
+
+    fn group_u32(&self, group: u32, field: usize) -> u32 {
+        le32(&self.groups, group as usize * GROUP_DESC_SIZE + field)
+    }
+
+    fn group_u16(&self, group: u32, field: usize) -> u16 {
+        le16(&self.groups, group as usize * GROUP_DESC_SIZE + field)
+    }
+
+    /// 调整块组和超级块中的计数
+    fn adjust_count(&mut self, group: u32, group_field: usize, sb_field: Option<usize>, delta: i32) {
+        let offset = group as usize * GROUP_DESC_SIZE + group_field;
+        let value = le16(&self.groups, offset).wrapping_add(delta as u16);
+        set_le16(&mut self.groups, offset, value);
+        if let Some(sb_field) = sb_field {
+            let value = le32(&self.superblock, sb_field).wrapping_add(delta as u32);
+            set_le32(&mut self.superblock, sb_field, value);
+        }
+    }
+
+    /// 把超级块和块组描述符表写回磁盘
+    fn sync_meta(&mut self) -> Result<(), FileError> {
+        write_at(&mut self.disk, SUPERBLOCK_OFFSET, &self.superblock)?;
+        let table_block = self.sb(SB_FIRST_DATA_BLOCK) as u64 + 1;
+        write_at(&mut self.disk, table_block * self.block_size, &self.groups)
+    }
+
+    fn check_writable(&self) -> Result<(), FileError> {
+        if self.read_only {
+            Err(ReadOnlyError)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read_block(&mut self, block: u32) -> Result<Vec<u8>, FileError> {
+        let mut data = vec![0u8; self.block_size as usize];
+        read_at(&mut self.disk, block as u64 * self.block_size, &mut data)?;
+        Ok(data)
+    }
+
+    fn write_block(&mut self, block: u32, data: &[u8]) -> Result<(), FileError> {
+        write_at(&mut self.disk, block as u64 * self.block_size, data)
+    }
+
+    /// 读取间接块中的第`index`项
+    fn read_pointer(&mut self, block: u32, index: u64) -> Result<u32, FileError> {
+        let mut buf = [0u8; 4];
+        read_at(&mut self.disk, block as u64 * self.block_size + index * 4, &mut buf)?;
+        Ok(LittleEndian::read_u32(&buf))
+    }
+
+    fn write_pointer(&mut self, block: u32, index: u64, value: u32) -> Result<(), FileError> {
+        let mut buf = [0u8; 4];
+        LittleEndian::write_u32(&mut buf, value);
+        write_at(&mut self.disk, block as u64 * self.block_size + index * 4, &buf)
+    }
+
+    fn inode_pos(&self, ino: u32) -> Result<u64, FileError> {
+        if ino == 0 || ino > self.sb(SB_INODES_COUNT) {
+            return Err(NotFoundError);
+        }
+        let per_group = self.sb(SB_INODES_PER_GROUP);
+        let group = (ino - 1) / per_group;
+        if group >= self.group_count() {
+            return Err(DeviceIOError);
+        }
+        let table = self.group_u32(group, GD_INODE_TABLE) as u64;
+        Ok(table * self.block_size + ((ino - 1) % per_group) as u64 * self.inode_size as u64)
+    }
+
+    fn read_inode(&mut self, ino: u32) -> Result<Inode, FileError> {
+        let pos = self.inode_pos(ino)?;
+        let mut raw = vec![0u8; self.inode_size];
+        read_at(&mut self.disk, pos, &mut raw)?;
+        Ok(Inode { raw })
+    }
+
+    fn write_inode(&mut self, ino: u32, inode: &Inode) -> Result<(), FileError> {
+        let pos = self.inode_pos(ino)?;
+        write_at(&mut self.disk, pos, &inode.raw)
+    }
+
+    /// 分配一个数据块并清零
+    fn alloc_block(&mut self) -> Result<u32, FileError> {
+        let first = self.sb(SB_FIRST_DATA_BLOCK);
+        let per_group = self.sb(SB_BLOCKS_PER_GROUP);
+        for group in 0..self.group_count() {
+            if self.group_u16(group, GD_FREE_BLOCKS) == 0 {
+                continue;
+            }
+            let bitmap_block = self.group_u32(group, GD_BLOCK_BITMAP);
+            let mut bitmap = self.read_block(bitmap_block)?;
+            // 最后一个块组可能不满
+            let limit = per_group.min(self.sb(SB_BLOCKS_COUNT) - first - group * per_group) as usize;
+            if let Some(bit) = take_free_bit(&mut bitmap, limit) {
+                self.write_block(bitmap_block, &bitmap)?;
+                self.adjust_count(group, GD_FREE_BLOCKS, Some(SB_FREE_BLOCKS), -1);
+                self.sync_meta()?;
+                let block = first + group * per_group + bit as u32;
+                self.write_block(block, &vec![0u8; self.block_size as usize])?;
+                return Ok(block);
+            }
+        }
+        Err(FileError::NoSpaceError)
+    }
+
+    fn free_block(&mut self, block: u32) -> Result<(), FileError> {
+        let per_group = self.sb(SB_BLOCKS_PER_GROUP);
+        let first = self.sb(SB_FIRST_DATA_BLOCK);
+        if block < first || block >= self.sb(SB_BLOCKS_COUNT) {
+            return Err(DeviceIOError);
+        }
+        let index = block - first;
+        let (group, bit) = (index / per_group, (index % per_group) as usize);
+        let bitmap_block = self.group_u32(group, GD_BLOCK_BITMAP);
+        let mut bitmap = self.read_block(bitmap_block)?;
+        bitmap[bit / 8] &= !(1 << (bit % 8));
+        self.write_block(bitmap_block, &bitmap)?;
+        self.adjust_count(group, GD_FREE_BLOCKS, Some(SB_FREE_BLOCKS), 1);
+        self.sync_meta()
+    }
+
+    fn alloc_inode(&mut self, is_dir: bool) -> Result<u32, FileError> {
+        let per_group = self.sb(SB_INODES_PER_GROUP);
+        for group in 0..self.group_count() {
+            if self.group_u16(group, GD_FREE_INODES) == 0 {
+                continue;
+            }
+            let bitmap_block = self.group_u32(group, GD_INODE_BITMAP);
+            let mut bitmap = self.read_block(bitmap_block)?;
+            if let Some(bit) = take_free_bit(&mut bitmap, per_group as usize) {
+                self.write_block(bitmap_block, &bitmap)?;
+                self.adjust_count(group, GD_FREE_INODES, Some(SB_FREE_INODES), -1);
+                if is_dir {
+                    self.adjust_count(group, GD_USED_DIRS, None, 1);
+                }
+                self.sync_meta()?;
+                return Ok(group * per_group + bit as u32 + 1);
+            }
+        }
+        Err(FileError::NoSpaceError)
+    }
+
+    /// 文件第`index`块在inode块表中的位置：`i_block`中的槽位和各级间接块中的下标
+    fn locate(&self, mut index: u64) -> Result<(usize, Vec<u64>), FileError> {
+        if index < DIRECT_BLOCKS {
+            return Ok((index as usize, Vec::new()));
+        }
+        index -= DIRECT_BLOCKS;
+        let per_block = self.block_size / 4;
+        let mut span = per_block;
+        for depth in 1..=3 {
+            if index < span {
+                let mut path = vec![0u64; depth];
+                for level in (0..depth).rev() {
+                    path[level] = index % per_block;
+                    index /= per_block;
+                }
+                return Ok((DIRECT_BLOCKS as usize - 1 + depth, path));
+            }
+            index -= span;
+            span *= per_block;
+        }
+        Err(FileError::UnsupportedError)
+    }
+
+    /// 文件第`index`块对应的磁盘块，空洞返回0
+    fn bmap(&mut self, inode: &Inode, index: u64) -> Result<u32, FileError> {
+        let (slot, path) = self.locate(index)?;
+        let mut block = inode.block(slot);
+        for index in path {
+            if block == 0 {
+                break;
+            }
+            block = self.read_pointer(block, index)?;
+        }
+        Ok(block)
+    }
+
+    /// 与[`Ext2::bmap`]相同，但会按需分配数据块和间接块
+    fn bmap_alloc(&mut self, inode: &mut Inode, index: u64) -> Result<u32, FileError> {
+        let sectors = (self.block_size / 512) as u32;
+        let (slot, path) = self.locate(index)?;
+        let mut block = inode.block(slot);
+        if block == 0 {
+            block = self.alloc_block()?;
+            inode.set_block(slot, block);
+            inode.add_sectors(sectors);
+        }
+        for index in path {
+            let mut next = self.read_pointer(block, index)?;
+            if next == 0 {
+                next = self.alloc_block()?;
+                self.write_pointer(block, index, next)?;
+                inode.add_sectors(sectors);
+            }
+            block = next;
+        }
+        Ok(block)
+    }
+
+    /// 释放文件第`keep`块及以后的数据块，以及因此不再需要的间接块
+    fn free_blocks_from(&mut self, inode: &mut Inode, keep: u64) -> Result<(), FileError> {
+        let sectors = (self.block_size / 512) as u32;
+        for slot in keep.min(DIRECT_BLOCKS) as usize..DIRECT_BLOCKS as usize {
+            let block = inode.block(slot);
+            if block != 0 {
+                self.free_block(block)?;
+                inode.set_block(slot, 0);
+                inode.sub_sectors(sectors);
+            }
+        }
+
+        let per_block = self.block_size / 4;
+        let mut base = DIRECT_BLOCKS;
+        let mut span = per_block;
+        for depth in 1..=3u32 {
+            let slot = DIRECT_BLOCKS as usize - 1 + depth as usize;
+            let block = inode.block(slot);
+            let tree_keep = keep.saturating_sub(base);
+            if block != 0 && tree_keep < span && self.free_tree(inode, block, depth, tree_keep)? {
+                inode.set_block(slot, 0);
+            }
+            base += span;
+            span *= per_block;
+        }
+        Ok(())
+    }
+
+    /// 释放深度为`depth`的间接块树中第`keep`个及以后的数据块，整棵树都被释放时返回true
+    fn free_tree(&mut self, inode: &mut Inode, block: u32, depth: u32, keep: u64) -> Result<bool, FileError> {
+        let sectors = (self.block_size / 512) as u32;
+        let per_block = self.block_size / 4;
+        let child_span = per_block.pow(depth - 1);
+        let mut data = self.read_block(block)?;
+        let mut changed = false;
+
+        for index in 0..per_block {
+            let child = le32(&data, index as usize * 4);
+            let child_keep = keep.saturating_sub(index * child_span);
+            if child == 0 || child_keep >= child_span {
+                continue;
+            }
+            let freed = if depth == 1 {
+                self.free_block(child)?;
+                inode.sub_sectors(sectors);
+                true
+            } else {
+                self.free_tree(inode, child, depth - 1, child_keep)?
+            };
+            if freed {
+                set_le32(&mut data, index as usize * 4, 0);
+                changed = true;
+            }
+        }
+
+        if keep == 0 {
+            self.free_block(block)?;
+            inode.sub_sectors(sectors);
+            Ok(true)
+        } else {
+            if changed {
+                self.write_block(block, &data)?;
+            }
+            Ok(false)
+        }
+    }
+
+    fn read_data(&mut self, inode: &Inode, offset: u64, buf: &mut [u8]) -> Result<usize, FileError> {
+        let size = inode.size();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = (buf.len() as u64).min(size - offset) as usize;
+        let mut done = 0usize;
+        while done < len {
+            let pos = offset + done as u64;
+            let in_block = (pos % self.block_size) as usize;
+            let count = (self.block_size as usize - in_block).min(len - done);
+            let block = self.bmap(inode, pos / self.block_size)?;
+            if block == 0 {
+                buf[done..done + count].fill(0);
+            } else {
+                read_at(&mut self.disk, block as u64 * self.block_size + in_block as u64, &mut buf[done..done + count])?;
+            }
+            done += count;
+        }
+        Ok(len)
+    }
+
+    /// 写入数据并按需增长文件，调用者负责写回inode
+    fn write_data(&mut self, inode: &mut Inode, offset: u64, buf: &[u8]) -> Result<usize, FileError> {
+        let mut done = 0usize;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let in_block = (pos % self.block_size) as usize;
+            let count = (self.block_size as usize - in_block).min(buf.len() - done);
+            let block = self.bmap_alloc(inode, pos / self.block_size)?;
+            write_at(&mut self.disk, block as u64 * self.block_size + in_block as u64, &buf[done..done + count])?;
+            done += count;
+        }
+        if offset + buf.len() as u64 > inode.size() {
+            inode.set_size(offset + buf.len() as u64);
+        }
+        inode.touch();
+        Ok(buf.len())
+    }
+
+    /// 读取符号链接的目标，短目标直接存在`i_block`里
+    fn read_link(&mut self, inode: &Inode) -> Result<String, FileError> {
+        // 链接目标最长一块
+        if inode.size() > self.block_size {
+            return Err(DeviceIOError);
+        }
+        let size = inode.size() as usize;
+        let acl_sectors = if inode.file_acl() != 0 { (self.block_size / 512) as u32 } else { 0 };
+        let target = if inode.sectors() == acl_sectors {
+            inode.raw[40..40 + size.min(60)].to_vec()
+        } else {
+            let mut data = vec![0u8; size];
+            self.read_data(inode, 0, &mut data)?;
+            data
+        };
+        String::from_utf8(target).map_err(|_| BadRelatePathError)
+    }
+
+    fn dir_entries(&mut self, dir: &Inode) -> Result<Vec<DirEntry>, FileError> {
+        let mut data = vec![0u8; dir.size() as usize];
+        self.read_data(dir, 0, &mut data)?;
+        let mut result = Vec::new();
+        let mut pos = 0usize;
+        while pos + 8 <= data.len() {
+            let inode = le32(&data, pos);
+            let rec_len = le16(&data, pos + 4) as usize;
+            let name_len = data[pos + 6] as usize;
+            if rec_len < 8 || pos + rec_len > data.len() || 8 + name_len > rec_len {
+                return Err(DeviceIOError);
+            }
+            if inode != 0 {
+                let name = String::from_utf8_lossy(&data[pos + 8..pos + 8 + name_len]).into_owned();
+                result.push(DirEntry { inode, name });
+            }
+            pos += rec_len;
+        }
+        Ok(result)
+    }
+
+    fn find_entry(&mut self, dir: &Inode, name: &str) -> Result<Option<u32>, FileError> {
+        Ok(self.dir_entries(dir)?.into_iter().find(|x| x.name == name).map(|x| x.inode))
+    }
+
+    /// 找到目录项在磁盘上的位置
+    fn locate_entry(&mut self, dir: &Inode, name: &str) -> Result<Option<EntryPos>, FileError> {
+        let block_size = self.block_size as usize;
+        for index in 0..dir.size() / self.block_size {
+            let block = self.bmap(dir, index)?;
+            if block == 0 {
+                continue;
+            }
+            let data = self.read_block(block)?;
+            let (mut pos, mut prev) = (0usize, None);
+            while pos + 8 <= block_size {
+                let rec_len = le16(&data, pos + 4) as usize;
+                let name_len = data[pos + 6] as usize;
+                if rec_len < 8 || pos + rec_len > block_size || 8 + name_len > rec_len {
+                    return Err(DeviceIOError);
+                }
+                if le32(&data, pos) != 0 && &data[pos + 8..pos + 8 + name_len] == name.as_bytes() {
+                    return Ok(Some(EntryPos { block, pos, prev }));
+                }
+                prev = Some(pos);
+                pos += rec_len;
+            }
+        }
+        Ok(None)
+    }
+
+    fn put_entry(&self, data: &mut [u8], rec_len: usize, name: &str, ino: u32, file_type: u8) {
+        set_le32(data, 0, ino);
+        set_le16(data, 4, rec_len as u16);
+        data[6] = name.len() as u8;
+        data[7] = if self.filetype { file_type } else { 0 };
+        data[8..8 + name.len()].copy_from_slice(name.as_bytes());
+    }
+
+    /// 在目录中添加一项，优先利用已有块中的空隙
+    fn add_entry(&mut self, dir_ino: u32, name: &str, ino: u32, file_type: u8) -> Result<(), FileError> {
+        let mut dir = self.read_inode(dir_ino)?;
+        dir.clear_index();
+        let block_size = self.block_size as usize;
+        let needed = entry_len(name.len());
+        let blocks = dir.size() / self.block_size;
+
+        for index in 0..blocks {
+            let block = self.bmap(&dir, index)?;
+            if block == 0 {
+                continue;
+            }
+            let mut data = self.read_block(block)?;
+            let mut pos = 0usize;
+            while pos + 8 <= block_size {
+                let rec_len = le16(&data, pos + 4) as usize;
+                let used = if le32(&data, pos) == 0 { 0 } else { entry_len(data[pos + 6] as usize) };
+                if rec_len < 8 || pos + rec_len > block_size || used > rec_len {
+                    return Err(DeviceIOError);
+                }
+                if rec_len - used >= needed {
+                    if used > 0 {
+                        set_le16(&mut data, pos + 4, used as u16);
+                    }
+                    self.put_entry(&mut data[pos + used..], rec_len - used, name, ino, file_type);
+                    self.write_block(block, &data)?;
+                    dir.touch();
+                    return self.write_inode(dir_ino, &dir);
+                }
+                pos += rec_len;
+            }
+        }
+
+        // 没有空隙，追加一个新块
+        let block = self.bmap_alloc(&mut dir, blocks)?;
+        let mut data = vec![0u8; block_size];
+        self.put_entry(&mut data, block_size, name, ino, file_type);
+        self.write_block(block, &data)?;
+        dir.set_size(dir.size() + self.block_size);
+        dir.touch();
+        self.write_inode(dir_ino, &dir)
+    }
+
+    /// 删除目录项，空出的位置并入前一项
+    fn remove_entry(&mut self, dir_ino: u32, name: &str) -> Result<(), FileError> {
+        let mut dir = self.read_inode(dir_ino)?;
+        let entry = self.locate_entry(&dir, name)?.ok_or(NotFoundError)?;
+        let mut data = self.read_block(entry.block)?;
+        match entry.prev {
+            Some(prev) => {
+                let merged = le16(&data, prev + 4) + le16(&data, entry.pos + 4);
+                set_le16(&mut data, prev + 4, merged);
+            }
+            None => set_le32(&mut data, entry.pos, 0),
+        }
+        self.write_block(entry.block, &data)?;
+        dir.touch();
+        self.write_inode(dir_ino, &dir)
+    }
+
+    /// 修改目录项指向的inode
+    fn update_entry(&mut self, dir_ino: u32, name: &str, ino: u32) -> Result<(), FileError> {
+        let dir = self.read_inode(dir_ino)?;
+        let entry = self.locate_entry(&dir, name)?.ok_or(NotFoundError)?;
+        let mut data = self.read_block(entry.block)?;
+        set_le32(&mut data, entry.pos, ino);
+        self.write_block(entry.block, &data)
+    }
+
+    fn add_links(&mut self, ino: u32, delta: i16) -> Result<(), FileError> {
+        let mut inode = self.read_inode(ino)?;
+        inode.set_links(inode.links().wrapping_add(delta as u16));
+        self.write_inode(ino, &inode)
+    }
+
+    /// 解析路径得到inode号，`follow`决定最后一级是符号链接时是否跟随
+    fn lookup(&mut self, path: &str, follow: bool) -> Result<u32, FileError> {
+        // 待解析的路径分量，逆序存放，末尾是下一个要解析的
+        let mut pending: Vec<String> = path.split('/').filter(|x| !x.is_empty()).rev().map(String::from).collect();
+        let mut current = ROOT_INODE;
+        let mut links = 0usize;
+
+        while let Some(name) = pending.pop() {
+            let dir = self.read_inode(current)?;
+            if !dir.is_dir() {
+                return Err(NotADirError);
+            }
+            let ino = self.find_entry(&dir, name.as_str())?.ok_or(NotFoundError)?;
+            let inode = self.read_inode(ino)?;
+            if inode.is_symlink() && (follow || !pending.is_empty()) {
+                links += 1;
+                if links > MAX_SYMLINKS {
+                    return Err(BadRelatePathError);
+                }
+                let target = self.read_link(&inode)?;
+                if target.starts_with('/') {
+                    current = ROOT_INODE;
+                }
+                pending.extend(target.split('/').filter(|x| !x.is_empty()).rev().map(String::from));
+                continue;
+            }
+            current = ino;
+        }
+        Ok(current)
+    }
+
+    /// 解析路径的父目录，返回父目录inode号和文件名
+    fn lookup_parent<'a>(&mut self, path: &'a str) -> Result<(u32, &'a str), FileError> {
+        let name = filename(path);
+        if name.is_empty() {
+            return Err(RootDirError);
+        }
+        if name.len() > MAX_NAME_LEN {
+            return Err(BadRelatePathError);
+        }
+        let parent = self.lookup(dirname(path), true)?;
+        if !self.read_inode(parent)?.is_dir() {
+            return Err(NotADirError);
+        }
+        Ok((parent, name))
+    }
+
+    fn metadata(&mut self, path: &str, ino: u32) -> Result<Metadata, FileError> {
+        let inode = self.read_inode(ino)?;
+        let len = if inode.is_dir() { 0 } else { inode.size() };
+        Ok(Metadata::new(path, inode.is_dir(), len, datetime(inode.mtime())).with_unix(ino as u64, inode.mode()))
+    }
+}
+
+impl<IO: fatfs::ReadWriteSeek + Send> FileSystem for Ext2FileSystem<IO> {
+    fn metadata(&self, path: &str) -> Result<Metadata, FileError> {
+        let mut fs = self.inner.lock();
+        let ino = fs.lookup(path, true)?;
+        fs.metadata(path, ino)
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<FileEntry>, FileError> {
+        self.read_dir(path, 0, usize::MAX)
+    }
+
+    fn read_dir(&self, path: &str, start: usize, count: usize) -> Result<Vec<FileEntry>, FileError> {
+        let mut fs = self.inner.lock();
+        let ino = fs.lookup(path, true)?;
+        let dir = fs.read_inode(ino)?;
+        if !dir.is_dir() {
+            return Err(NotADirError);
+        }
+
+        let entries = fs.dir_entries(&dir)?;
+        let mut result = Vec::new();
+        for entry in entries.into_iter().filter(|x| x.name != "." && x.name != "..").skip(start).take(count) {
+            let child = path_combine(path, entry.name.as_str());
+            // 符号链接显示为它指向的文件，悬空的链接显示为链接本身
+            let target = fs.lookup(child.as_str(), true).unwrap_or(entry.inode);
+            let metadata = fs.metadata(child.as_str(), target)?;
+            if metadata.is_dir() {
+                result.push(FileEntry::Dir(metadata));
+            } else {
+                result.push(FileEntry::File(metadata));
+            }
+        }
+        Ok(result)
+    }
+
+    fn read(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, FileError> {
+        let mut fs = self.inner.lock();
+        let ino = fs.lookup(path, true)?;
+        let inode = fs.read_inode(ino)?;
+        if inode.is_dir() {
+            return Err(NotAFileError);
+        }
+        fs.read_data(&inode, offset, buf)
+    }
+
+    fn write(&self, path: &str, offset: u64, buf: &[u8]) -> Result<usize, FileError> {
+        let mut fs = self.inner.lock();
+        fs.check_writable()?;
+        let ino = fs.lookup(path, true)?;
+        let mut inode = fs.read_inode(ino)?;
+        if !inode.is_file() {
+            return Err(NotAFileError);
+        }
+        let result = fs.write_data(&mut inode, offset, buf);
+        // 即使中途出错，已分配的块也要记录下来
+        fs.write_inode(ino, &inode)?;
+        result
+    }
+
+    fn truncate(&self, path: &str, len: u64) -> Result<(), FileError> {
+        let mut fs = self.inner.lock();
+        fs.check_writable()?;
+        let ino = fs.lookup(path, true)?;
+        let mut inode = fs.read_inode(ino)?;
+        if !inode.is_file() {
+            return Err(NotAFileError);
+        }
+
+        if len < inode.size() {
+            let block_size = fs.block_size;
+            let keep = (len + block_size - 1) / block_size;
+            fs.free_blocks_from(&mut inode, keep)?;
+            // 清零最后一块中截掉的部分，以后扩展文件时读到的是0
+            let in_block = (len % block_size) as usize;
+            if in_block != 0 {
+                let block = fs.bmap(&inode, len / block_size)?;
+                if block != 0 {
+                    let zeros = vec![0u8; block_size as usize - in_block];
+                    write_at(&mut fs.disk, block as u64 * block_size + in_block as u64, &zeros)?;
+                }
+            }
+        }
+        inode.set_size(len);
+        inode.touch();
+        fs.write_inode(ino, &inode)
+    }
+
+    fn create(&self, path: &str) -> Result<(), FileError> {
+        let mut fs = self.inner.lock();
+        fs.check_writable()?;
+        let (parent, name) = fs.lookup_parent(path)?;
+        let parent_inode = fs.read_inode(parent)?;
+        if let Some(ino) = fs.find_entry(&parent_inode, name)? {
+            return if fs.read_inode(ino)?.is_dir() { Err(NotAFileError) } else { Ok(()) };
+        }
+
+        let ino = fs.alloc_inode(false)?;
+        let inode = Inode::new(fs.inode_size, S_IFREG | 0o644, 1);
+        fs.write_inode(ino, &inode)?;
+        fs.add_entry(parent, name, ino, FT_REG_FILE)
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), FileError> {
+        if path == "/" {
+            return Ok(());
+        }
+        let mut fs = self.inner.lock();
+        fs.check_writable()?;
+        let (parent, name) = fs.lookup_parent(path)?;
+        let parent_inode = fs.read_inode(parent)?;
+        if let Some(ino) = fs.find_entry(&parent_inode, name)? {
+            return if fs.read_inode(ino)?.is_dir() { Ok(()) } else { Err(NotADirError) };
+        }
+
+        let ino = fs.alloc_inode(true)?;
+        let mut inode = Inode::new(fs.inode_size, S_IFDIR | 0o755, 2);
+        let block = fs.alloc_block()?;
+        let block_size = fs.block_size as usize;
+        let mut data = vec![0u8; block_size];
+        fs.put_entry(&mut data, 12, ".", ino, FT_DIR);
+        fs.put_entry(&mut data[12..], block_size - 12, "..", parent, FT_DIR);
+        fs.write_block(block, &data)?;
+        inode.set_block(0, block);
+        inode.add_sectors((fs.block_size / 512) as u32);
+        inode.set_size(fs.block_size);
+        fs.write_inode(ino, &inode)?;
+
+        fs.add_entry(parent, name, ino, FT_DIR)?;
+        fs.add_links(parent, 1)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), FileError> {
+        let mut fs = self.inner.lock();
+        fs.check_writable()?;
+        let (from_parent, from_name) = fs.lookup_parent(from)?;
+        let (to_parent, to_name) = fs.lookup_parent(to)?;
+
+        let from_dir = fs.read_inode(from_parent)?;
+        let ino = fs.find_entry(&from_dir, from_name)?.ok_or(NotFoundError)?;
+        let to_dir = fs.read_inode(to_parent)?;
+        if fs.find_entry(&to_dir, to_name)?.is_some() {
+            return Err(AlreadyExistsError);
+        }
+        let inode = fs.read_inode(ino)?;
+        let is_dir = inode.is_dir();
+        // 不能把目录移动到它自己下面
+        if is_dir && to.starts_with(from) && to.as_bytes().get(from.len()) == Some(&b'/') {
+            return Err(BadRelatePathError);
+        }
+
+        fs.add_entry(to_parent, to_name, ino, inode.file_type())?;
+        fs.remove_entry(from_parent, from_name)?;
+        if is_dir && from_parent != to_parent {
+            fs.update_entry(ino, "..", to_parent)?;
+            fs.add_links(from_parent, -1)?;
+            fs.add_links(to_parent, 1)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::format;
+    use alloc::string::String;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use byteorder::{ByteOrder, LittleEndian};
+    use spin::Mutex;
+
+    use cinea_os_sysapi::fs::FileError;
+    use cinea_os_sysapi::time::{Date, DateTime, Time};
+
+    use crate::syskrnl::fs::block::BlockDeviceReader;
+    use crate::syskrnl::fs::cache;
+    use crate::syskrnl::fs::vfs::FileSystem;
+    use crate::syskrnl::io::block::RamDisk;
+
+    use super::{datetime, timestamp, Ext2FileSystem};
+    use super::{SB_BLOCKS_COUNT, SB_BLOCKS_PER_GROUP, SB_FREE_BLOCKS, SB_INODES_PER_GROUP, SB_LOG_BLOCK_SIZE, SUPERBLOCK_OFFSET};
+
+    /// `assets/ext2_test.py`用mke2fs生成的256KB镜像：1KB的块，32个inode，根目录下有内容为`hello ext2`的`hello.txt`
+    const IMAGE: &[u8] = include_bytes!("../../../assets/ext2_test.img");
+
+    fn mount(image: Vec<u8>) -> Result<Ext2FileSystem<BlockDeviceReader>, FileError> {
+        let id = cache::register(Box::new(RamDisk::new(Arc::new(Mutex::new(image)))));
+        Ext2FileSystem::new(BlockDeviceReader::new(id))
+    }
+
+    fn free_blocks(fs: &Ext2FileSystem<BlockDeviceReader>) -> u32 {
+        fs.inner.lock().sb(SB_FREE_BLOCKS)
+    }
+
+    fn names(fs: &Ext2FileSystem<BlockDeviceReader>, path: &str) -> Vec<String> {
+        let mut names: Vec<String> = fs.list(path).unwrap().iter().map(|x| String::from(x.metadata().unwrap().file_name())).collect();
+        names.sort();
+        names
+    }
+
+    #[test_case]
+    fn test_ext2_timestamp() {
+        let epoch = DateTime::new(Date::new(1970, 1, 1), Time::new(0, 0, 0, 0));
+        assert_eq!(timestamp(epoch), 0);
+        assert_eq!(datetime(0), epoch);
+
+        // 2024-02-29 12:34:56 UTC
+        let leap = DateTime::new(Date::new(2024, 2, 29), Time::new(12, 34, 56, 0));
+        assert_eq!(timestamp(leap), 1709210096);
+        assert_eq!(datetime(1709210096), leap);
+        println!("[ok]  FileSystem ext2 timestamps")
+    }
+
+    #[test_case]
+    fn test_ext2_read_write() {
+        let fs = mount(IMAGE.to_vec()).unwrap();
+        let mut buf = [0u8; 32];
+        assert_eq!(fs.read("/hello.txt", 0, &mut buf).unwrap(), 10);
+        assert_eq!(&buf[..10], b"hello ext2");
+
+        fs.create("/a.txt").unwrap();
+        assert_eq!(fs.write("/a.txt", 0, b"first").unwrap(), 5);
+        // 写入文件末尾之后，中间留下空洞
+        fs.write("/a.txt", 3000, b"tail").unwrap();
+        assert_eq!(fs.metadata("/a.txt").unwrap().len(), 3004);
+        let mut buf = vec![0u8; 3004];
+        assert_eq!(fs.read("/a.txt", 0, &mut buf).unwrap(), 3004);
+        assert_eq!(&buf[..5], b"first");
+        assert!(buf[5..3000].iter().all(|x| *x == 0));
+        assert_eq!(&buf[3000..], b"tail");
+
+        // 超过12块，用到一级间接块
+        let free = free_blocks(&fs);
+        let data: Vec<u8> = (0..20 * 1024).map(|x| (x % 251) as u8).collect();
+        fs.create("/big").unwrap();
+        fs.write("/big", 0, &data).unwrap();
+        assert_eq!(free_blocks(&fs), free - 21);
+        let mut buf = vec![0u8; data.len()];
+        assert_eq!(fs.read("/big", 0, &mut buf).unwrap(), data.len());
+        assert_eq!(buf, data);
+
+        // 截断到直接块以内时释放间接块，截断部分以后读到0
+        fs.truncate("/big", 1500).unwrap();
+        assert_eq!(free_blocks(&fs), free - 2);
+        fs.truncate("/big", 4096).unwrap();
+        let mut buf = vec![0u8; 4096];
+        fs.read("/big", 0, &mut buf).unwrap();
+        assert_eq!(&buf[..1500], &data[..1500]);
+        assert!(buf[1500..].iter().all(|x| *x == 0));
+        fs.truncate("/big", 0).unwrap();
+        assert_eq!(free_blocks(&fs), free);
+
+        assert!(matches!(fs.write("/", 0, b"x"), Err(FileError::NotAFileError)));
+        assert!(matches!(fs.read("/missing", 0, &mut buf), Err(FileError::NotFoundError)));
+        println!("[ok]  FileSystem ext2 read, write and truncate")
+    }
+
+    #[test_case]
+    fn test_ext2_directories() {
+        let fs = mount(IMAGE.to_vec()).unwrap();
+        fs.create_dir("/docs").unwrap();
+        fs.create_dir("/docs/sub").unwrap();
+        fs.create("/docs/note").unwrap();
+        fs.write("/docs/note", 0, b"note").unwrap();
+        assert!(fs.metadata("/docs/sub").unwrap().is_dir());
+        assert_eq!(names(&fs, "/docs"), ["note", "sub"]);
+
+        // 同一目录内改名，再移动到另一个目录
+        fs.rename("/docs/note", "/docs/memo").unwrap();
+        fs.rename("/docs/memo", "/memo").unwrap();
+        assert_eq!(names(&fs, "/docs"), ["sub"]);
+        let mut buf = [0u8; 4];
+        fs.read("/memo", 0, &mut buf).unwrap();
+        assert_eq!(&buf, b"note");
+
+        // 移动目录后`..`指向新的父目录
+        fs.rename("/docs/sub", "/sub").unwrap();
+        fs.create("/sub/../top").unwrap();
+        assert!(fs.metadata("/top").is_ok());
+        assert!(matches!(fs.rename("/memo", "/hello.txt"), Err(FileError::AlreadyExistsError)));
+        assert!(matches!(fs.rename("/docs", "/docs/inner"), Err(FileError::BadRelatePathError)));
+
+        // 长文件名很快填满一个目录块，之后追加新块
+        let long_name = |i: usize| format!("/docs/{:080}", i);
+        for i in 0..12 {
+            fs.create(long_name(i).as_str()).unwrap();
+        }
+        {
+            let mut inner = fs.inner.lock();
+            let ino = inner.lookup("/docs", true).unwrap();
+            assert_eq!(inner.read_inode(ino).unwrap().size(), 2048);
+        }
+        assert_eq!(fs.list("/docs").unwrap().len(), 12);
+        assert!(fs.metadata(long_name(11).as_str()).is_ok());
+        println!("[ok]  FileSystem ext2 directories and rename")
+    }
+
+    #[test_case]
+    fn test_ext2_bad_superblock() {
+        let corrupt = |offset: usize, value: u32| {
+            let mut image = IMAGE.to_vec();
+            LittleEndian::write_u32(&mut image[SUPERBLOCK_OFFSET as usize + offset..], value);
+            mount(image)
+        };
+        assert!(matches!(corrupt(SB_BLOCKS_PER_GROUP, 0), Err(FileError::UnsupportedError)));
+        assert!(matches!(corrupt(SB_LOG_BLOCK_SIZE, 40), Err(FileError::UnsupportedError)));
+        assert!(matches!(corrupt(SB_INODES_PER_GROUP, 0), Err(FileError::UnsupportedError)));
+        assert!(matches!(corrupt(SB_BLOCKS_COUNT, 0), Err(FileError::UnsupportedError)));
+        // 文件系统比磁盘大
+        assert!(matches!(corrupt(SB_BLOCKS_COUNT, 4096), Err(FileError::UnsupportedError)));
+        println!("[ok]  FileSystem ext2 superblock validation")
+    }
+}
@@ -0,0 +1,222 @@
+//! 本文件提供对fatfs的封装
+
+use alloc::vec::Vec;
+
+use fatfs::{DirEntry, Read, Seek, SeekFrom, Write};
+
+use cinea_os_sysapi::fs as fsapi;
+use cinea_os_sysapi::fs::{dirname, filename, path_combine, FileEntry, FileError, Metadata};
+use fsapi::FileError::{NotAFileError, NotFoundError, OSError, RootDirError};
+
+use crate::syskrnl::sync::Mutex;
+
+use super::oem::Cp437Converter;
+use super::time::{now, CosTimeProvider};
+use super::vfs::FileSystem;
+
+/// 挂载在VFS上的FAT文件系统
+pub struct FatFileSystem<IO: fatfs::ReadWriteSeek> {
+    inner: Mutex<fatfs::FileSystem<IO, CosTimeProvider, Cp437Converter>>,
+}
+
+impl<IO: fatfs::ReadWriteSeek> FatFileSystem<IO> {
+    pub fn new(disk: IO) -> Result<Self, FileError> {
+        let option = fatfs::FsOptions::new().oem_cp_converter(Cp437Converter).time_provider(CosTimeProvider);
+        match fatfs::FileSystem::new(disk, option) {
+            Ok(fs) => Ok(Self { inner: Mutex::new(fs) }),
+            Err(_) => Err(FileError::DeviceIOError),
+        }
+    }
+}
+
+fn seekpath<'a, IO, TP, OCC>(path: &str, root_dir: fatfs::Dir<'a, IO, TP, OCC>) -> Result<DirEntry<'a, IO, TP, OCC>, FileError>
+where
+    IO: fatfs::ReadWriteSeek,
+    TP: fatfs::TimeProvider,
+    OCC: fatfs::OemCpConverter,
+{
+    // Split the path
+    let dirname = dirname(path);
+    let filename = filename(path);
+    let mut spilted_path: Vec<_> = dirname.split('/').filter(|x| x.len() > 0).collect();
+    fsapi::process_relative_path(&mut spilted_path)?;
+
+    let mut dir = root_dir;
+
+    for next in spilted_path {
+        if let Ok(next_dir) = dir.open_dir(next) {
+            dir = next_dir;
+        } else {
+            return Err(NotFoundError);
+        }
+    }
+
+    if filename.len() == 0 {
+        return Err(RootDirError);
+    }
+
+    if let Some(target) = dir.iter().find(|x| {
+        if let Ok(x) = x {
+            // debugln!("fn:{} ?= {}",x.file_name(), filename);
+            x.file_name() == filename
+        } else {
+            false
+        }
+    }) {
+        Ok(target.unwrap())
+    } else {
+        Err(NotFoundError)
+    }
+}
+
+impl<IO: fatfs::ReadWriteSeek + Send> FileSystem for FatFileSystem<IO> {
+    fn metadata(&self, path: &str) -> Result<Metadata, FileError> {
+        if path == "/" {
+            return Ok(Metadata::new(path, true, 0, now()));
+        }
+        let lock = self.inner.lock();
+        let entry = seekpath(path, lock.root_dir())?;
+        Ok(Metadata::from_dir_entry(entry, path))
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<FileEntry>, FileError> {
+        self.read_dir(path, 0, usize::MAX)
+    }
+
+    fn read_dir(&self, path: &str, start: usize, count: usize) -> Result<Vec<FileEntry>, FileError> {
+        let lock = self.inner.lock();
+        let dir = if path == "/" {
+            lock.root_dir()
+        } else {
+            let entry = seekpath(path, lock.root_dir())?;
+            if !entry.is_dir() {
+                return Err(FileError::NotADirError);
+            }
+            entry.to_dir()
+        };
+
+        let result: Vec<FileEntry> = dir
+            .iter()
+            .filter(|dir_entry| dir_entry.is_ok())
+            .skip(start)
+            .take(count)
+            .map(|dir_entry| {
+                let dir_entry = dir_entry.unwrap();
+                let new_path = path_combine(path, dir_entry.file_name().as_str());
+                if dir_entry.is_dir() {
+                    FileEntry::Dir(Metadata::from_dir_entry(dir_entry, new_path.as_str()))
+                } else {
+                    FileEntry::File(Metadata::from_dir_entry(dir_entry, new_path.as_str()))
+                }
+            })
+            .collect();
+        Ok(result)
+    }
+
+    fn read(&self, path: &str, offset: u64, store: &mut [u8]) -> Result<usize, FileError> {
+        let lock = self.inner.lock();
+        let file = seekpath(path, lock.root_dir())?;
+        if !file.is_file() {
+            return Err(NotAFileError);
+        }
+        let mut file = file.to_file();
+
+        if file.seek(SeekFrom::Start(offset)).is_err() {
+            return Err(OSError);
+        }
+        // 读到缓冲区满或文件末尾为止
+        let mut pos = 0usize;
+        while pos < store.len() {
+            match file.read(&mut store[pos..]) {
+                Ok(0) => break,
+                Ok(len) => pos += len,
+                Err(_) => return Err(OSError),
+            }
+        }
+        Ok(pos)
+    }
+
+    fn write(&self, path: &str, offset: u64, buf: &[u8]) -> Result<usize, FileError> {
+        let lock = self.inner.lock();
+        let file = seekpath(path, lock.root_dir())?;
+        if !file.is_file() {
+            return Err(NotAFileError);
+        }
+        let mut file = file.to_file();
+
+        // fatfs的seek不会越过文件末尾，越过的部分以0填充
+        let end = file.seek(SeekFrom::Start(offset)).map_err(|_| OSError)?;
+        if end < offset && write_zeros(&mut file, offset - end).is_err() {
+            return Err(OSError);
+        }
+        match file.write_all(buf) {
+            Err(_) => Err(OSError),
+            Ok(()) => Ok(buf.len()),
+        }
+    }
+
+    fn truncate(&self, path: &str, len: u64) -> Result<(), FileError> {
+        let lock = self.inner.lock();
+        let file = seekpath(path, lock.root_dir())?;
+        if !file.is_file() {
+            return Err(NotAFileError);
+        }
+        let mut file = file.to_file();
+
+        let end = file.seek(SeekFrom::Start(len)).map_err(|_| OSError)?;
+        let result = if end < len { write_zeros(&mut file, len - end) } else { file.truncate() };
+        result.map_err(|_| OSError)
+    }
+
+    fn create(&self, path: &str) -> Result<(), FileError> {
+        let lock = self.inner.lock();
+        match lock.root_dir().create_file(fat_path(path)?) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(fat_error(e)),
+        }
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), FileError> {
+        let lock = self.inner.lock();
+        match lock.root_dir().create_dir(fat_path(path)?) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(fat_error(e)),
+        }
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), FileError> {
+        let lock = self.inner.lock();
+        let root_dir = lock.root_dir();
+        root_dir.rename(fat_path(from)?, &root_dir, fat_path(to)?).map_err(fat_error)
+    }
+}
+
+/// 挂载点内路径转fatfs路径（相对于根目录），根目录本身不能作为操作对象
+fn fat_path(path: &str) -> Result<&str, FileError> {
+    match path.trim_start_matches('/') {
+        "" => Err(RootDirError),
+        path => Ok(path),
+    }
+}
+
+/// fatfs错误转文件错误
+fn fat_error<T>(e: fatfs::Error<T>) -> FileError {
+    match e {
+        fatfs::Error::NotFound => NotFoundError,
+        fatfs::Error::AlreadyExists => FileError::AlreadyExistsError,
+        fatfs::Error::InvalidInput => NotAFileError,
+        fatfs::Error::Io(_) => FileError::DeviceIOError,
+        _ => OSError,
+    }
+}
+
+/// 在文件当前位置写入`count`个0
+fn write_zeros<T: Write>(file: &mut T, mut count: u64) -> Result<(), T::Error> {
+    let zeros = [0u8; 512];
+    while count > 0 {
+        let len = count.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..len])?;
+        count -= len as u64;
+    }
+    Ok(())
+}
@@ -1,8 +1,157 @@
-mod ahci;
-mod ata;
+pub mod block;
+pub mod cache;
 pub mod device;
+mod ext2;
+mod fat;
 mod oem;
+pub mod partition;
+mod procfs;
+mod ramfs;
 mod time;
+pub mod vfs;
 mod wrap;
 
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+
+use cinea_os_sysapi::fs::FileError;
+
 pub use wrap::*;
+
+use block::{BlockDeviceFile, BlockDeviceReader};
+use device::DeviceFileSystem;
+use ext2::Ext2FileSystem;
+use fat::FatFileSystem;
+use partition::{Partition, SharedDisk};
+use procfs::ProcFileSystem;
+use ramfs::RamFileSystem;
+
+use crate::syskrnl::io::ahci::{self, AhciDrive};
+use crate::syskrnl::io::ata;
+use crate::syskrnl::io::block::BlockDevice;
+use crate::syskrnl::io::virtio_blk;
+use crate::syskrnl::sync::Mutex;
+
+/// 挂载文件系统：数据盘挂载到`/`，设备表挂载到`/dev`，内存文件系统挂载到`/tmp`，进程信息挂载到`/proc`
+///
+/// AHCI磁盘按端口顺序注册为`/dev/sda`、`/dev/sdb`……，virtio磁盘注册为`/dev/vda`、`/dev/vdb`……，
+/// IDE磁盘注册为`/dev/hda`、`/dev/hdb`……。
+/// 数据盘是第一块能挂载的磁盘，依次尝试AHCI、virtio和IDE；IDE上通常还有启动盘，它没有可挂载的文件系统，会被跳过。
+/// 没有数据盘时用内存文件系统代替`/`，系统照常启动。
+pub fn init() {
+    device::init();
+    let mut root_mounted = false;
+
+    let drives = ahci::ports().into_iter().filter_map(|port_no| {
+        let drive = AhciDrive::open(port_no);
+        if drive.is_none() {
+            println!("AHCI端口{}上的磁盘无法打开", port_no);
+        }
+        drive
+    });
+    for (i, drive) in drives.enumerate() {
+        root_mounted |= add_disk(disk_name("sd", i).as_str(), drive, !root_mounted);
+    }
+
+    for (i, drive) in virtio_blk::list().into_iter().enumerate() {
+        root_mounted |= add_disk(disk_name("vd", i).as_str(), drive, !root_mounted);
+    }
+
+    for (i, drive) in ata::list().into_iter().enumerate() {
+        root_mounted |= add_disk(disk_name("hd", i).as_str(), drive, !root_mounted);
+    }
+
+    if !root_mounted {
+        println!("没有找到数据盘，根目录使用内存文件系统");
+        vfs::mount("/", Arc::new(RamFileSystem::new())).unwrap();
+    }
+    vfs::mount("/dev", Arc::new(DeviceFileSystem)).unwrap();
+    vfs::mount("/tmp", Arc::new(RamFileSystem::new())).unwrap();
+    vfs::mount("/proc", Arc::new(ProcFileSystem)).unwrap();
+}
+
+/// 把磁盘注册为`/dev/<name>`，`try_mount`时尝试作为数据盘挂载，返回是否挂载成功
+fn add_disk<D: BlockDevice + 'static>(name: &str, drive: D, try_mount: bool) -> bool {
+    let disk = Arc::new(Mutex::new(BlockDeviceReader::new(cache::register(Box::new(drive)))));
+    device::register_device(name, Box::new(BlockDeviceFile::new(disk.clone()))).unwrap();
+    if !try_mount {
+        return false;
+    }
+    match mount_data_disk(disk) {
+        Ok(()) => {
+            println!("数据盘：/dev/{}", name);
+            true
+        }
+        Err(err) => {
+            debugln!("/dev/{} is not a data disk: {:?}", name, err);
+            false
+        }
+    }
+}
+
+/// 挂载数据盘
+///
+/// 有分区表时，第一个能挂载的分区作为`/`，其余分区挂载到`/mnt/part<分区号>`；没有分区表时整块磁盘作为`/`。
+/// 根目录下没有`/mnt`时在那里挂载一个内存文件系统，这样`/mnt`能被列出，其中的挂载点也能看到。
+fn mount_data_disk(disk: SharedDisk<BlockDeviceReader>) -> Result<(), FileError> {
+    let partitions = partition::read_partitions(&mut *disk.lock())?;
+    if partitions.is_empty() {
+        let volume = Partition::whole(disk).map_err(|_| FileError::DeviceIOError)?;
+        return mount_volume("/", volume);
+    }
+
+    let mut root_mounted = false;
+    for info in partitions {
+        if root_mounted && vfs::metadata("/mnt").is_err() {
+            vfs::mount("/mnt", Arc::new(RamFileSystem::new()))?;
+        }
+        let point = if root_mounted { format!("/mnt/part{}", info.number) } else { String::from("/") };
+        match mount_volume(point.as_str(), Partition::new(disk.clone(), &info)) {
+            Ok(()) => root_mounted = true,
+            Err(err) => println!("数据盘分区{}挂载失败：{:?}", info.number, err),
+        }
+    }
+    if root_mounted {
+        Ok(())
+    } else {
+        Err(FileError::NotFoundError)
+    }
+}
+
+/// 第`index`块磁盘的设备名：`<prefix>a`……`<prefix>z`，之后是`<prefix>aa`、`<prefix>ab`……
+fn disk_name(prefix: &str, index: usize) -> String {
+    let mut suffix = String::new();
+    let mut index = index + 1;
+    while index > 0 {
+        index -= 1;
+        suffix.insert(0, (b'a' + (index % 26) as u8) as char);
+        index /= 26;
+    }
+    format!("{}{}", prefix, suffix)
+}
+
+/// 探测卷上的文件系统并挂载：是ext2时使用ext2驱动，否则按FAT挂载
+fn mount_volume<IO: fatfs::ReadWriteSeek + Send + 'static>(point: &str, mut volume: IO) -> Result<(), FileError> {
+    if ext2::probe(&mut volume) {
+        vfs::mount(point, Arc::new(Ext2FileSystem::new(volume)?))
+    } else {
+        vfs::mount(point, Arc::new(FatFileSystem::new(volume)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disk_name;
+
+    #[test_case]
+    fn test_disk_name() {
+        assert_eq!(disk_name("sd", 0), "sda");
+        assert_eq!(disk_name("sd", 1), "sdb");
+        assert_eq!(disk_name("sd", 25), "sdz");
+        assert_eq!(disk_name("sd", 26), "sdaa");
+        assert_eq!(disk_name("hd", 27), "hdab");
+        println!("[ok]  FileSystem disk names")
+    }
+}
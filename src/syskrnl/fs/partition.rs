@@ -0,0 +1,437 @@
+//! 分区表
+//!
+//! 支持MBR（包括扩展分区中的逻辑分区）和GPT。每个分区通过[`Partition`]暴露为只能访问分区范围的块设备，
+//! 可以像整块磁盘一样交给FAT或ext2驱动挂载；同一块磁盘上的各个分区通过[`SharedDisk`]共享底层设备。
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use byteorder::{ByteOrder, LittleEndian};
+use fatfs::{IoBase, Read, Seek, SeekFrom, Write};
+
+use cinea_os_sysapi::fs::FileError;
+
+use crate::syskrnl::sync::Mutex;
+
+pub const SECTOR_SIZE: u64 = 512;
+
+const MBR_ENTRIES_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+/// 逻辑分区链的最大长度，防止损坏的EBR形成环
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// GPT分区项的最小长度
+const GPT_MIN_ENTRY_SIZE: usize = 128;
+/// 分区项数组的最大长度，标准的GPT是128项、每项128字节
+const GPT_MAX_ENTRIES_BYTES: usize = 128 * 128;
+
+/// 分区类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// MBR分区的类型字节
+    Mbr(u8),
+    /// GPT分区的类型GUID
+    Gpt([u8; 16]),
+}
+
+/// 分区表中的一个分区
+#[derive(Debug, Clone)]
+pub struct PartitionInfo {
+    /// 分区号，从1开始；MBR的逻辑分区从5开始
+    pub number: usize,
+    pub start_lba: u64,
+    pub sectors: u64,
+    pub kind: PartitionKind,
+}
+
+/// 被多个分区共享的磁盘
+pub type SharedDisk<IO> = Arc<Mutex<IO>>;
+
+/// 磁盘上的一个分区，所有位置都相对于分区起点，不能越过分区末尾
+pub struct Partition<IO: fatfs::ReadWriteSeek> {
+    disk: SharedDisk<IO>,
+    start: u64,
+    len: u64,
+    position: u64,
+}
+
+impl<IO: fatfs::ReadWriteSeek> Partition<IO> {
+    pub fn new(disk: SharedDisk<IO>, info: &PartitionInfo) -> Self {
+        Self {
+            disk,
+            start: info.start_lba * SECTOR_SIZE,
+            len: info.sectors * SECTOR_SIZE,
+            position: 0,
+        }
+    }
+
+    /// 把整块磁盘作为一个分区，用于没有分区表的磁盘
+    pub fn whole(disk: SharedDisk<IO>) -> Result<Self, FileError> {
+        let len = disk.lock().seek(SeekFrom::End(0)).map_err(|_| FileError::DeviceIOError)?;
+        Ok(Self { disk, start: 0, len, position: 0 })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+}
+
+impl<IO: fatfs::ReadWriteSeek> IoBase for Partition<IO> {
+    type Error = ();
+}
+
+impl<IO: fatfs::ReadWriteSeek> Read for Partition<IO> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let len = (buf.len() as u64).min(self.len - self.position) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        let mut disk = self.disk.lock();
+        disk.seek(SeekFrom::Start(self.start + self.position)).map_err(|_| ())?;
+        let read = disk.read(&mut buf[..len]).map_err(|_| ())?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<IO: fatfs::ReadWriteSeek> Write for Partition<IO> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let len = (buf.len() as u64).min(self.len - self.position) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        let mut disk = self.disk.lock();
+        disk.seek(SeekFrom::Start(self.start + self.position)).map_err(|_| ())?;
+        let written = disk.write(&buf[..len]).map_err(|_| ())?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.disk.lock().flush().map_err(|_| ())
+    }
+}
+
+impl<IO: fatfs::ReadWriteSeek> Seek for Partition<IO> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let position = match pos {
+            SeekFrom::Start(pos) => pos as i64,
+            SeekFrom::End(pos) => self.len as i64 + pos,
+            SeekFrom::Current(pos) => self.position as i64 + pos,
+        };
+        if position < 0 || position as u64 > self.len {
+            return Err(());
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+fn read_sectors<IO: fatfs::ReadWriteSeek>(disk: &mut IO, lba: u64, buf: &mut [u8]) -> Result<(), FileError> {
+    disk.seek(SeekFrom::Start(lba * SECTOR_SIZE)).map_err(|_| FileError::DeviceIOError)?;
+    disk.read_exact(buf).map_err(|_| FileError::DeviceIOError)
+}
+
+fn disk_sectors<IO: fatfs::ReadWriteSeek>(disk: &mut IO) -> Result<u64, FileError> {
+    Ok(disk.seek(SeekFrom::End(0)).map_err(|_| FileError::DeviceIOError)? / SECTOR_SIZE)
+}
+
+/// 分区项描述的扇区是否非空且都在磁盘上
+fn in_disk(start_lba: u64, sectors: u64, disk_sectors: u64) -> bool {
+    sectors != 0 && start_lba.checked_add(sectors).map_or(false, |end| end <= disk_sectors)
+}
+
+/// 判断扇区是否是有效的MBR
+///
+/// FAT卷的引导扇区同样以`0x55AA`结尾，因此还要排除带有FAT标识的扇区，并检查每个分区项的引导标志。
+fn is_mbr(sector: &[u8]) -> bool {
+    if sector[510] != 0x55 || sector[511] != 0xAA {
+        return false;
+    }
+    if &sector[54..57] == b"FAT" || &sector[82..87] == b"FAT32" {
+        return false;
+    }
+    let entries = &sector[MBR_ENTRIES_OFFSET..MBR_ENTRIES_OFFSET + 4 * MBR_ENTRY_SIZE];
+    entries.chunks(MBR_ENTRY_SIZE).all(|x| x[0] == 0x00 || x[0] == 0x80) && entries.chunks(MBR_ENTRY_SIZE).any(|x| x[4] != 0)
+}
+
+/// 读取磁盘的分区表，没有分区表时返回空表
+pub fn read_partitions<IO: fatfs::ReadWriteSeek>(disk: &mut IO) -> Result<Vec<PartitionInfo>, FileError> {
+    let mut mbr = [0u8; SECTOR_SIZE as usize];
+    read_sectors(disk, 0, &mut mbr)?;
+    if !is_mbr(&mbr) {
+        return Ok(Vec::new());
+    }
+    let disk_sectors = disk_sectors(disk)?;
+
+    let mut result = Vec::new();
+    for (i, entry) in mbr[MBR_ENTRIES_OFFSET..MBR_ENTRIES_OFFSET + 4 * MBR_ENTRY_SIZE].chunks(MBR_ENTRY_SIZE).enumerate() {
+        let kind = entry[4];
+        let start_lba = LittleEndian::read_u32(&entry[8..]) as u64;
+        let sectors = LittleEndian::read_u32(&entry[12..]) as u64;
+        match kind {
+            0 => {}
+            // 保护性MBR，真正的分区表在GPT里
+            MBR_TYPE_GPT_PROTECTIVE => return read_gpt(disk),
+            // 跳过损坏的分区项
+            _ if !in_disk(start_lba, sectors, disk_sectors) => {}
+            kind if MBR_TYPES_EXTENDED.contains(&kind) => read_logical(disk, start_lba, disk_sectors, &mut result)?,
+            kind => result.push(PartitionInfo {
+                number: i + 1,
+                start_lba,
+                sectors,
+                kind: PartitionKind::Mbr(kind),
+            }),
+        }
+    }
+    Ok(result)
+}
+
+/// 沿扩展分区中的EBR链读取逻辑分区
+fn read_logical<IO: fatfs::ReadWriteSeek>(
+    disk: &mut IO,
+    extended_lba: u64,
+    disk_sectors: u64,
+    result: &mut Vec<PartitionInfo>,
+) -> Result<(), FileError> {
+    let mut ebr = [0u8; SECTOR_SIZE as usize];
+    let mut ebr_lba = extended_lba;
+    let mut number = 5;
+    for _ in 0..MAX_LOGICAL_PARTITIONS {
+        read_sectors(disk, ebr_lba, &mut ebr)?;
+        if ebr[510] != 0x55 || ebr[511] != 0xAA {
+            break;
+        }
+        // 第一项是逻辑分区，起点相对于本EBR；第二项指向下一个EBR，起点相对于扩展分区
+        let entry = &ebr[MBR_ENTRIES_OFFSET..];
+        let start_lba = ebr_lba + LittleEndian::read_u32(&entry[8..]) as u64;
+        let sectors = LittleEndian::read_u32(&entry[12..]) as u64;
+        if entry[4] != 0 {
+            // 损坏的逻辑分区不挂载，但仍占用分区号
+            if in_disk(start_lba, sectors, disk_sectors) {
+                result.push(PartitionInfo {
+                    number,
+                    start_lba,
+                    sectors,
+                    kind: PartitionKind::Mbr(entry[4]),
+                });
+            }
+            number += 1;
+        }
+        let next = &ebr[MBR_ENTRIES_OFFSET + MBR_ENTRY_SIZE..];
+        let next_start = LittleEndian::read_u32(&next[8..]) as u64;
+        if next[4] == 0 || next_start == 0 || extended_lba + next_start >= disk_sectors {
+            break;
+        }
+        ebr_lba = extended_lba + next_start;
+    }
+    Ok(())
+}
+
+fn read_gpt<IO: fatfs::ReadWriteSeek>(disk: &mut IO) -> Result<Vec<PartitionInfo>, FileError> {
+    let mut header = [0u8; SECTOR_SIZE as usize];
+    read_sectors(disk, 1, &mut header)?;
+    if &header[0..8] != GPT_SIGNATURE {
+        return Err(FileError::DeviceIOError);
+    }
+    let entries_lba = LittleEndian::read_u64(&header[72..]);
+    let entry_count = LittleEndian::read_u32(&header[80..]) as usize;
+    let entry_size = LittleEndian::read_u32(&header[84..]) as usize;
+    // 分区表来自磁盘，不能相信其中的长度
+    let bytes = match entry_count.checked_mul(entry_size) {
+        Some(bytes) if entry_size >= GPT_MIN_ENTRY_SIZE && bytes <= GPT_MAX_ENTRIES_BYTES => bytes,
+        _ => return Err(FileError::DeviceIOError),
+    };
+    let disk_sectors = disk_sectors(disk)?;
+
+    let mut entries = vec![0u8; (bytes + SECTOR_SIZE as usize - 1) / SECTOR_SIZE as usize * SECTOR_SIZE as usize];
+    read_sectors(disk, entries_lba, &mut entries)?;
+
+    let mut result = Vec::new();
+    for (i, entry) in entries[..bytes].chunks(entry_size).enumerate() {
+        let mut kind = [0u8; 16];
+        kind.copy_from_slice(&entry[0..16]);
+        // 类型GUID全0表示未使用
+        if kind == [0u8; 16] {
+            continue;
+        }
+        let first = LittleEndian::read_u64(&entry[32..]);
+        let last = LittleEndian::read_u64(&entry[40..]);
+        // 跳过损坏的分区项
+        if last < first || last >= disk_sectors {
+            continue;
+        }
+        result.push(PartitionInfo {
+            number: i + 1,
+            start_lba: first,
+            sectors: last + 1 - first,
+            kind: PartitionKind::Gpt(kind),
+        });
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use byteorder::{ByteOrder, LittleEndian};
+    use fatfs::{IoBase, Read, Seek, SeekFrom, Write};
+
+    use cinea_os_sysapi::fs::FileError;
+
+    use crate::syskrnl::sync::Mutex;
+
+    use super::{read_partitions, Partition, PartitionKind, SECTOR_SIZE};
+
+    /// 内存中的磁盘
+    struct MemDisk {
+        data: Vec<u8>,
+        position: usize,
+    }
+
+    impl IoBase for MemDisk {
+        type Error = ();
+    }
+
+    impl Read for MemDisk {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+            let len = buf.len().min(self.data.len() - self.position);
+            buf[..len].copy_from_slice(&self.data[self.position..self.position + len]);
+            self.position += len;
+            Ok(len)
+        }
+    }
+
+    impl Write for MemDisk {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+            let len = buf.len().min(self.data.len() - self.position);
+            self.data[self.position..self.position + len].copy_from_slice(&buf[..len]);
+            self.position += len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for MemDisk {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64, ()> {
+            self.position = match pos {
+                SeekFrom::Start(pos) => pos as usize,
+                SeekFrom::End(pos) => (self.data.len() as i64 + pos) as usize,
+                SeekFrom::Current(pos) => (self.position as i64 + pos) as usize,
+            };
+            Ok(self.position as u64)
+        }
+    }
+
+    fn mbr_entry(data: &mut [u8], index: usize, kind: u8, start: u32, sectors: u32) {
+        let entry = &mut data[446 + index * 16..];
+        entry[4] = kind;
+        LittleEndian::write_u32(&mut entry[8..], start);
+        LittleEndian::write_u32(&mut entry[12..], sectors);
+    }
+
+    #[test_case]
+    fn test_mbr_partitions() {
+        let mut data = vec![0u8; 64 * SECTOR_SIZE as usize];
+        mbr_entry(&mut data, 0, 0x0C, 2, 10);
+        mbr_entry(&mut data, 1, 0x05, 20, 40);
+        data[510] = 0x55;
+        data[511] = 0xAA;
+        // 扩展分区中的一个逻辑分区
+        let ebr = 20 * SECTOR_SIZE as usize;
+        mbr_entry(&mut data[ebr..], 0, 0x83, 1, 8);
+        mbr_entry(&mut data[ebr..], 1, 0x05, 10, 10);
+        data[ebr + 510] = 0x55;
+        data[ebr + 511] = 0xAA;
+        // 损坏的分区项：长度为0、越过磁盘末尾的主分区和逻辑分区
+        mbr_entry(&mut data, 2, 0x83, 40, 0);
+        mbr_entry(&mut data, 3, 0x83, 60, 10);
+        let ebr = 30 * SECTOR_SIZE as usize;
+        mbr_entry(&mut data[ebr..], 0, 0x83, 1, 100);
+        data[ebr + 510] = 0x55;
+        data[ebr + 511] = 0xAA;
+
+        let mut disk = MemDisk { data, position: 0 };
+        let partitions = read_partitions(&mut disk).unwrap();
+        assert_eq!(partitions.len(), 2);
+        assert_eq!((partitions[0].number, partitions[0].start_lba, partitions[0].sectors), (1, 2, 10));
+        assert_eq!(partitions[0].kind, PartitionKind::Mbr(0x0C));
+        assert_eq!((partitions[1].number, partitions[1].start_lba, partitions[1].sectors), (5, 21, 8));
+
+        // 没有分区表
+        let mut disk = MemDisk { data: vec![0u8; 4 * SECTOR_SIZE as usize], position: 0 };
+        assert!(read_partitions(&mut disk).unwrap().is_empty());
+        println!("[ok]  FileSystem MBR partitions")
+    }
+
+    /// 保护性MBR加上有`entries`个128字节分区项的GPT，分区项从第2扇区开始
+    fn gpt_disk(entries: &[([u8; 16], u64, u64)]) -> Vec<u8> {
+        let mut data = vec![0u8; 64 * SECTOR_SIZE as usize];
+        mbr_entry(&mut data, 0, 0xEE, 1, 63);
+        data[510] = 0x55;
+        data[511] = 0xAA;
+        let header = SECTOR_SIZE as usize;
+        data[header..header + 8].copy_from_slice(b"EFI PART");
+        LittleEndian::write_u64(&mut data[header + 72..], 2);
+        LittleEndian::write_u32(&mut data[header + 80..], entries.len() as u32);
+        LittleEndian::write_u32(&mut data[header + 84..], 128);
+        for (i, (kind, first, last)) in entries.iter().enumerate() {
+            let entry = &mut data[2 * SECTOR_SIZE as usize + i * 128..];
+            entry[0..16].copy_from_slice(kind);
+            LittleEndian::write_u64(&mut entry[32..], *first);
+            LittleEndian::write_u64(&mut entry[40..], *last);
+        }
+        data
+    }
+
+    #[test_case]
+    fn test_gpt_partitions() {
+        let kind = [0xAFu8; 16];
+        // 第2项未使用，第3项结束在开始之前，第4项越过磁盘末尾
+        let data = gpt_disk(&[(kind, 34, 43), ([0u8; 16], 0, 0), (kind, 50, 40), (kind, 44, 64)]);
+        let mut disk = MemDisk { data, position: 0 };
+        let partitions = read_partitions(&mut disk).unwrap();
+        assert_eq!(partitions.len(), 1);
+        assert_eq!((partitions[0].number, partitions[0].start_lba, partitions[0].sectors), (1, 34, 10));
+        assert_eq!(partitions[0].kind, PartitionKind::Gpt(kind));
+
+        // 分区项长度和个数不合理的分区表
+        let header = SECTOR_SIZE as usize;
+        for (count, size) in [(4, 48), (4, 0x1000_0000), (u32::MAX, 128), (u32::MAX, u32::MAX)] {
+            let mut data = gpt_disk(&[(kind, 34, 43)]);
+            LittleEndian::write_u32(&mut data[header + 80..], count);
+            LittleEndian::write_u32(&mut data[header + 84..], size);
+            let mut disk = MemDisk { data, position: 0 };
+            assert!(matches!(read_partitions(&mut disk), Err(FileError::DeviceIOError)));
+        }
+        println!("[ok]  FileSystem GPT partitions")
+    }
+
+    #[test_case]
+    fn test_partition_bounds() {
+        let mut data = vec![0u8; 8 * SECTOR_SIZE as usize];
+        mbr_entry(&mut data, 0, 0x83, 2, 2);
+        data[510] = 0x55;
+        data[511] = 0xAA;
+        let disk = Arc::new(Mutex::new(MemDisk { data, position: 0 }));
+
+        let info = read_partitions(&mut *disk.lock()).unwrap().remove(0);
+        let mut partition = Partition::new(disk.clone(), &info);
+        assert_eq!(partition.len(), 2 * SECTOR_SIZE);
+        partition.seek(SeekFrom::End(-2)).unwrap();
+        // 写入被截断在分区末尾
+        assert_eq!(partition.write(b"abcd").unwrap(), 2);
+        assert_eq!(&disk.lock().data[4 * SECTOR_SIZE as usize - 2..4 * SECTOR_SIZE as usize + 2], b"ab\0\0");
+        partition.seek(SeekFrom::Start(3 * SECTOR_SIZE)).unwrap_err();
+        println!("[ok]  FileSystem partition bounds")
+    }
+}
@@ -0,0 +1,177 @@
+//! 进程信息文件系统，挂载在`/proc`
+//!
+//! 所有文件在读取时按需生成，内容为文本：
+//!
+//! - `/proc/<pid>/status`：进程号、父进程、工作目录、代码地址和堆使用情况
+//! - `/proc/<pid>/fd`：打开的文件句柄，每行为`句柄 模式 位置 路径`
+//! - `/proc/meminfo`：物理内存和帧分配器统计
+//! - `/proc/sched`：调度器状态
+//! - `/proc/uptime`：开机时间（秒）
+//! - `/proc/blockcache`：块缓存的命中、未命中次数和缓存块数
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use cinea_os_sysapi::fs::{path_combine, FileEntry, FileError, Metadata};
+
+use crate::syskrnl::proc::{self, ProcessInfo, SCHEDULER};
+use crate::syskrnl::{memory, time};
+
+use super::cache;
+use super::time::now;
+use super::vfs::FileSystem;
+
+const GLOBAL_FILES: [&str; 4] = ["meminfo", "sched", "uptime", "blockcache"];
+const PROCESS_FILES: [&str; 2] = ["status", "fd"];
+
+/// `/proc`中的节点
+enum ProcNode {
+    /// 目录，包含子项的（名字，是否目录）
+    Dir(Vec<(String, bool)>),
+    /// 文件及其内容
+    File(String),
+}
+
+pub struct ProcFileSystem;
+
+fn process(pid: &str) -> Option<ProcessInfo> {
+    let pid: usize = pid.parse().ok()?;
+    proc::processes().into_iter().find(|x| x.id == pid)
+}
+
+fn status(info: &ProcessInfo) -> String {
+    format!(
+        "Pid: {}\nParent: {}\nDir: {}\nUser: {}\nCodeAddr: {:#x}\nHeapSize: {}\nHeapAllocated: {}\n",
+        info.id,
+        info.parent,
+        info.dir,
+        info.user.as_deref().unwrap_or("-"),
+        info.code_addr,
+        info.heap_size,
+        info.heap_allocated
+    )
+}
+
+fn file_handles(info: &ProcessInfo) -> String {
+    info.file_handles
+        .iter()
+        .map(|handle| {
+            let mode = match (handle.readable(), handle.writable(), handle.append()) {
+                (_, _, true) => "a",
+                (true, true, _) => "rw",
+                (false, true, _) => "w",
+                _ => "r",
+            };
+            format!("{} {} {} {}\n", handle.id, mode, handle.pos, handle.path)
+        })
+        .collect()
+}
+
+fn meminfo() -> String {
+    format!(
+        "MemTotal: {} KB\nFramesUsable: {}\nFramesAllocated: {}\n",
+        memory::memory_size() >> 10,
+        memory::usable_frames(),
+        memory::allocated_frames()
+    )
+}
+
+fn blockcache() -> String {
+    let stats = cache::stats();
+    format!(
+        "Hits: {}\nMisses: {}\nWritebacks: {}\nCached: {}\nDirty: {}\n",
+        stats.hits, stats.misses, stats.writebacks, stats.cached, stats.dirty
+    )
+}
+
+/// 找到路径对应的节点并生成内容
+fn resolve(path: &str) -> Result<ProcNode, FileError> {
+    let parts: Vec<&str> = path.split('/').filter(|x| !x.is_empty()).collect();
+    match parts.as_slice() {
+        [] => {
+            let mut entries: Vec<(String, bool)> = proc::processes().iter().map(|x| (x.id.to_string(), true)).collect();
+            entries.extend(GLOBAL_FILES.iter().map(|x| (x.to_string(), false)));
+            Ok(ProcNode::Dir(entries))
+        }
+        ["meminfo"] => Ok(ProcNode::File(meminfo())),
+        ["sched"] => Ok(ProcNode::File(format!("{:#?}\n", SCHEDULER.lock()))),
+        ["uptime"] => Ok(ProcNode::File(format!("{:.3}\n", time::uptime()))),
+        ["blockcache"] => Ok(ProcNode::File(blockcache())),
+        [pid] => {
+            process(pid).ok_or(FileError::NotFoundError)?;
+            Ok(ProcNode::Dir(PROCESS_FILES.iter().map(|x| (x.to_string(), false)).collect()))
+        }
+        [pid, file] => {
+            let info = process(pid).ok_or(FileError::NotFoundError)?;
+            match *file {
+                "status" => Ok(ProcNode::File(status(&info))),
+                "fd" => Ok(ProcNode::File(file_handles(&info))),
+                _ => Err(FileError::NotFoundError),
+            }
+        }
+        _ => Err(FileError::NotFoundError),
+    }
+}
+
+impl FileSystem for ProcFileSystem {
+    fn metadata(&self, path: &str) -> Result<Metadata, FileError> {
+        match resolve(path)? {
+            ProcNode::Dir(_) => Ok(Metadata::new(path, true, 0, now())),
+            ProcNode::File(content) => Ok(Metadata::new(path, false, content.len() as u64, now())),
+        }
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<FileEntry>, FileError> {
+        match resolve(path)? {
+            ProcNode::File(_) => Err(FileError::NotADirError),
+            ProcNode::Dir(entries) => {
+                let time = now();
+                let result = entries
+                    .into_iter()
+                    .map(|(name, is_dir)| {
+                        let metadata = Metadata::new(path_combine(path, name.as_str()).as_str(), is_dir, 0, time);
+                        if is_dir {
+                            FileEntry::Dir(metadata)
+                        } else {
+                            FileEntry::File(metadata)
+                        }
+                    })
+                    .collect();
+                Ok(result)
+            }
+        }
+    }
+
+    fn read(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, FileError> {
+        let content = match resolve(path)? {
+            ProcNode::Dir(_) => return Err(FileError::NotAFileError),
+            ProcNode::File(content) => content,
+        };
+        let content = content.as_bytes();
+        let start = (offset as usize).min(content.len());
+        let len = buf.len().min(content.len() - start);
+        buf[..len].copy_from_slice(&content[start..start + len]);
+        Ok(len)
+    }
+
+    fn write(&self, _path: &str, _offset: u64, _buf: &[u8]) -> Result<usize, FileError> {
+        Err(FileError::UnsupportedError)
+    }
+
+    fn truncate(&self, _path: &str, _len: u64) -> Result<(), FileError> {
+        Err(FileError::UnsupportedError)
+    }
+
+    fn create(&self, _path: &str) -> Result<(), FileError> {
+        Err(FileError::UnsupportedError)
+    }
+
+    fn create_dir(&self, _path: &str) -> Result<(), FileError> {
+        Err(FileError::UnsupportedError)
+    }
+
+    fn rename(&self, _from: &str, _to: &str) -> Result<(), FileError> {
+        Err(FileError::UnsupportedError)
+    }
+}
@@ -0,0 +1,259 @@
+//! 内存文件系统，挂载在`/tmp`，用于临时数据和进程间传递文件
+//!
+//! 所有节点按挂载点内的绝对路径平铺在一张表里，目录的子项就是`dirname`等于它的节点。
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use spin::RwLock;
+
+use cinea_os_sysapi::fs::{dirname, FileEntry, FileError, Metadata};
+use cinea_os_sysapi::time::DateTime;
+
+use super::time::now;
+use super::vfs::FileSystem;
+
+/// 把文件内容改为`len`字节，扩展部分以0填充；内存不足时返回`NoSpaceError`，文件不变
+fn resize(data: &mut Vec<u8>, len: u64) -> Result<(), FileError> {
+    let len = usize::try_from(len).map_err(|_| FileError::NoSpaceError)?;
+    if len > data.len() {
+        data.try_reserve_exact(len - data.len()).map_err(|_| FileError::NoSpaceError)?;
+    }
+    data.resize(len, 0);
+    Ok(())
+}
+
+/// 内存文件系统的节点
+struct RamNode {
+    /// 文件内容，目录为`None`
+    data: Option<Vec<u8>>,
+    modified: DateTime,
+}
+
+impl RamNode {
+    fn dir() -> Self {
+        Self { data: None, modified: now() }
+    }
+
+    fn file() -> Self {
+        Self {
+            data: Some(Vec::new()),
+            modified: now(),
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.data.is_none()
+    }
+
+    fn metadata(&self, path: &str) -> Metadata {
+        let len = self.data.as_ref().map(|x| x.len()).unwrap_or(0);
+        Metadata::new(path, self.is_dir(), len as u64, self.modified)
+    }
+}
+
+pub struct RamFileSystem {
+    nodes: RwLock<BTreeMap<String, RamNode>>,
+}
+
+impl RamFileSystem {
+    pub fn new() -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(String::from("/"), RamNode::dir());
+        Self { nodes: RwLock::new(nodes) }
+    }
+}
+
+/// 判断`path`是否在目录`dir`之下（不含`dir`本身）
+fn is_child_of(path: &str, dir: &str) -> bool {
+    path.len() > dir.len() && path.starts_with(dir) && (dir == "/" || path.as_bytes()[dir.len()] == b'/')
+}
+
+/// 检查父目录存在且是目录
+fn check_parent(nodes: &BTreeMap<String, RamNode>, path: &str) -> Result<(), FileError> {
+    if path == "/" {
+        return Err(FileError::RootDirError);
+    }
+    match nodes.get(dirname(path)) {
+        None => Err(FileError::NotFoundError),
+        Some(parent) if !parent.is_dir() => Err(FileError::NotADirError),
+        Some(_) => Ok(()),
+    }
+}
+
+impl FileSystem for RamFileSystem {
+    fn metadata(&self, path: &str) -> Result<Metadata, FileError> {
+        match self.nodes.read().get(path) {
+            None => Err(FileError::NotFoundError),
+            Some(node) => Ok(node.metadata(path)),
+        }
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<FileEntry>, FileError> {
+        let nodes = self.nodes.read();
+        match nodes.get(path) {
+            None => return Err(FileError::NotFoundError),
+            Some(node) if !node.is_dir() => return Err(FileError::NotADirError),
+            Some(_) => {}
+        }
+        let result = nodes
+            .iter()
+            .filter(|(key, _)| key.as_str() != "/" && dirname(key.as_str()) == path)
+            .map(|(key, node)| {
+                let metadata = node.metadata(key.as_str());
+                if node.is_dir() {
+                    FileEntry::Dir(metadata)
+                } else {
+                    FileEntry::File(metadata)
+                }
+            })
+            .collect();
+        Ok(result)
+    }
+
+    fn read(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, FileError> {
+        let nodes = self.nodes.read();
+        let data = match nodes.get(path) {
+            None => return Err(FileError::NotFoundError),
+            Some(RamNode { data: None, .. }) => return Err(FileError::NotAFileError),
+            Some(RamNode { data: Some(data), .. }) => data,
+        };
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn write(&self, path: &str, offset: u64, buf: &[u8]) -> Result<usize, FileError> {
+        let mut nodes = self.nodes.write();
+        let node = nodes.get_mut(path).ok_or(FileError::NotFoundError)?;
+        let data = node.data.as_mut().ok_or(FileError::NotAFileError)?;
+        let end = offset.checked_add(buf.len() as u64).ok_or(FileError::NoSpaceError)?;
+        if (data.len() as u64) < end {
+            resize(data, end)?;
+        }
+        let start = offset as usize;
+        data[start..start + buf.len()].copy_from_slice(buf);
+        node.modified = now();
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, path: &str, len: u64) -> Result<(), FileError> {
+        let mut nodes = self.nodes.write();
+        let node = nodes.get_mut(path).ok_or(FileError::NotFoundError)?;
+        let data = node.data.as_mut().ok_or(FileError::NotAFileError)?;
+        resize(data, len)?;
+        node.modified = now();
+        Ok(())
+    }
+
+    fn create(&self, path: &str) -> Result<(), FileError> {
+        let mut nodes = self.nodes.write();
+        check_parent(&nodes, path)?;
+        match nodes.get(path) {
+            Some(node) if node.is_dir() => Err(FileError::NotAFileError),
+            Some(_) => Ok(()),
+            None => {
+                nodes.insert(String::from(path), RamNode::file());
+                Ok(())
+            }
+        }
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), FileError> {
+        let mut nodes = self.nodes.write();
+        if path == "/" {
+            return Ok(());
+        }
+        check_parent(&nodes, path)?;
+        match nodes.get(path) {
+            Some(node) if !node.is_dir() => Err(FileError::NotADirError),
+            Some(_) => Ok(()),
+            None => {
+                nodes.insert(String::from(path), RamNode::dir());
+                Ok(())
+            }
+        }
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), FileError> {
+        let mut nodes = self.nodes.write();
+        if from == "/" {
+            return Err(FileError::RootDirError);
+        }
+        if !nodes.contains_key(from) {
+            return Err(FileError::NotFoundError);
+        }
+        if nodes.contains_key(to) {
+            return Err(FileError::AlreadyExistsError);
+        }
+        // 不能把目录移动到它自己下面
+        if is_child_of(to, from) {
+            return Err(FileError::BadRelatePathError);
+        }
+        check_parent(&nodes, to)?;
+
+        let moved: Vec<String> = nodes.keys().filter(|key| key.as_str() == from || is_child_of(key, from)).cloned().collect();
+        for key in moved {
+            let node = nodes.remove(key.as_str()).unwrap();
+            nodes.insert(alloc::format!("{}{}", to, &key[from.len()..]), node);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cinea_os_sysapi::fs::FileError;
+
+    use super::super::vfs::FileSystem;
+    use super::RamFileSystem;
+
+    #[test_case]
+    fn test_ramfs_read_write() {
+        let fs = RamFileSystem::new();
+        fs.create("/a.txt").unwrap();
+        assert_eq!(fs.write("/a.txt", 0, b"hello").unwrap(), 5);
+        assert_eq!(fs.write("/a.txt", 8, b"world").unwrap(), 5);
+        assert_eq!(fs.metadata("/a.txt").unwrap().len(), 13);
+
+        let mut buf = [0xFFu8; 16];
+        assert_eq!(fs.read("/a.txt", 0, &mut buf).unwrap(), 13);
+        assert_eq!(&buf[..13], b"hello\0\0\0world");
+        // 读取不会超出缓冲区
+        assert_eq!(fs.read("/a.txt", 3, &mut buf[..4]).unwrap(), 4);
+        assert_eq!(&buf[..4], b"lo\0\0");
+        assert_eq!(fs.read("/a.txt", 20, &mut buf).unwrap(), 0);
+
+        fs.truncate("/a.txt", 2).unwrap();
+        assert_eq!(fs.read("/a.txt", 0, &mut buf).unwrap(), 2);
+
+        // 放不进内存的大小被拒绝，文件不变
+        assert!(matches!(fs.write("/a.txt", 1 << 40, b"x"), Err(FileError::NoSpaceError)));
+        assert!(matches!(fs.truncate("/a.txt", u64::MAX), Err(FileError::NoSpaceError)));
+        assert_eq!(fs.metadata("/a.txt").unwrap().len(), 2);
+        println!("[ok]  FileSystem RamFS read/write")
+    }
+
+    #[test_case]
+    fn test_ramfs_dirs() {
+        let fs = RamFileSystem::new();
+        fs.create_dir("/d").unwrap();
+        fs.create("/d/f").unwrap();
+        fs.create("/g").unwrap();
+        fs.create("/x/f").unwrap_err();
+        fs.create_dir("/g").unwrap_err();
+        assert!(fs.metadata("/d").unwrap().is_dir());
+        assert_eq!(fs.list("/").unwrap().len(), 2);
+        assert_eq!(fs.list("/d").unwrap().len(), 1);
+        fs.list("/g").unwrap_err();
+
+        fs.rename("/d", "/e").unwrap();
+        fs.metadata("/d/f").unwrap_err();
+        assert!(fs.metadata("/e/f").unwrap().is_file());
+        fs.rename("/g", "/e/f").unwrap_err();
+        fs.rename("/e", "/e/sub").unwrap_err();
+        println!("[ok]  FileSystem RamFS directories")
+    }
+}
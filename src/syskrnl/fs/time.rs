@@ -25,3 +25,13 @@ impl fatfs::TimeProvider for CosTimeProvider {
         DateTime::new(date, time)
     }
 }
+
+/// 获取当前时间（用于非FAT文件系统的元数据）
+pub fn now() -> cinea_os_sysapi::time::DateTime {
+    use cinea_os_sysapi::time as tm;
+
+    let now = syskrnl::time::raw_time();
+    let date = tm::Date::new(now.year as u16, now.month as u16, now.day as u16);
+    let time = tm::Time::new(now.hour as u16, now.minute as u16, now.second as u16, 0);
+    tm::DateTime::new(date, time)
+}
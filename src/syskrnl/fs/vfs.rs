@@ -0,0 +1,211 @@
+//! 虚拟文件系统层
+//!
+//! 各个具体的文件系统（FAT、设备表……）实现[`FileSystem`]，并挂载到挂载表的某个路径上。
+//! 上层的`open`/`read`/`write`/`list`只面对绝对路径，由本层按最长前缀找到对应的文件系统，
+//! 再把挂载点内的相对路径（总是以`/`开头）交给它。
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use lazy_static::lazy_static;
+use spin::RwLock;
+
+use cinea_os_sysapi::fs::{dirname, filename, path_combine, FileEntry, FileError, Metadata};
+
+use super::time::now;
+
+/// 可被挂载的文件系统
+///
+/// 所有路径均为挂载点内的绝对路径，根目录为`/`。
+pub trait FileSystem: Send + Sync {
+    /// 获取路径元数据
+    fn metadata(&self, path: &str) -> Result<Metadata, FileError>;
+
+    /// 列出目录下的文件
+    fn list(&self, path: &str) -> Result<Vec<FileEntry>, FileError>;
+
+    /// 从第`start`项开始列出目录下至多`count`项，返回的项数少于`count`表示已到末尾
+    ///
+    /// 默认基于[`FileSystem::list`]实现，目录很大的文件系统应当重写它。
+    fn read_dir(&self, path: &str, start: usize, count: usize) -> Result<Vec<FileEntry>, FileError> {
+        Ok(self.list(path)?.into_iter().skip(start).take(count).collect())
+    }
+
+    /// 从`offset`处读取文件
+    fn read(&self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, FileError>;
+
+    /// 从`offset`处写入文件，文件会按需增长
+    fn write(&self, path: &str, offset: u64, buf: &[u8]) -> Result<usize, FileError>;
+
+    /// 把文件截断（或以0填充扩展）到`len`字节
+    fn truncate(&self, path: &str, len: u64) -> Result<(), FileError>;
+
+    /// 创建空文件，文件已存在时不做任何事
+    fn create(&self, path: &str) -> Result<(), FileError>;
+
+    /// 创建目录，目录已存在时不做任何事
+    fn create_dir(&self, path: &str) -> Result<(), FileError>;
+
+    /// 重命名（移动）文件或目录，目标不能已存在
+    fn rename(&self, from: &str, to: &str) -> Result<(), FileError>;
+
+    /// 是否允许多个句柄同时写入同一文件，设备文件通常允许
+    fn concurrent_write(&self) -> bool {
+        false
+    }
+}
+
+lazy_static! {
+    static ref MOUNT_TABLE: RwLock<BTreeMap<String, Arc<dyn FileSystem>>> = RwLock::new(BTreeMap::new());
+}
+
+/// 挂载文件系统
+pub fn mount(point: &str, fs: Arc<dyn FileSystem>) -> Result<(), FileError> {
+    let mut table = MOUNT_TABLE.write();
+    if table.contains_key(point) {
+        return Err(FileError::FileBusyError);
+    }
+    table.insert(String::from(point), fs);
+    Ok(())
+}
+
+/// 卸载文件系统
+pub fn umount(point: &str) -> Result<(), FileError> {
+    match MOUNT_TABLE.write().remove(point) {
+        Some(_) => Ok(()),
+        None => Err(FileError::NotFoundError),
+    }
+}
+
+/// 列出所有挂载点
+pub fn mount_points() -> Vec<String> {
+    MOUNT_TABLE.read().keys().cloned().collect()
+}
+
+/// 判断挂载点`point`是否包含路径`path`
+fn contains(point: &str, path: &str) -> bool {
+    point == "/" || path == point || (path.starts_with(point) && path.as_bytes().get(point.len()) == Some(&b'/'))
+}
+
+/// 找到路径所在的文件系统，返回（挂载点，文件系统，挂载点内路径）
+fn resolve(path: &str) -> Result<(String, Arc<dyn FileSystem>, String), FileError> {
+    let table = MOUNT_TABLE.read();
+    let (point, fs) = table
+        .iter()
+        .filter(|(point, _)| contains(point, path))
+        .max_by_key(|(point, _)| point.len())
+        .ok_or(FileError::NotFoundError)?;
+    let inner = if point == "/" { path } else { &path[point.len()..] };
+    let inner = if inner.is_empty() { "/" } else { inner };
+    Ok((point.clone(), fs.clone(), String::from(inner)))
+}
+
+/// 把挂载点内的路径还原成绝对路径
+fn rebase(point: &str, inner: &str) -> String {
+    if point == "/" {
+        String::from(inner)
+    } else if inner == "/" {
+        String::from(point)
+    } else {
+        alloc::format!("{}{}", point, inner)
+    }
+}
+
+/// 获取路径元数据
+pub fn metadata(path: &str) -> Result<Metadata, FileError> {
+    let (point, fs, inner) = resolve(path)?;
+    let mut meta = fs.metadata(inner.as_str())?;
+    meta.set_path(rebase(point.as_str(), meta.path()).as_str());
+    Ok(meta)
+}
+
+/// 把文件系统返回的目录项路径还原成绝对路径
+fn rebase_entries(point: &str, entries: &mut [FileEntry]) {
+    for entry in entries.iter_mut() {
+        if let Some(meta) = entry.metadata_mut() {
+            let path = rebase(point, meta.path());
+            meta.set_path(path.as_str());
+        }
+    }
+}
+
+/// 列出目录下的文件，挂载在该目录下的文件系统也会作为目录列出
+pub fn list(path: &str) -> Result<Vec<FileEntry>, FileError> {
+    let (point, fs, inner) = resolve(path)?;
+    let mut result = fs.list(inner.as_str())?;
+    rebase_entries(point.as_str(), &mut result);
+    result.extend(child_mounts(path));
+    Ok(result)
+}
+
+/// 从第`start`项开始列出目录下至多`count`项，不包括挂载在该目录下的文件系统
+pub fn read_dir(path: &str, start: usize, count: usize) -> Result<Vec<FileEntry>, FileError> {
+    let (point, fs, inner) = resolve(path)?;
+    let mut result = fs.read_dir(inner.as_str(), start, count)?;
+    rebase_entries(point.as_str(), &mut result);
+    Ok(result)
+}
+
+/// 挂载在目录`path`下、且在该目录所在的文件系统中不存在同名项的挂载点
+///
+/// 存在同名项时该项已由所在文件系统自己列出。
+pub fn child_mounts(path: &str) -> Vec<FileEntry> {
+    let parent = resolve(path).ok();
+    mount_points()
+        .into_iter()
+        .filter(|child| child != "/" && dirname(child.as_str()) == path)
+        .filter(|child| match &parent {
+            Some((_, fs, inner)) => fs.metadata(path_combine(inner.as_str(), filename(child.as_str())).as_str()).is_err(),
+            None => true,
+        })
+        .map(|child| FileEntry::Dir(Metadata::new(child.as_str(), true, 0, now())))
+        .collect()
+}
+
+/// 读取文件
+pub fn read(path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, FileError> {
+    let (_, fs, inner) = resolve(path)?;
+    fs.read(inner.as_str(), offset, buf)
+}
+
+/// 写入文件
+pub fn write(path: &str, offset: u64, buf: &[u8]) -> Result<usize, FileError> {
+    let (_, fs, inner) = resolve(path)?;
+    fs.write(inner.as_str(), offset, buf)
+}
+
+/// 截断文件
+pub fn truncate(path: &str, len: u64) -> Result<(), FileError> {
+    let (_, fs, inner) = resolve(path)?;
+    fs.truncate(inner.as_str(), len)
+}
+
+/// 创建空文件
+pub fn create(path: &str) -> Result<(), FileError> {
+    let (_, fs, inner) = resolve(path)?;
+    fs.create(inner.as_str())
+}
+
+/// 创建目录
+pub fn create_dir(path: &str) -> Result<(), FileError> {
+    let (_, fs, inner) = resolve(path)?;
+    fs.create_dir(inner.as_str())
+}
+
+/// 重命名，不支持跨文件系统移动
+pub fn rename(from: &str, to: &str) -> Result<(), FileError> {
+    let (from_point, fs, from_inner) = resolve(from)?;
+    let (to_point, _, to_inner) = resolve(to)?;
+    if from_point != to_point {
+        return Err(FileError::UnsupportedError);
+    }
+    fs.rename(from_inner.as_str(), to_inner.as_str())
+}
+
+/// 路径所在的文件系统是否允许并发写入
+pub fn concurrent_write(path: &str) -> Result<bool, FileError> {
+    let (_, fs, _) = resolve(path)?;
+    Ok(fs.concurrent_write())
+}
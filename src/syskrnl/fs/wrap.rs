@@ -1,135 +1,54 @@
-//! 本文件提供对fatfs的封装
+//! 本文件提供文件操作的统一接口，具体的文件系统由[`super::vfs`]按挂载点分派
 
 use alloc::collections::BTreeMap;
+use alloc::format;
 use alloc::string::String;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-use fatfs::{DirEntry, Read, Seek, SeekFrom, Write};
 use lazy_static::lazy_static;
 use spin::Mutex;
 
 use cinea_os_sysapi::fs as fsapi;
-use cinea_os_sysapi::fs::FileError::{NotAFileError, OSError};
-use cinea_os_sysapi::fs::{dirname, filename, path_combine, realpath, FileEntry, Metadata};
-use fsapi::FileError::{self, NotADirError, NotFoundError, RootDirError};
+use cinea_os_sysapi::fs::FileError::OSError;
+use cinea_os_sysapi::fs::{FileEntry, LockMode, Metadata, OpenFlags, SeekFrom};
+use fsapi::FileError::{self, NotADirError, NotFoundError};
 
-use crate::syskrnl::fs::device::is_device;
 use crate::syskrnl::proc;
 use crate::syskrnl::proc::{file_handles, set_dir};
 
-use super::ahci::AhciDeviceReader;
-use super::oem::Cp437Converter;
-use super::time::CosTimeProvider;
-
-lazy_static! {
-    static ref DATA_DISK_FS: Mutex<fatfs::FileSystem<AhciDeviceReader, CosTimeProvider, Cp437Converter>> = {
-        let reader = AhciDeviceReader::new(0).unwrap();
-        let option = fatfs::FsOptions::new().oem_cp_converter(Cp437Converter).time_provider(CosTimeProvider);
-        let fs = fatfs::FileSystem::new(reader, option);
-        Mutex::new(fs.unwrap())
-    };
-}
+use super::{cache, vfs};
 
+/// 调试用：通过已注册的设备文件（如`/dev/sda`）读出磁盘开头的字节并列出根目录
+///
+/// 经由设备文件读取，和挂载的文件系统共用同一份块缓存，不会出现同一批扇区的两份缓存。
 #[allow(dead_code)]
-fn test() {
+fn test(disk: &str) {
     let mut buf = [0u8; 100];
-    let mut reader = AhciDeviceReader::new(0).unwrap();
-    reader.read(&mut buf).unwrap();
+    vfs::read(format!("/dev/{}", disk).as_str(), 0, &mut buf).unwrap();
 
-    println!("TEST AHCI and AHCI_READER:");
+    println!("TEST /dev/{}:", disk);
     for n in buf {
         print!("{:02X} ", n)
     }
     println!();
 
-    println!("DATAFS Type: {:?}", DATA_DISK_FS.lock().fat_type());
-}
-
-// fn is_device<IO, TP, OCC>(file: &mut fatfs::File<IO, TP, OCC>) -> Option<usize>
-//     where IO: fatfs::ReadWriteSeek, TP: fatfs::TimeProvider, OCC: fatfs::OemCpConverter {
-//     let mut buf = [0u8; 9];
-//     if let Ok(len) = file.read(&mut buf) {
-//         if len == 9 {
-//             if let Ok(id) = super::device::device_id(&buf) {
-//                 return Some(id);
-//             }
-//         }
-//     }
-//     None
-// }
-
-fn seekpath<'a, IO, TP, OCC>(path: &str, root_dir: fatfs::Dir<'a, IO, TP, OCC>) -> Result<DirEntry<'a, IO, TP, OCC>, FileError>
-where
-    IO: fatfs::ReadWriteSeek,
-    TP: fatfs::TimeProvider,
-    OCC: fatfs::OemCpConverter,
-{
-    // Split the path
-    let path = realpath(path, proc::dir().as_str());
-    let dirname = dirname(path.as_str());
-    let filename = filename(path.as_str());
-    let mut spilted_path: Vec<_> = dirname.split('/').filter(|x| x.len() > 0).collect();
-    fsapi::process_relative_path(&mut spilted_path)?;
-
-    let mut dir = root_dir;
-
-    for next in spilted_path {
-        if let Ok(next_dir) = dir.open_dir(next) {
-            dir = next_dir;
-        } else {
-            return Err(NotFoundError);
-        }
-    }
-
-    if filename.len() == 0 {
-        return Err(RootDirError);
-    }
+    println!("DATAFS Root: {:?}", list("/").map(|x| x.len()));
+}
 
-    if let Some(target) = dir.iter().find(|x| {
-        if let Ok(x) = x {
-            // debugln!("fn:{} ?= {}",x.file_name(), filename);
-            x.file_name() == filename
-        } else {
-            false
-        }
-    }) {
-        Ok(target.unwrap())
-    } else {
-        Err(NotFoundError)
-    }
+/// 将路径转换为标准化的绝对路径，相对路径以当前进程的工作目录为基准
+pub fn absolute(path: &str) -> Result<String, FileError> {
+    fsapi::absolute_path(path, proc::dir().as_str())
 }
 
 /// 获取路径元数据
 pub fn metadata(path: &str) -> Result<Metadata, FileError> {
-    let lock = DATA_DISK_FS.lock();
-    let entry = seekpath(path, lock.root_dir())?;
-    Ok(fsapi::Metadata::from_dir_entry(entry, path))
+    vfs::metadata(absolute(path)?.as_str())
 }
 
 /// 列出目录下的文件
 pub fn list(path: &str) -> Result<Vec<FileEntry>, FileError> {
-    let lock = DATA_DISK_FS.lock();
-    let entry = seekpath(path, lock.root_dir())?;
-    if !entry.is_dir() {
-        return Err(FileError::NotADirError);
-    }
-
-    let result: Vec<FileEntry> = entry
-        .to_dir()
-        .iter()
-        .filter(|dir_entry| dir_entry.is_ok())
-        .map(|dir_entry| {
-            let dir_entry = dir_entry.unwrap();
-            let new_path = path_combine(path, dir_entry.file_name().as_str());
-            if dir_entry.is_dir() {
-                FileEntry::Dir(fsapi::Metadata::from_dir_entry(dir_entry, new_path.as_str()))
-            } else {
-                FileEntry::File(fsapi::Metadata::from_dir_entry(dir_entry, new_path.as_str()))
-            }
-        })
-        .collect();
-    Ok(result)
+    vfs::list(absolute(path)?.as_str())
 }
 
 /// 获取当前工作路径
@@ -139,7 +58,7 @@ pub fn current_dir() -> String {
 
 /// 更换路径
 pub fn change_dir(path: &str) -> Result<(), FileError> {
-    let path = fsapi::path_standardize(path)?;
+    let path = absolute(path)?;
     let meta = metadata(path.as_str())?;
     if !meta.is_dir() {
         Err(NotADirError)
@@ -154,15 +73,54 @@ pub fn change_dir(path: &str) -> Result<(), FileError> {
 pub struct OpenFileHandle {
     pub id: usize,
     pub path: String,
-    pub write: bool,
-    pub device: bool,
+    /// 打开方式
+    pub flags: OpenFlags,
+    /// 读写位置
+    pub pos: u64,
+}
+
+impl OpenFileHandle {
+    pub fn new(id: usize, path: String, flags: OpenFlags) -> Self {
+        Self { id, path, flags, pos: 0 }
+    }
+
+    pub fn readable(&self) -> bool {
+        self.flags.contains(OpenFlags::READ)
+    }
+
+    pub fn writable(&self) -> bool {
+        self.flags.contains(OpenFlags::WRITE)
+    }
+
+    /// 追加模式：每次写入前都把读写位置移到文件末尾
+    pub fn append(&self) -> bool {
+        self.flags.contains(OpenFlags::APPEND)
+    }
 }
 
 /// 系统文件表-条目
+///
+/// 普通文件遵循读写者规则：可以同时有多个读者，或者只有一个写者。
+/// 允许并发写入的文件系统（如设备）不受此限制。
 pub struct SystemFileEntry {
     pub path: String,
-    pub share: usize,
-    pub mutex: bool,
+    /// 以读方式打开的句柄数
+    pub readers: usize,
+    /// 以写方式打开的句柄数
+    pub writers: usize,
+    /// 建议锁：句柄 -> 锁类型
+    pub locks: BTreeMap<usize, LockMode>,
+}
+
+impl SystemFileEntry {
+    fn new(path: String) -> Self {
+        Self {
+            path,
+            readers: 0,
+            writers: 0,
+            locks: BTreeMap::new(),
+        }
+    }
 }
 
 static USER_FILE_HANDLER_ID: AtomicUsize = AtomicUsize::new(4);
@@ -171,221 +129,350 @@ lazy_static! {
     static ref SYSTEM_FILE_TABLE: Mutex<BTreeMap<String, SystemFileEntry >> = Mutex::new(BTreeMap::new());
 }
 
-fn register_opened_file(path: String, write: bool, device: bool) -> Result<usize, FileError> {
+fn register_opened_file(path: String, flags: OpenFlags) -> Result<usize, FileError> {
+    let write = flags.contains(OpenFlags::WRITE);
+    let concurrent = vfs::concurrent_write(path.as_str())?;
     let mut lock = SYSTEM_FILE_TABLE.lock();
-    if let Some(sft) = lock.get_mut(path.as_str()) {
-        if sft.mutex {
-            Err(FileError::FileBusyError)
-        } else {
-            let fh = proc::file_handles();
-            let new_id = USER_FILE_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
-            fh.lock().insert(
-                new_id,
-                OpenFileHandle {
-                    id: new_id,
-                    path,
-                    write,
-                    device,
-                },
-            );
-            sft.share += 1;
-            Ok(new_id)
-        }
+    let sft = lock.entry(path.clone()).or_insert_with(|| SystemFileEntry::new(path.clone()));
+    // 冲突时条目中必然已有句柄，不会留下空条目
+    if !concurrent && (sft.writers > 0 || (write && sft.readers > 0)) {
+        return Err(FileError::FileBusyError);
+    }
+    if write {
+        sft.writers += 1;
     } else {
-        lock.insert(
-            path.clone(),
-            SystemFileEntry {
-                path: path.clone(),
-                share: 1,
-                mutex: write,
-            },
-        );
-        let fh = proc::file_handles();
-        let new_id = USER_FILE_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
-        fh.lock().insert(
-            new_id,
-            OpenFileHandle {
-                id: new_id,
-                path,
-                write,
-                device,
-            },
-        );
-        Ok(new_id)
+        sft.readers += 1;
     }
+
+    let fh = proc::file_handles();
+    let new_id = USER_FILE_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
+    fh.lock().insert(new_id, OpenFileHandle::new(new_id, path, flags));
+    Ok(new_id)
 }
 
 /// 打开文件（内核级）
-pub fn open(path: &str, write: bool) -> Result<usize, FileError> {
-    let path = fsapi::path_standardize(path)?;
-
-    // Device Check
-    if is_device(path.as_str()) {
-        return register_opened_file(path, write, true);
+pub fn open(path: &str, flags: OpenFlags) -> Result<usize, FileError> {
+    flags.validate()?;
+    let path = absolute(path)?;
+
+    match vfs::metadata(path.as_str()) {
+        Ok(_) if flags.contains(OpenFlags::EXCL) => return Err(FileError::AlreadyExistsError),
+        Ok(data) if !data.is_file() => return Err(FileError::NotAFileError),
+        Ok(_) => {}
+        Err(NotFoundError) if flags.contains(OpenFlags::CREATE) => vfs::create(path.as_str())?,
+        Err(e) => return Err(e),
     }
 
-    let data = metadata(path.as_str())?;
-    if !data.is_file() {
-        Err(FileError::NotAFileError)
-    } else {
-        register_opened_file(path, write, false)
+    let id = register_opened_file(path.clone(), flags)?;
+    // 先登记再截断，避免截断别人正在使用的文件
+    if flags.contains(OpenFlags::TRUNC) {
+        if let Err(e) = vfs::truncate(path.as_str(), 0) {
+            let _ = close(id);
+            return Err(e);
+        }
     }
+    Ok(id)
 }
 
-/// 关闭文件（内核）
+/// 关闭文件（内核），同时释放句柄持有的建议锁
 pub fn close(id: usize) -> Result<(), FileError> {
     if id < 4 {
         return Err(NotFoundError);
     } // 不允许关闭系统设备
-    let fh = proc::file_handles();
-    let mut lock = fh.lock();
-    if !lock.contains_key(&id) {
-        return Err(NotFoundError);
-    }
-    let path = lock.get(&id).unwrap().path.clone();
-    lock.remove(&id);
+    let handle = file_handles().lock().remove(&id).ok_or(NotFoundError)?;
     let mut lock = SYSTEM_FILE_TABLE.lock();
-    let sft = lock.get_mut(path.as_str());
-    if sft.is_none() {
-        return Err(OSError);
-    }
-    let sft = sft.unwrap();
-    if sft.share == 1 {
-        lock.remove(path.as_str());
-        Ok(())
+    let sft = lock.get_mut(handle.path.as_str()).ok_or(OSError)?;
+    if handle.writable() {
+        sft.writers -= 1;
     } else {
-        sft.share -= 1;
-        Ok(())
+        sft.readers -= 1;
     }
+    sft.locks.remove(&id);
+    if sft.readers == 0 && sft.writers == 0 {
+        lock.remove(handle.path.as_str());
+    }
+    Ok(())
 }
 
-fn write_all_path(path: &str, buf: &[u8]) -> Result<usize, FileError> {
-    let lock = DATA_DISK_FS.lock();
-    let root = lock.root_dir();
-    let file = seekpath(path, root)?;
-    if !file.is_file() {
-        return Err(NotAFileError);
+/// 关闭当前进程打开的所有文件和目录，在进程退出时调用
+pub fn close_all() {
+    let ids: Vec<usize> = file_handles().lock().keys().filter(|id| **id >= 4).cloned().collect();
+    for id in ids {
+        let _ = close(id);
+    }
+    let pid = proc::id();
+    DIR_HANDLES.lock().retain(|_, handle| handle.pid != pid);
+}
+
+/// 打开的目录句柄
+struct DirHandle {
+    pid: usize,
+    path: String,
+    /// 挂载点所在文件系统的目录项是否已读完，读完后继续列出挂载在该目录下的文件系统
+    fs_done: bool,
+    /// 当前阶段已读出的项数
+    index: usize,
+}
+
+lazy_static! {
+    static ref DIR_HANDLES: Mutex<BTreeMap<usize, DirHandle>> = Mutex::new(BTreeMap::new());
+}
+
+/// 打开目录，用于分批读取目录项
+pub fn open_dir(path: &str) -> Result<usize, FileError> {
+    let path = absolute(path)?;
+    if !vfs::metadata(path.as_str())?.is_dir() {
+        return Err(NotADirError);
+    }
+    let id = USER_FILE_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
+    let handle = DirHandle {
+        pid: proc::id(),
+        path,
+        fs_done: false,
+        index: 0,
+    };
+    DIR_HANDLES.lock().insert(id, handle);
+    Ok(id)
+}
+
+/// 读出目录中接下来的至多`count`项，返回空表示已读完
+pub fn read_dir(id: usize, count: usize) -> Result<Vec<FileEntry>, FileError> {
+    let pid = proc::id();
+    let (path, mut fs_done, mut index) = match DIR_HANDLES.lock().get(&id) {
+        Some(handle) if handle.pid == pid => (handle.path.clone(), handle.fs_done, handle.index),
+        _ => return Err(NotFoundError),
+    };
+
+    let mut result = Vec::new();
+    if !fs_done {
+        result = vfs::read_dir(path.as_str(), index, count)?;
+        index += result.len();
+        if result.len() < count {
+            fs_done = true;
+            index = 0;
+        }
+    }
+    if fs_done && result.len() < count {
+        let mounts: Vec<FileEntry> = vfs::child_mounts(path.as_str()).into_iter().skip(index).take(count - result.len()).collect();
+        index += mounts.len();
+        result.extend(mounts);
+    }
+
+    if let Some(handle) = DIR_HANDLES.lock().get_mut(&id) {
+        handle.fs_done = fs_done;
+        handle.index = index;
     }
-    let mut file = file.to_file();
+    Ok(result)
+}
 
-    if file.seek(SeekFrom::Start(0)).is_err() {
-        return Err(OSError);
+/// 关闭目录
+pub fn close_dir(id: usize) -> Result<(), FileError> {
+    let pid = proc::id();
+    let mut lock = DIR_HANDLES.lock();
+    match lock.get(&id) {
+        Some(handle) if handle.pid == pid => {
+            lock.remove(&id);
+            Ok(())
+        }
+        _ => Err(NotFoundError),
     }
-    match file.write_all(buf) {
-        Err(_) => Err(OSError),
-        Ok(()) => Ok(buf.len()),
+}
+
+/// 对句柄对应的文件加建议锁或解锁，不会阻塞，冲突时返回`FileBusyError`
+///
+/// 共享锁可以被多个句柄同时持有，独占锁只能被一个句柄持有。锁只约束同样调用`flock`的程序，不影响读写。
+pub fn flock(id: usize, mode: LockMode) -> Result<(), FileError> {
+    let handle = handle(id)?;
+    let mut lock = SYSTEM_FILE_TABLE.lock();
+    let sft = lock.get_mut(handle.path.as_str()).ok_or(OSError)?;
+    let conflict = sft.locks.iter().filter(|(owner, _)| **owner != id).any(|(_, held)| match mode {
+        LockMode::Unlock => false,
+        LockMode::Shared => *held == LockMode::Exclusive,
+        LockMode::Exclusive => true,
+    });
+    if conflict {
+        return Err(FileError::FileBusyError);
     }
+    match mode {
+        LockMode::Unlock => sft.locks.remove(&id),
+        _ => sft.locks.insert(id, mode),
+    };
+    Ok(())
 }
 
-fn write_all_device(path: &str, buf: &[u8]) -> Result<usize, FileError> {
-    super::device::write(path, buf)
+/// 把块缓存中所有的脏块写回磁盘
+pub fn sync() -> Result<(), FileError> {
+    cache::sync().map_err(|_| FileError::DeviceIOError)
 }
 
-/// 全部写
-/// FIXME：暂不提供部分写、指定指针等功能
-pub fn write_all(id: usize, buf: &[u8]) -> Result<usize, FileError> {
+/// 取出当前进程文件句柄的副本
+///
+/// 读写时不持有句柄表的锁，因为像`/proc`这样的文件系统在读取时也要访问句柄表。
+fn handle(id: usize) -> Result<OpenFileHandle, FileError> {
+    file_handles().lock().get(&id).cloned().ok_or(NotFoundError)
+}
+
+/// 更新句柄的读写位置
+fn set_pos(id: usize, pos: u64) {
+    if let Some(handle) = file_handles().lock().get_mut(&id) {
+        handle.pos = pos;
+    }
+}
+
+/// 查找当前进程打开`path`的句柄，检查其中是否有满足`allowed`的
+///
+/// 没有打开时返回`NotFoundError`；打开了但都不满足时返回`Ok(false)`。
+fn opened_with(path: &str, allowed: impl Fn(&OpenFileHandle) -> bool) -> Result<bool, FileError> {
     let fh = file_handles();
     let fh_lock = fh.lock();
-    if fh_lock.contains_key(&id) {
-        let handle = fh_lock.get(&id).unwrap();
-        if handle.write {
-            if handle.device {
-                write_all_device(handle.path.as_str(), buf)
-            } else {
-                write_all_path(handle.path.as_str(), buf)
-            }
-        } else {
-            Err(FileError::OpenMethodError)
+    let mut handles = fh_lock.values().filter(|x| x.path == path).peekable();
+    if handles.peek().is_none() {
+        return Err(NotFoundError);
+    }
+    Ok(handles.any(allowed))
+}
+
+/// 在句柄的读写位置写入，返回实际写入的字节数，读写位置随之后移
+pub fn write(id: usize, buf: &[u8]) -> Result<usize, FileError> {
+    let handle = handle(id)?;
+    if !handle.writable() {
+        return Err(FileError::ReadOnlyError);
+    }
+    let pos = if handle.append() { vfs::metadata(handle.path.as_str())?.len() } else { handle.pos };
+    let len = vfs::write(handle.path.as_str(), pos, buf)?;
+    set_pos(id, pos + len as u64);
+    Ok(len)
+}
+
+/// 全部写：反复写入直到整个缓冲区都写完
+pub fn write_all(id: usize, buf: &[u8]) -> Result<usize, FileError> {
+    let mut written = 0;
+    while written < buf.len() {
+        match write(id, &buf[written..])? {
+            0 => return Err(FileError::DeviceIOError),
+            len => written += len,
         }
-    } else {
-        Err(NotFoundError)
     }
+    Ok(written)
 }
 
-/// 全部写（必须已经打开文件）
-/// FIXME：暂不提供部分写、指定指针等功能
+/// 全部写（必须已经打开文件），总是从文件开头写入，不影响句柄的读写位置
 pub fn write_with_path(path: &str, buf: &[u8]) -> Result<usize, FileError> {
-    let path = fsapi::path_standardize(path)?;
-    let fh = file_handles();
-    let fh_lock = fh.lock();
-    if let Some(handle) = fh_lock.iter().find(|x| (*x).1.path == path) {
-        if handle.1.write {
-            if handle.1.device {
-                write_all_device(path.as_str(), buf)
-            } else {
-                write_all_path(path.as_str(), buf)
-            }
-        } else {
-            Err(FileError::OpenMethodError)
-        }
+    let path = absolute(path)?;
+    if opened_with(path.as_str(), OpenFileHandle::writable)? {
+        vfs::write(path.as_str(), 0, buf)
     } else {
-        Err(NotFoundError)
+        Err(FileError::ReadOnlyError)
     }
 }
 
-fn read_path(path: &str, store: &mut [u8]) -> Result<usize, FileError> {
-    let lock = DATA_DISK_FS.lock();
-    let root = lock.root_dir();
-    let file = seekpath(path, root)?;
-    if !file.is_file() {
-        return Err(NotAFileError);
+/// 从句柄的读写位置读取，读写位置随之后移
+pub fn read(id: usize, buf: &mut [u8]) -> Result<usize, FileError> {
+    let handle = handle(id)?;
+    if !handle.readable() {
+        return Err(FileError::WriteOnlyError);
     }
-    let mut file = file.to_file();
+    let len = vfs::read(handle.path.as_str(), handle.pos, buf)?;
+    set_pos(id, handle.pos + len as u64);
+    Ok(len)
+}
+
+/// 句柄的读写位置之后剩余的字节数，设备文件没有长度，总是0
+pub fn remaining(id: usize) -> Result<u64, FileError> {
+    let handle = handle(id)?;
+    Ok(vfs::metadata(handle.path.as_str())?.len().saturating_sub(handle.pos))
+}
 
-    if file.seek(SeekFrom::Start(0)).is_err() {
-        return Err(OSError);
+/// 读取整个文件，缓冲区大小取自文件元数据
+pub fn read_all(path: &str) -> Result<Vec<u8>, FileError> {
+    let path = absolute(path)?;
+    let meta = vfs::metadata(path.as_str())?;
+    if !meta.is_file() {
+        return Err(FileError::NotAFileError);
     }
-    let mut buf = [0u8; 1024];
+    let mut buf = alloc::vec![0u8; meta.len() as usize];
     let mut pos = 0usize;
-    while let Ok(len) = file.read(&mut buf) {
-        if len > 0 {
-            store[pos..pos + len].copy_from_slice(&buf[..len]);
-            pos += len;
-            buf.fill(0);
-        } else {
-            return Ok(pos);
+    while pos < buf.len() {
+        match vfs::read(path.as_str(), pos as u64, &mut buf[pos..])? {
+            0 => break,
+            len => pos += len,
         }
     }
-    Err(OSError)
+    buf.truncate(pos);
+    Ok(buf)
 }
 
-fn read_device(path: &str, buf: &mut [u8]) -> Result<usize, FileError> {
-    super::device::read(path, buf)
+/// 读取（必须已经打开文件），总是从文件开头读取，不影响句柄的读写位置
+pub fn read_with_path(path: &str, buf: &mut [u8]) -> Result<usize, FileError> {
+    let path = absolute(path)?;
+    if opened_with(path.as_str(), OpenFileHandle::readable)? {
+        vfs::read(path.as_str(), 0, buf)
+    } else {
+        Err(FileError::WriteOnlyError)
+    }
 }
 
-pub fn read(id: usize, buf: &mut [u8]) -> Result<usize, FileError> {
-    let fh = file_handles();
-    let fh_lock = fh.lock();
-    if fh_lock.contains_key(&id) {
-        let handle = fh_lock.get(&id).unwrap();
-        if handle.device {
-            read_device(handle.path.as_str(), buf)
-        } else {
-            read_path(handle.path.as_str(), buf)
+/// 移动句柄的读写位置，返回新的位置（从文件开头算起）
+///
+/// 允许移动到文件末尾之后，之后的写入会以0填充中间的空洞。
+pub fn seek(id: usize, pos: SeekFrom) -> Result<u64, FileError> {
+    let handle = handle(id)?;
+    let new_pos = match pos {
+        SeekFrom::Start(offset) => offset,
+        SeekFrom::Current(offset) => handle.pos.checked_add_signed(offset).ok_or(FileError::OSError)?,
+        SeekFrom::End(offset) => {
+            let len = vfs::metadata(handle.path.as_str())?.len();
+            len.checked_add_signed(offset).ok_or(FileError::OSError)?
         }
-    } else {
-        Err(NotFoundError)
+    };
+    set_pos(id, new_pos);
+    Ok(new_pos)
+}
+
+/// 把句柄对应的文件截断（或以0填充扩展）到`len`字节，不影响读写位置
+pub fn ftruncate(id: usize, len: u64) -> Result<(), FileError> {
+    let handle = handle(id)?;
+    if !handle.writable() {
+        return Err(FileError::ReadOnlyError);
     }
+    vfs::truncate(handle.path.as_str(), len)
 }
 
-pub fn read_with_path(path: &str, buf: &mut [u8]) -> Result<usize, FileError> {
-    let path = fsapi::path_standardize(path)?;
-    let fh = file_handles();
-    let fh_lock = fh.lock();
-    if let Some(handle) = fh_lock.iter().find(|x| (*x).1.path == path) {
-        if handle.1.device {
-            read_device(path.as_str(), buf)
-        } else {
-            read_path(path.as_str(), buf)
-        }
-    } else {
-        Err(NotFoundError)
+/// 创建空文件，文件已存在时不做任何事
+pub fn create(path: &str) -> Result<(), FileError> {
+    vfs::create(absolute(path)?.as_str())
+}
+
+/// 创建目录
+pub fn create_dir(path: &str) -> Result<(), FileError> {
+    vfs::create_dir(absolute(path)?.as_str())
+}
+
+/// 重命名（移动）文件或目录，被打开的文件不能重命名
+pub fn rename(from: &str, to: &str) -> Result<(), FileError> {
+    let from = absolute(from)?;
+    let to = absolute(to)?;
+    if SYSTEM_FILE_TABLE.lock().contains_key(from.as_str()) {
+        return Err(FileError::FileBusyError);
+    }
+    vfs::rename(from.as_str(), to.as_str())
+}
+
+/// 把文件截断（或以0填充扩展）到`len`字节，以写方式打开的文件不能截断
+pub fn truncate(path: &str, len: u64) -> Result<(), FileError> {
+    let path = absolute(path)?;
+    if SYSTEM_FILE_TABLE.lock().get(path.as_str()).map(|x| x.writers > 0).unwrap_or(false) {
+        return Err(FileError::FileBusyError);
     }
+    let meta = vfs::metadata(path.as_str())?;
+    if !meta.is_file() {
+        return Err(FileError::NotAFileError);
+    }
+    vfs::truncate(path.as_str(), len)
 }
 
 pub fn info(path: &str) -> Result<Metadata, FileError> {
-    let path = fsapi::path_standardize(path)?;
-    metadata(path.as_str())
+    metadata(path)
 }
 
 #[cfg(test)]
@@ -396,7 +483,7 @@ mod tests {
 
     #[test_case]
     fn test_ahci() {
-        super::test();
+        super::test("sda");
         println!("[ok]  FileSystem AHCI AHCI_Reader")
     }
 
@@ -478,6 +565,30 @@ mod tests {
         println!("[ok]  FileSystem API test_realpath")
     }
 
+    #[test_case]
+    fn test_absolute_path() {
+        use super::fsapi::absolute_path;
+        assert_eq!(absolute_path("/usr/bin/gcc", "/home/user").unwrap(), "/usr/bin/gcc");
+        assert_eq!(absolute_path("gcc", "/usr/bin").unwrap(), "/usr/bin/gcc");
+        assert_eq!(absolute_path("../lib", "/usr/bin").unwrap(), "/usr/lib");
+        assert_eq!(absolute_path("..", "/usr").unwrap(), "/");
+        assert_eq!(absolute_path(".", "/").unwrap(), "/");
+        absolute_path("../..", "/usr").unwrap_err();
+        println!("[ok]  FileSystem API test_absolute_path")
+    }
+
+    #[test_case]
+    fn test_open_flags() {
+        use super::fsapi::OpenFlags;
+        OpenFlags::READ.validate().unwrap();
+        (OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCL).validate().unwrap();
+        (OpenFlags::READ | OpenFlags::WRITE | OpenFlags::TRUNC).validate().unwrap();
+        OpenFlags::CREATE.validate().unwrap_err();
+        (OpenFlags::READ | OpenFlags::APPEND).validate().unwrap_err();
+        (OpenFlags::WRITE | OpenFlags::EXCL).validate().unwrap_err();
+        println!("[ok]  FileSystem API test_open_flags")
+    }
+
     #[test_case]
     fn test_filename() {
         use super::fsapi::filename;
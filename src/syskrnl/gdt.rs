@@ -1,3 +1,5 @@
+use core::ptr::addr_of_mut;
+
 use lazy_static::lazy_static;
 use x86_64::instructions::segmentation::Segment;
 use x86_64::registers::segmentation::{SegmentSelector, DS};
@@ -20,30 +22,37 @@ pub struct Selectors {
     pub user_data_selector: SegmentSelector,
 }
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.privilege_stack_table[0] = {
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            VirtAddr::from_ptr(unsafe { &STACK }) + STACK_SIZE
-        };
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            VirtAddr::from_ptr(unsafe { &STACK }) + STACK_SIZE
-        };
-        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            VirtAddr::from_ptr(unsafe { &STACK }) + STACK_SIZE
-        };
-        tss.interrupt_stack_table[GENERAL_PROTECTION_FAULT_IST_INDEX as usize] = {
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            VirtAddr::from_ptr(unsafe { &STACK }) + STACK_SIZE
-        };
-        tss
+/// 任务状态段
+///
+/// 切换进程时要修改其中的内核栈，所以放在`static mut`中，只通过[`init_tss`]和[`set_kernel_stack`]访问。
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+/// 设置各个中断栈，返回初始化好的TSS
+fn init_tss() -> &'static TaskStateSegment {
+    let tss = unsafe { &mut *addr_of_mut!(TSS) };
+    tss.privilege_stack_table[0] = {
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        VirtAddr::from_ptr(unsafe { &STACK }) + STACK_SIZE
+    };
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        VirtAddr::from_ptr(unsafe { &STACK }) + STACK_SIZE
+    };
+    tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        VirtAddr::from_ptr(unsafe { &STACK }) + STACK_SIZE
+    };
+    tss.interrupt_stack_table[GENERAL_PROTECTION_FAULT_IST_INDEX as usize] = {
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        VirtAddr::from_ptr(unsafe { &STACK }) + STACK_SIZE
     };
+    tss
+}
+
+lazy_static! {
     pub static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(init_tss()));
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
         let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
         let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
@@ -71,3 +80,11 @@ pub fn init() {
         load_tss(GDT.1.tss_selector);
     }
 }
+
+/// 设置从用户态进入内核时使用的栈
+///
+/// 每个进程有自己的内核栈，切换进程时调用。
+pub fn set_kernel_stack(top: VirtAddr) {
+    // CPU只在特权级切换时读取TSS，切换进程时中断是关闭的
+    unsafe { (*addr_of_mut!(TSS)).privilege_stack_table[0] = top };
+}
@@ -3,7 +3,7 @@ use alloc::format;
 use x86::io::outw;
 use x86_64::structures::paging::{FrameAllocator, OffsetPageTable, Size4KiB};
 
-use crate::syskrnl::io::pci::{pci_config_read_u32, pci_find_device_by_device_id};
+use crate::syskrnl::io::pci;
 use crate::syskrnl::io::qemu::qemu_print;
 use crate::syskrnl::memory::graphic_support::create_graphic_memory_mapping;
 
@@ -53,11 +53,11 @@ pub unsafe fn bga_enter_graphic_mode(mapper: &mut OffsetPageTable, frame_allocat
     bga_write_register(VbeDispiIndex::Enable as u16, 0x41);
 
     // 获取LFB地址
-    let device = pci_find_device_by_device_id(0x1111, 0x1234);
-    qemu_print(format!("LFB device is {:?}\n", device).as_str());
-    let address = pci_config_read_u32(device.0, device.1, device.2, 0x10);
+    let device = pci::find_by_id(0x1234, 0x1111).expect("找不到BGA显卡");
+    qemu_print(format!("LFB device is {:?}\n", device.location()).as_str());
+    let address = device.bar(0).and_then(|bar| bar.memory_addr()).expect("BGA显卡没有显存BAR");
     qemu_print(format!("We get LFB address:{:?}\n", address).as_str());
 
     // 初始化显存
-    create_graphic_memory_mapping(mapper, frame_allocator, address as u64);
+    create_graphic_memory_mapping(mapper, frame_allocator, address);
 }
@@ -6,7 +6,7 @@ use lazy_static::lazy_static;
 use rusttype::{point, Font, HMetrics, Rect, Scale, ScaledGlyph};
 use spin::RwLock;
 
-use cinea_os_sysapi::fs::{read_all_from_path, FileError};
+use cinea_os_sysapi::fs::FileError;
 use cinea_os_sysapi::gui::{WindowGraphicMemory, WINDOW_CONTENT_HEIGHT, WINDOW_CONTENT_WIDTH};
 
 lazy_static! {
@@ -22,7 +22,7 @@ pub fn load_font(name: &str, path: &str) -> Result<(), FileError> {
         return Ok(());
     }
 
-    let buf = read_all_from_path(path)?;
+    let buf = crate::syskrnl::fs::read_all(path)?;
 
     if let Some(font) = Font::try_from_vec(buf) {
         let mut lock = FONT_MAP.write();
@@ -1,6 +1,7 @@
 use crate::syskrnl::graphic::text::TextWriter;
 use crate::syskrnl::graphic::{GD, GL};
-use crate::{hlt_loop, rgb888};
+use crate::syskrnl::io::HEADLESS;
+use crate::{hlt_loop, println, rgb888};
 
 pub struct PanicInfo<'a> {
     title: &'a str,
@@ -22,6 +23,12 @@ impl<'a> PanicInfo<'a> {
 }
 
 pub fn handle_panic(info: &PanicInfo) -> ! {
+    if HEADLESS {
+        // 没有显存可画，蓝屏的内容输出到串口和VGA文本模式
+        println!("{}\n{}", info.get_title(), info.get_description());
+        hlt_loop();
+    }
+
     let p_lock = GL.read();
     for layer in 0..2 {
         p_lock[layer].lock().enable = true;
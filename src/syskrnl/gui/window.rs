@@ -8,7 +8,6 @@ use lazy_static::lazy_static;
 use spin::{Mutex, RwLock};
 
 use cinea_os_sysapi::event::{gui_event_make_ret, GUI_EVENT_EXIT, GUI_EVENT_MOUSE_CLICK};
-use cinea_os_sysapi::fs::read_all_from_path;
 use cinea_os_sysapi::gui::WindowGraphicMemory;
 pub use cinea_os_sysapi::gui::{WINDOW_HEIGHT, WINDOW_WIDTH};
 
@@ -16,7 +15,7 @@ use crate::rgb888;
 use crate::syskrnl::event::{EVENT_QUEUE, GUI_EID_START};
 use crate::syskrnl::graphic::{resolve_32rgba, GL, HEIGHT, WIDTH};
 use crate::syskrnl::proc::SCHEDULER;
-use crate::syskrnl::{graphic, proc};
+use crate::syskrnl::{fs, graphic, proc};
 
 lazy_static! {
     pub static ref WINDOW_MANAGER: Mutex<WindowManager> = Mutex::new(WindowManager::new());
@@ -223,10 +222,10 @@ impl WindowManager {
 }
 
 pub fn init() {
-    let close_btn = read_all_from_path("/sys/ast/window_close_btn.bmp").expect("Read ASSETS fail");
-    let move_btn = read_all_from_path("/sys/ast/window_move_btn.bmp").expect("Read ASSETS fail");
-    let active = read_all_from_path("/sys/ast/window_active.bmp").expect("Read ASSETS fail");
-    let inactive = read_all_from_path("/sys/ast/window_inactive.bmp").expect("Read ASSETS fail");
+    let close_btn = fs::read_all("/sys/ast/window_close_btn.bmp").expect("Read ASSETS fail");
+    let move_btn = fs::read_all("/sys/ast/window_move_btn.bmp").expect("Read ASSETS fail");
+    let active = fs::read_all("/sys/ast/window_active.bmp").expect("Read ASSETS fail");
+    let inactive = fs::read_all("/sys/ast/window_inactive.bmp").expect("Read ASSETS fail");
     ASSETS
         .write()
         .insert(String::from("WindowCloseBtn"), resolve_32rgba(close_btn.as_slice()));
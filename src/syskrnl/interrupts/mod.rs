@@ -1,9 +1,11 @@
 use alloc::format;
+use alloc::vec::Vec;
 use core::arch::asm;
 use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use lazy_static::lazy_static;
 use spin::Mutex;
+use x86::io::{inb, outb};
 use x86_64::registers::control::Cr3;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, InterruptStackFrameValue, PageFaultErrorCode};
 
@@ -26,6 +28,8 @@ fn default_irq_handler() {}
 
 lazy_static! {
     pub static ref IRQ_HANDLERS: Mutex<[fn(); 16]> = Mutex::new([default_irq_handler; 16]);
+    /// 共享中断线上的处理函数
+    static ref SHARED_IRQ_HANDLERS: Mutex<Vec<fn()>> = Mutex::new(Vec::new());
 
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
@@ -56,6 +60,9 @@ lazy_static! {
             idt[0x82]
                 .set_handler_fn(core::mem::transmute(wrapped_proc_wait as *mut fn()))
                 .set_privilege_level(x86_64::PrivilegeLevel::Ring3);
+            // 内核中让出CPU，只能在内核中使用
+            idt[0x83]
+                .set_handler_fn(core::mem::transmute(wrapped_yield_handler as *mut fn()));
         }
         idt[interrupt_index(1) as usize].set_handler_fn(irq1_handler);
         idt[interrupt_index(2) as usize].set_handler_fn(irq2_handler);
@@ -111,6 +118,34 @@ pub fn set_irq_handler(irq: u8, handler: fn()) {
     handlers[irq as usize] = handler;
 }
 
+/// 为可能与其他设备共享的中断线（如PCI的INTx）添加处理函数
+///
+/// 中断线上的每个处理函数都会被调用，处理函数需要自己检查设备是否真的发出了中断。
+pub fn add_shared_irq_handler(irq: u8, handler: fn()) {
+    SHARED_IRQ_HANDLERS.lock().push(handler);
+    set_irq_handler(irq, shared_irq_handler);
+}
+
+fn shared_irq_handler() {
+    // 处理函数不知道自己被哪条中断线触发，调用所有共享处理函数，多余的调用什么也不做
+    for handler in SHARED_IRQ_HANDLERS.lock().iter() {
+        handler();
+    }
+}
+
+/// 在PIC上取消对某条IRQ线的屏蔽
+pub fn clear_irq_mask(irq: u8) {
+    let port = if irq < 8 { 0x21 } else { 0xA1 };
+    unsafe {
+        let mask = inb(port) & !(1 << (irq % 8));
+        outb(port, mask);
+    }
+    if irq >= 8 {
+        // 从片的中断经由主片的IRQ2级联
+        clear_irq_mask(2);
+    }
+}
+
 pub fn init_idt() {
     IDT.load();
 }
@@ -240,6 +275,9 @@ extern "sysv64" fn syscall_handler(stack_frame: &mut InterruptStackFrame, regs:
 
 unsafe fn switch_context_to(pid: usize, stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
     syskrnl::proc::set_id(pid);
+    if let Some(top) = syskrnl::proc::kernel_stack_top() {
+        syskrnl::gdt::set_kernel_stack(top);
+    }
     let sf = syskrnl::proc::stack_frame();
     //stack_frame.as_mut().write(sf);
     let (_, flags) = Cr3::read();
@@ -260,6 +298,9 @@ extern "sysv64" fn clock_handler(stack_frame: &mut InterruptStackFrame, regs: &m
     let handlers = IRQ_HANDLERS.lock();
     handlers[0]();
 
+    syskrnl::fs::cache::tick();
+    syskrnl::net::tick();
+
     if let Some(pid) = time::check_wakeup() {
         SCHEDULER.lock().wakeup(pid);
     }
@@ -287,11 +328,7 @@ extern "sysv64" fn clock_handler(stack_frame: &mut InterruptStackFrame, regs: &m
                 }
             }
 
-            let lock = syskrnl::event::EVENT_DATA.lock();
-            if lock.contains_key(&next_pid) {
-                regs.rax = *lock.get(&next_pid).unwrap();
-            }
-            drop(lock);
+            deliver_event_data(next_pid, stack_frame, regs);
 
             LAST_SCHEDULE.store(ticks(), Ordering::SeqCst);
         };
@@ -302,6 +339,44 @@ extern "sysv64" fn clock_handler(stack_frame: &mut InterruptStackFrame, regs: &m
     unsafe { pics::PICS.lock().notify_end_of_interrupt(interrupt_index(0) as u8) };
 }
 
+/// 把唤醒进程的事件数据作为返回值交给它
+///
+/// 只对回到用户态的进程有效：在内核中等待的进程（比如等待磁盘）不是通过事件返回的，不能改动它的寄存器。
+fn deliver_event_data(pid: usize, stack_frame: &InterruptStackFrame, regs: &mut Registers) {
+    if stack_frame.code_segment & 3 != 3 {
+        return;
+    }
+    let lock = syskrnl::event::EVENT_DATA.lock();
+    if let Some(ret) = lock.get(&pid) {
+        regs.rax = *ret;
+    }
+}
+
+wrap!(yield_handler => wrapped_yield_handler);
+
+/// 内核中让出CPU：当前进程已经在调度器中等待，切换到`rdi`中的下一个进程
+///
+/// 被唤醒后由时钟中断切换回来，从`int 0x83`之后继续执行。
+extern "sysv64" fn yield_handler(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
+    let next_pid = regs.rdi;
+    if next_pid != syskrnl::proc::id() {
+        syskrnl::proc::set_stack_frame(**stack_frame);
+        syskrnl::proc::set_registers(*regs);
+        unsafe {
+            switch_context_to(next_pid, stack_frame, regs);
+        }
+        deliver_event_data(next_pid, stack_frame, regs);
+        LAST_SCHEDULE.store(ticks(), Ordering::SeqCst);
+    }
+}
+
+/// 在内核中等待时让出CPU，切换到`next_pid`
+///
+/// 调用前当前进程应当已经通过`EVENT_QUEUE`等待，返回时等待的事件已经发生。
+pub fn yield_to(next_pid: usize) {
+    unsafe { asm!("int 0x83", in("rdi") next_pid) };
+}
+
 wrap!(save_context => wrapped_save_context);
 
 extern "sysv64" fn save_context(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
@@ -1,18 +1,27 @@
 use alloc::boxed::Box;
-use alloc::collections::BTreeSet;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
 use alloc::slice;
-use core::alloc::Layout;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
 use core::{mem, ptr};
 
 use lazy_static::lazy_static;
 use spin::Mutex;
 use volatile::Volatile;
-use x86_64::structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PhysFrame, Size4KiB};
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PhysFrame, Size4KiB};
 use x86_64::{PhysAddr, VirtAddr};
 
 use crate::debugln;
-use crate::syskrnl::io;
+use crate::syskrnl;
+use crate::syskrnl::event::{EVENT_QUEUE, KERNEL_EID_START};
+use crate::syskrnl::io::block::{self, BlockDevice};
+use crate::syskrnl::io::pci::{PciDevice, PciDriver, PciMatch};
+use crate::syskrnl::memory;
 use crate::syskrnl::memory::translate_addr;
+use crate::syskrnl::proc::SCHEDULER;
+use crate::syskrnl::sync;
 
 #[repr(u8)]
 pub enum FisType {
@@ -265,7 +274,8 @@ impl HbaCmdHeader {
     }
 }
 
-#[repr(C)]
+/// 命令表，CTBA要求128字节对齐
+#[repr(C, align(128))]
 #[derive(Debug)]
 pub struct HbaCmdTbl {
     // 0x00
@@ -278,31 +288,22 @@ pub struct HbaCmdTbl {
     pub rsv: [u8; 48], // Reserved
 
     // 0x80
-    prdt_entry: [HbaPrdtEntry; 0], // Physical region descriptor table entries, 0 ~ 65535
+    prdt_entry: [HbaPrdtEntry; MAX_PRDT_ENTRIES], // Physical region descriptor table entries
 }
 
 impl HbaCmdTbl {
-    pub fn with_entries(n: usize) -> Box<Self> {
-        let layout = Layout::from_size_align(
-            mem::size_of::<Self>() + n * mem::size_of::<HbaPrdtEntry>(),
-            mem::align_of::<HbaPrdtEntry>(),
-        )
-        .unwrap();
-
-        unsafe {
-            let raw = alloc::alloc::alloc_zeroed(layout);
-            let hba = &mut *(raw as *mut HbaCmdTbl);
-
-            Box::from_raw(hba)
-        }
+    /// 在堆上分配清零的命令表，对齐由类型保证
+    pub fn new() -> Box<Self> {
+        // 全部字段都是整数，全零是合法的值
+        unsafe { Box::<Self>::new_zeroed().assume_init() }
     }
 
     pub fn pred_entries(&mut self, len: usize) -> &[HbaPrdtEntry] {
-        unsafe { slice::from_raw_parts(self.prdt_entry.as_ptr(), len) }
+        &self.prdt_entry[..len]
     }
 
     pub fn prdt_entries_mut(&mut self, len: usize) -> &mut [HbaPrdtEntry] {
-        unsafe { slice::from_raw_parts_mut(self.prdt_entry.as_mut_ptr(), len) }
+        &mut self.prdt_entry[..len]
     }
 }
 
@@ -406,9 +407,6 @@ fn check_type(port: &HbaPort) -> u8 {
     }
 }
 
-const AHCI_BASE: u64 = 0x0100_0000;
-const AHCI_PHYSIC_BASE: u64 = 0x0100_0000;
-
 #[allow(non_upper_case_globals)]
 const HBA_PxCMD_ST: u32 = 0x0001;
 
@@ -421,40 +419,52 @@ const HBA_PxCMD_FR: u32 = 0x4000;
 #[allow(non_upper_case_globals)]
 const HBA_PxCMD_CR: u32 = 0x8000;
 
-pub fn port_rebase(port: &mut HbaPort, port_no: u64) {
+/// 端口的DMA区域：一个物理帧，前1K是命令列表，随后256字节是接收FIS
+///
+/// 命令表在签发命令时从内核堆上分配，不占用这里的空间。重复初始化同一端口时复用已分配的帧。
+fn port_dma_frame(port_no: usize) -> Option<PhysAddr> {
+    let mut regions = PORT_DMA.lock();
+    if let Some(addr) = regions.get(&port_no) {
+        return Some(*addr);
+    }
+    let frame = memory::frame_allocator().allocate_frame()?;
+    regions.insert(port_no, frame.start_address());
+    Some(frame.start_address())
+}
+
+/// 重新设置端口的命令列表和FIS区域，分配不到DMA内存时返回`false`
+pub fn port_rebase(port: &mut HbaPort, port_no: usize) -> bool {
+    let dma = match port_dma_frame(port_no) {
+        Some(addr) => addr,
+        None => {
+            debugln!("Cannot allocate DMA memory for port {}", port_no);
+            return false;
+        }
+    };
+
     // 停止命令引擎
     stop_cmd(port);
 
-    // 命令列表偏移量：1K * port_no
     // 命令列表条目大小：32
     // 命令列表条目最大数量：32
     // 命令列表最大大小：32 * 32 = 1K per Port （看懂了）
-    port.clb.write(AHCI_PHYSIC_BASE + (port_no << 10));
     unsafe {
-        ptr::write_bytes((port.clb.read() - AHCI_PHYSIC_BASE + AHCI_BASE) as *mut u8, 0, 1024);
+        ptr::write_bytes(memory::phys_to_virt(dma).as_mut_ptr::<u8>(), 0, 4096);
     }
+    port.clb.write(dma.as_u64());
 
-    // FIS 偏移量：32K + 256 * port_no
+    // FIS 偏移量：1K
     // FIS 条目大小：256byte per Port
-    port.fb.write(AHCI_PHYSIC_BASE + (32 << 10) + (port_no << 8));
-    unsafe {
-        ptr::write_bytes((port.fb.read() - AHCI_PHYSIC_BASE + AHCI_BASE) as *mut u8, 0, 256);
-    }
+    port.fb.write(dma.as_u64() + (1 << 10));
 
-    // 命令表偏移量：40K + 8K * port_no
-    // 命令表大小：256 * 32 = 8K per Port
-    let cmd_header = unsafe { slice::from_raw_parts_mut((port.clb.read() - AHCI_PHYSIC_BASE + AHCI_BASE) as *mut HbaCmdHeader, 32) };
-    for i in 0..32 {
-        cmd_header[i].prdtl = 8; // 8 prdt entries per command table; 256 bytes per command table, 64+16+48+16*8
-
-        // 命令表偏移量：40K + 8K * port_no +  cmd_header_index * 256
-        cmd_header[i].ctba = AHCI_PHYSIC_BASE + (40 << 10) + (port_no << 13) + ((i as u64) << 8);
-        unsafe {
-            ptr::write_bytes((cmd_header[i].ctba - AHCI_PHYSIC_BASE + AHCI_BASE) as *mut u8, 0, 256);
-        }
+    // 命令表在签发命令时才设置
+    for cmd_header in port.cmd_headers() {
+        cmd_header.prdtl = 0;
+        cmd_header.ctba = 0;
     }
 
     start_cmd(port); // 重启命令引擎
+    true
 }
 
 /// 启动命令引擎
@@ -477,37 +487,61 @@ fn stop_cmd(port: &mut HbaPort) {
     while port.cmd.read() & HBA_PxCMD_FR > 0 || port.cmd.read() & HBA_PxCMD_CR > 0 {}
 }
 
-const ABAR_ADDR: u64 = 0xffffffff_febf1000;
-
-pub fn create_abar_memory_mapping(mapper: &mut OffsetPageTable, frame_allocator: &mut impl FrameAllocator<Size4KiB>) {
-    let controller = io::pci::pci_find_device_by_class_code(0x01, 0x06);
-    let abar_addr = io::pci::pci_config_read_u32(controller.0, controller.1, controller.2, 0x24);
-
-    use x86_64::structures::paging::PageTableFlags as Flags;
-
-    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(ABAR_ADDR));
-    let frame = PhysFrame::containing_address(PhysAddr::new(abar_addr as u64));
-    let flags = Flags::PRESENT | Flags::WRITABLE;
+/// HBA寄存器映射到的内核虚拟地址
+const ABAR_ADDR: u64 = 0xD000_0000;
+/// HBA寄存器占用的页数，32个端口的寄存器共0x1100字节
+const ABAR_PAGES: u64 = 2;
+
+/// AHCI驱动，匹配大容量存储控制器中的SATA控制器
+pub static PCI_DRIVER: PciDriver = PciDriver {
+    name: "ahci",
+    matches: &[PciMatch::class(0x01, 0x06)],
+    probe,
+};
+
+/// 接管AHCI控制器：映射HBA寄存器，扫描端口并复位HBA
+///
+/// 只支持一个控制器，已经接管过时返回`false`。
+fn probe(device: &PciDevice) -> bool {
+    if present() {
+        return false;
+    }
+    // BAR5是HBA寄存器（ABAR）
+    let abar_addr = match device.bar(5).and_then(|bar| bar.memory_addr()) {
+        Some(addr) => addr,
+        None => {
+            debugln!("AHCI controller has no ABAR");
+            return false;
+        }
+    };
+    map_abar(abar_addr);
+    device.enable_bus_mastering();
+    *CONTROLLER.lock() = Some(device.clone());
 
-    let map_to_result = unsafe { mapper.map_to(page, frame, flags, frame_allocator) };
-    map_to_result.expect("Map_to_AHCI aBar Memory Failed").flush();
+    let abar = abar();
+    probe_port(abar);
+    start_hba(abar);
+    enable_interrupts(abar, device);
+    true
 }
 
-pub fn create_ahci_memory_mapping(mapper: &mut OffsetPageTable, frame_allocator: &mut impl FrameAllocator<Size4KiB>) {
+/// 把HBA寄存器映射到`ABAR_ADDR`
+fn map_abar(abar_addr: u64) {
     use x86_64::structures::paging::PageTableFlags as Flags;
 
-    // We need 16 pages
-    for i in 0..16 {
-        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(AHCI_BASE + 0x1000 * i));
-        let frame = PhysFrame::containing_address(PhysAddr::new(AHCI_PHYSIC_BASE + 0x1000 * i));
-        let flags = Flags::PRESENT | Flags::WRITABLE;
-
-        let map_to_result = unsafe { mapper.map_to(page, frame, flags, frame_allocator) };
-        map_to_result.expect("Map_to_AHCI Memory Failed").flush();
+    let mapper = memory::mapper();
+    let mut frame_allocator = memory::frame_allocator();
+    let flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_CACHE | Flags::WRITE_THROUGH;
+    for i in 0..ABAR_PAGES {
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(ABAR_ADDR + 0x1000 * i));
+        let frame = PhysFrame::containing_address(PhysAddr::new(abar_addr + 0x1000 * i));
+        let map_to_result = unsafe { mapper.map_to(page, frame, flags, &mut frame_allocator) };
+        map_to_result.expect("Map_to_AHCI aBar Memory Failed").flush();
     }
 }
 
 const HBA_GHC_HR: u32 = 0x0001; // HBA Reset
+const HBA_GHC_IE: u32 = 0x0002; // Interrupt Enable
 const HBA_GHC_AE: u32 = 0x8000; // AHCI Enable
 
 fn start_hba(abar: &mut HbaMem) {
@@ -517,26 +551,223 @@ fn start_hba(abar: &mut HbaMem) {
     abar.ghc.update(|x| *x |= HBA_GHC_AE);
 }
 
+/// 每个端口的命令槽数量
+const COMMAND_SLOTS: usize = 32;
+
+/// 端口上的命令队列状态，每一位对应一个命令槽
+#[derive(Debug, Clone, Copy, Default)]
+struct CommandQueue {
+    /// 已签发、尚未被等待者回收的命令
+    issued: u32,
+    /// 已完成的命令
+    done: u32,
+    /// 出错的命令
+    failed: u32,
+}
+
 lazy_static! {
+    /// 已接管的AHCI控制器，没有控制器时为`None`
+    static ref CONTROLLER: Mutex<Option<PciDevice>> = Mutex::new(None);
     static ref AVALIABLE_PORTS: Mutex<BTreeSet<usize>> = Mutex::new(BTreeSet::new());
+    static ref PORT_DMA: Mutex<BTreeMap<usize, PhysAddr>> = Mutex::new(BTreeMap::new());
+    static ref PORT_QUEUES: Mutex<[CommandQueue; 32]> = Mutex::new([CommandQueue::default(); 32]);
+}
+
+/// 是否已经启用AHCI中断，未启用时等待命令只能轮询
+static IRQ_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// 等待端口`port_no`上`slot`号命令槽的事件，紧跟在等待锁的事件之后
+fn command_event(port_no: usize, slot: u32) -> usize {
+    KERNEL_EID_START + 1 + port_no * COMMAND_SLOTS + slot as usize
+}
+
+fn abar() -> &'static mut HbaMem {
+    unsafe { &mut *(ABAR_ADDR as *mut HbaMem) }
 }
 
 pub fn get_port(port_no: usize) -> Option<&'static mut HbaPort> {
     if AVALIABLE_PORTS.lock().contains(&port_no) {
-        Some(&mut unsafe { &mut *(ABAR_ADDR as *mut HbaMem) }.ports[port_no])
+        Some(&mut abar().ports[port_no])
     } else {
         None
     }
 }
 
-pub fn translate_phys_address(addr: u64) -> u64 {
-    addr - AHCI_PHYSIC_BASE + AHCI_BASE
+/// 连接了SATA磁盘的端口号，从小到大
+pub fn ports() -> Vec<usize> {
+    AVALIABLE_PORTS.lock().iter().copied().collect()
 }
 
-pub fn init() {
-    let abar = unsafe { &mut *(ABAR_ADDR as *mut HbaMem) };
-    probe_port(abar);
-    start_hba(abar);
+/// 是否找到了AHCI控制器
+pub fn present() -> bool {
+    CONTROLLER.lock().is_some()
+}
+
+#[allow(non_upper_case_globals)]
+const HBA_PxIE_DHRE: u32 = 1 << 0; // Device to Host Register FIS
+#[allow(non_upper_case_globals)]
+const HBA_PxIE_PSE: u32 = 1 << 1; // PIO Setup FIS
+#[allow(non_upper_case_globals)]
+const HBA_PxIE_DSE: u32 = 1 << 2; // DMA Setup FIS
+#[allow(non_upper_case_globals)]
+const HBA_PxIE_SDBE: u32 = 1 << 3; // Set Device Bits FIS
+#[allow(non_upper_case_globals)]
+const HBA_PxIE_TFEE: u32 = 1 << 30; // Task File Error
+
+/// 把HBA的PCI中断线接到PIC上，并打开各端口的中断
+fn enable_interrupts(abar: &mut HbaMem, device: &PciDevice) {
+    let irq = match device.irq() {
+        Some(irq) => irq,
+        None => {
+            debugln!("AHCI controller has no legacy IRQ line, falling back to polling");
+            return;
+        }
+    };
+    device.enable_intx();
+
+    interrupts::without_interrupts(|| {
+        syskrnl::interrupts::add_shared_irq_handler(irq, ahci_interrupt_handler);
+    });
+    for port_no in AVALIABLE_PORTS.lock().iter() {
+        let port = &mut abar.ports[*port_no];
+        port.is.write(u32::MAX);
+        port.ie.write(HBA_PxIE_DHRE | HBA_PxIE_PSE | HBA_PxIE_DSE | HBA_PxIE_SDBE | HBA_PxIE_TFEE);
+    }
+    abar.is.write(u32::MAX);
+    abar.ghc.update(|x| *x |= HBA_GHC_IE);
+    syskrnl::interrupts::clear_irq_mask(irq);
+    IRQ_ENABLED.store(true, Ordering::SeqCst);
+    debugln!("AHCI interrupt on IRQ {}", irq);
+}
+
+/// AHCI中断处理函数：记录各端口上完成的命令
+fn ahci_interrupt_handler() {
+    let abar = abar();
+    let pending = abar.is.read();
+    if pending == 0 {
+        // 中断线可能与其他设备共享
+        return;
+    }
+    let mut queues = PORT_QUEUES.lock();
+    for port_no in 0..32 {
+        if pending & (1 << port_no) != 0 {
+            collect(&mut abar.ports[port_no], &mut queues[port_no]);
+            wake_finished(port_no, &queues[port_no]);
+        }
+    }
+    abar.is.write(pending);
+}
+
+/// 唤醒等待已结束命令的进程
+fn wake_finished(port_no: usize, queue: &CommandQueue) {
+    let finished = queue.issued & (queue.done | queue.failed);
+    let mut events = EVENT_QUEUE.lock();
+    for slot in (0..COMMAND_SLOTS as u32).filter(|slot| finished & (1 << slot) != 0) {
+        while let Some(pid) = events.wakeup(command_event(port_no, slot)) {
+            SCHEDULER.lock().wakeup(pid);
+        }
+    }
+}
+
+/// 根据端口的中断状态和`ci`更新命令队列
+///
+/// 中断处理函数和等待命令的一方都会调用，后者保证中断丢失时也能发现命令已完成。
+fn collect(port: &mut HbaPort, queue: &mut CommandQueue) {
+    let status = port.is.read();
+    port.is.write(status);
+
+    let outstanding = queue.issued & !queue.done & !queue.failed;
+    let running = port.ci.read();
+    queue.done |= outstanding & !running;
+
+    if status & HBA_PxIS_TFES != 0 {
+        // 出错后HBA停止处理命令列表，仍在队列中的命令全部失败，重启命令引擎后才能签发新命令
+        queue.failed |= outstanding & running;
+        stop_cmd(port);
+        port.serr.write(u32::MAX);
+        port.is.write(u32::MAX);
+        start_cmd(port);
+    }
+}
+
+/// 进程能否在等待命令时让出CPU
+///
+/// 需要完成中断来唤醒它；0号进程（内核自己，比如启动时挂载文件系统）不能停止调度，只能等待中断。
+fn can_block() -> bool {
+    IRQ_ENABLED.load(Ordering::SeqCst) && sync::can_block()
+}
+
+/// 等待下一个中断，中断未启用时退化为忙等
+fn idle() {
+    if IRQ_ENABLED.load(Ordering::SeqCst) {
+        // 系统调用中中断是关闭的，临时打开以便收到完成中断
+        let enabled = interrupts::are_enabled();
+        interrupts::enable_and_hlt();
+        if !enabled {
+            interrupts::disable();
+        }
+    } else {
+        core::hint::spin_loop();
+    }
+}
+
+/// 已签发的命令
+///
+/// 持有命令表和缓冲区的借用直到命令完成；丢弃时也会等待完成，避免HBA继续访问已释放的内存。
+pub struct Command<'a> {
+    port_no: usize,
+    slot: u32,
+    table: Option<Box<HbaCmdTbl>>,
+    _buf: PhantomData<&'a mut [u8]>,
+}
+
+impl Command<'_> {
+    /// 等待命令完成，返回是否成功
+    pub fn wait(mut self) -> bool {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> bool {
+        let table = match self.table.take() {
+            Some(table) => table,
+            None => return true,
+        };
+        let mask = 1u32 << self.slot;
+        let ok = loop {
+            let result = interrupts::without_interrupts(|| {
+                let mut queues = PORT_QUEUES.lock();
+                let queue = &mut queues[self.port_no];
+                collect(&mut abar().ports[self.port_no], queue);
+                if (queue.done | queue.failed) & mask == 0 {
+                    // 在检查和登记之间中断是关闭的，完成中断不会被错过
+                    let next_pid = can_block().then(|| EVENT_QUEUE.lock().wait_for(command_event(self.port_no, self.slot)));
+                    return Err(next_pid);
+                }
+                let ok = queue.failed & mask == 0;
+                queue.issued &= !mask;
+                queue.done &= !mask;
+                queue.failed &= !mask;
+                Ok(ok)
+            });
+            match result {
+                Ok(ok) => break ok,
+                // 让出CPU，完成中断唤醒这个进程后再回来检查
+                Err(Some(next_pid)) => syskrnl::interrupts::yield_to(next_pid),
+                Err(None) => idle(),
+            }
+        };
+        if !ok {
+            debugln!("AHCI command failed on port {} slot {}", self.port_no, self.slot);
+        }
+        drop(table);
+        ok
+    }
+}
+
+impl Drop for Command<'_> {
+    fn drop(&mut self) {
+        self.finish();
+    }
 }
 
 const ATA_CMD_READ_DMA_EX: u8 = 0x25;
@@ -549,10 +780,27 @@ const ATA_DEV_DRQ: u8 = 0x08;
 #[allow(non_upper_case_globals)]
 const HBA_PxIS_TFES: u32 = 1 << 30;
 
+/// PRDT条目在页边界处断开：缓冲区虚拟地址连续，物理地址不一定连续
+const PAGE_SIZE: usize = 4096;
+/// 一条命令最多的PRDT条目数，最大的命令跨越的页数
+const MAX_PRDT_ENTRIES: usize = MAX_SECTORS_PER_COMMAND * SECTOR_SIZE / PAGE_SIZE + 1;
+
 impl HbaPort {
-    fn find_cmdslot(&self) -> Option<u32> {
-        let mut slots = self.sact.read() | self.ci.read();
-        for i in 0..32 {
+    /// 端口号，由端口寄存器在HBA内存中的位置算出
+    fn port_no(&self) -> usize {
+        (self as *const HbaPort as u64 - ABAR_ADDR - 0x100) as usize / mem::size_of::<HbaPort>()
+    }
+
+    /// 命令列表中的32个命令头
+    fn cmd_headers(&self) -> &'static mut [HbaCmdHeader] {
+        let addr = memory::phys_to_virt(PhysAddr::new(self.clb.read()));
+        unsafe { slice::from_raw_parts_mut(addr.as_mut_ptr::<HbaCmdHeader>(), COMMAND_SLOTS) }
+    }
+
+    fn find_cmdslot(&self, queue: &CommandQueue) -> Option<u32> {
+        // 已完成但还没被回收的槽也不能复用
+        let mut slots = self.sact.read() | self.ci.read() | queue.issued;
+        for i in 0..COMMAND_SLOTS as u32 {
             if (slots & 1) == 0 {
                 return Some(i);
             }
@@ -562,261 +810,216 @@ impl HbaPort {
         None
     }
 
-    pub fn read(&mut self, pos: u64, count: u32, buf: &mut [u8]) -> bool {
-        if count as usize * 512 > buf.len() {
-            panic!("错误的缓冲区大小")
+    /// 等待设备不再忙，超时返回`false`
+    fn wait_idle(&self) -> bool {
+        let mut spin = 0;
+        while (self.tfd.read() & (ATA_DEV_BUSY | ATA_DEV_DRQ) as u32) > 0 && spin < 1000000 {
+            spin += 1;
         }
+        spin < 1000000
+    }
 
-        self.is.write((-1i32) as u32);
-        let mut spin = 0;
-        if let Some(slot) = self.find_cmdslot() {
-            let cmd_header = unsafe { &mut *((self.clb.read() - AHCI_PHYSIC_BASE + AHCI_BASE) as *mut HbaCmdHeader).add(slot as usize) };
-            cmd_header.set_cfl((mem::size_of::<FisRegH2d>() / mem::size_of::<u32>()) as u8); // Command FIS size
-            cmd_header.set_w(false); // Read from device
-            cmd_header.prdtl = (((count - 1) >> 4) + 1) as u16;
-
-            let mut cmd_table = HbaCmdTbl::with_entries(cmd_header.prdtl as usize);
-
-            let mut buf_addr = unsafe { translate_addr(buf.as_mut_ptr() as u64).unwrap() };
-            let mut count = count;
-            let prdt_entry = cmd_table.prdt_entries_mut(cmd_header.prdtl as usize);
-
-            for i in 0..cmd_header.prdtl as usize {
-                if i < cmd_header.prdtl as usize - 1 {
-                    prdt_entry[i].dba = buf_addr;
-                    prdt_entry[i].set_dbc(8 * 1024 - 1); // 8K bytes (this value should always be set to 1 less than the actual value)
-                    prdt_entry[i].set_i(true);
-                    buf_addr += 8 * 1024; // 8K bytes
-                    count -= 16; // 16 sectors
-                } else {
-                    prdt_entry[i].dba = buf_addr;
-                    prdt_entry[i].set_dbc((count << 9) - 1); // 512 bytes per sector
-                    prdt_entry[i].set_i(true);
-                }
+    /// 签发一条命令，不等待其完成
+    ///
+    /// `buf`是DMA缓冲区的虚拟地址，`len`是其字节数，最多跨越[`MAX_PRDT_ENTRIES`]页。
+    fn issue<'a>(&mut self, command: u8, pos: u64, count: u32, buf: u64, len: usize, write: bool) -> Option<Command<'a>> {
+        let port_no = self.port_no();
+        let mut cmd_table = HbaCmdTbl::new();
+
+        // 每一页单独翻译成物理地址
+        let mut entries = 0;
+        let mut done = 0;
+        while done < len {
+            if entries == MAX_PRDT_ENTRIES {
+                debugln!("AHCI buffer spans too many pages");
+                return None;
             }
+            let start = buf + done as u64;
+            let size = (PAGE_SIZE - start as usize % PAGE_SIZE).min(len - done);
+            let entry = &mut cmd_table.prdt_entry[entries];
+            entry.dba = unsafe { translate_addr(start)? };
+            entry.set_dbc(size as u32 - 1); // this value should always be set to 1 less than the actual value
+            entry.set_i(true);
+            entries += 1;
+            done += size;
+        }
 
-            let cmd_fis = unsafe { &mut *(cmd_table.cfis.as_ptr() as *mut FisRegH2d) };
-            cmd_fis.fis_type = FisType::FisTypeRegH2d as u8;
-            cmd_fis.set_c(1); // Command
-            cmd_fis.command = ATA_CMD_READ_DMA_EX;
+        let cmd_fis = unsafe { &mut *(cmd_table.cfis.as_ptr() as *mut FisRegH2d) };
+        cmd_fis.fis_type = FisType::FisTypeRegH2d as u8;
+        cmd_fis.set_c(1); // Command
+        cmd_fis.command = command;
 
-            cmd_fis.lba0 = pos as u8;
-            cmd_fis.lba1 = (pos >> 8) as u8;
-            cmd_fis.lba2 = (pos >> 16) as u8;
-            cmd_fis.lba3 = (pos >> 24) as u8;
-            cmd_fis.lba4 = (pos >> 32) as u8;
-            cmd_fis.lba5 = (pos >> 40) as u8;
+        cmd_fis.lba0 = pos as u8;
+        cmd_fis.lba1 = (pos >> 8) as u8;
+        cmd_fis.lba2 = (pos >> 16) as u8;
+        cmd_fis.lba3 = (pos >> 24) as u8;
+        cmd_fis.lba4 = (pos >> 32) as u8;
+        cmd_fis.lba5 = (pos >> 40) as u8;
 
-            cmd_fis.device = 1 << 6; // LBA mode
-            cmd_fis.countl = (count & 0xFF) as u8;
-            cmd_fis.counth = ((count >> 8) & 0xFF) as u8;
+        cmd_fis.device = 1 << 6; // LBA mode
+        cmd_fis.countl = (count & 0xFF) as u8;
+        cmd_fis.counth = ((count >> 8) & 0xFF) as u8;
 
-            let cmd_table_addr = Box::into_raw(cmd_table) as u64;
-            cmd_header.ctba = unsafe { translate_addr(cmd_table_addr).unwrap() };
+        let cmd_table_addr = unsafe { translate_addr(cmd_table.as_ref() as *const HbaCmdTbl as u64).unwrap() };
 
-            // The below loop waits until the port is no longer busy before issuing a new command
-            while (self.tfd.read() & (ATA_DEV_BUSY | ATA_DEV_DRQ) as u32) > 0 && spin < 1000000 {
-                spin += 1;
-            }
-            if spin == 1000000 {
+        interrupts::without_interrupts(|| {
+            let mut queues = PORT_QUEUES.lock();
+            let queue = &mut queues[port_no];
+
+            // 端口上没有命令时才检查设备是否忙，队列中的命令由HBA依次执行
+            if queue.issued == 0 && !self.wait_idle() {
                 debugln!("Port is hung");
-                let _ = unsafe { Box::from_raw(cmd_table_addr as *mut HbaCmdTbl) };
-                false
-            } else {
-                self.ci.update(|x| *x |= 1 << slot); // 签发命令
-
-                // Wait for completion
-                loop {
-                    // In some longer duration reads, it may be helpful to spin on the DPS bit
-                    // in the PxIS port field as well (1 << 5)
-                    if self.ci.read() & (1 << slot) == 0 {
-                        break;
-                    }
-                    if self.is.read() & HBA_PxIS_TFES > 0 {
-                        debugln!("Read disk error");
-                        let _ = unsafe { Box::from_raw(cmd_table_addr as *mut HbaCmdTbl) };
-                        return false;
-                    }
-                }
+                return None;
+            }
 
-                let _ = unsafe { Box::from_raw(cmd_table_addr as *mut HbaCmdTbl) };
+            let slot = self.find_cmdslot(queue)?;
+            let cmd_header = &mut self.cmd_headers()[slot as usize];
+            cmd_header.set_cfl((mem::size_of::<FisRegH2d>() / mem::size_of::<u32>()) as u8); // Command FIS size
+            cmd_header.set_w(write);
+            cmd_header.prdtl = entries as u16;
+            cmd_header.prdbc.write(0);
+            cmd_header.ctba = cmd_table_addr;
+
+            queue.issued |= 1 << slot;
+            self.ci.write(1 << slot); // 签发命令，写0的位不受影响
+
+            Some(Command {
+                port_no,
+                slot,
+                table: Some(cmd_table),
+                _buf: PhantomData,
+            })
+        })
+    }
 
-                // Check again
-                if self.is.read() & HBA_PxIS_TFES > 0 {
-                    debugln!("Read disk error");
-                    false
-                } else {
-                    true
-                }
-            }
-        } else {
-            false
+    /// 签发读命令，从`pos`扇区开始读取`count`个扇区
+    pub fn issue_read<'a>(&mut self, pos: u64, count: u32, buf: &'a mut [u8]) -> Option<Command<'a>> {
+        if count as usize * 512 > buf.len() {
+            panic!("错误的缓冲区大小")
         }
+        self.issue(ATA_CMD_READ_DMA_EX, pos, count, buf.as_mut_ptr() as u64, count as usize * 512, false)
     }
 
-    pub fn write(&mut self, pos: u64, count: u32, buf: &[u8]) -> bool {
+    /// 签发写命令，从`pos`扇区开始写入`count`个扇区
+    pub fn issue_write<'a>(&mut self, pos: u64, count: u32, buf: &'a [u8]) -> Option<Command<'a>> {
         if count as usize * 512 > buf.len() {
             panic!("错误的缓冲区大小")
         }
+        self.issue(ATA_CMD_WRITE_DMA_EX, pos, count, buf.as_ptr() as u64, count as usize * 512, true)
+    }
 
-        self.is.write((-1i32) as u32);
-        let mut spin = 0;
-        if let Some(slot) = self.find_cmdslot() {
-            let cmd_header = unsafe { &mut *((self.clb.read() - AHCI_PHYSIC_BASE + AHCI_BASE) as *mut HbaCmdHeader).add(slot as usize) };
-            cmd_header.set_cfl((mem::size_of::<FisRegH2d>() / mem::size_of::<u32>()) as u8); // Command FIS size
-            cmd_header.set_w(true); // Write to device
-            cmd_header.prdtl = (((count - 1) >> 4) + 1) as u16;
-
-            let mut cmd_table = HbaCmdTbl::with_entries(cmd_header.prdtl as usize);
-
-            let mut buf_addr = unsafe { translate_addr(buf.as_ptr() as u64).unwrap() };
-            let mut count = count;
-            let prdt_entry = cmd_table.prdt_entries_mut(cmd_header.prdtl as usize);
-
-            for i in 0..cmd_header.prdtl as usize {
-                if i < cmd_header.prdtl as usize - 1 {
-                    prdt_entry[i].dba = buf_addr;
-                    prdt_entry[i].set_dbc(8 * 1024 - 1); // 8K bytes (this value should always be set to 1 less than the actual value)
-                    prdt_entry[i].set_i(true);
-                    buf_addr += 8 * 1024; // 8K bytes
-                    count -= 16; // 16 sectors
-                } else {
-                    prdt_entry[i].dba = buf_addr;
-                    prdt_entry[i].set_dbc((count << 9) - 1); // 512 bytes per sector
-                    prdt_entry[i].set_i(true);
-                }
-            }
-
-            let cmd_fis = unsafe { &mut *(cmd_table.cfis.as_ptr() as *mut FisRegH2d) };
-            cmd_fis.fis_type = FisType::FisTypeRegH2d as u8;
-            cmd_fis.set_c(1); // Command
-            cmd_fis.command = ATA_CMD_WRITE_DMA_EX;
-
-            cmd_fis.lba0 = pos as u8;
-            cmd_fis.lba1 = (pos >> 8) as u8;
-            cmd_fis.lba2 = (pos >> 16) as u8;
-            cmd_fis.lba3 = (pos >> 24) as u8;
-            cmd_fis.lba4 = (pos >> 32) as u8;
-            cmd_fis.lba5 = (pos >> 40) as u8;
-
-            cmd_fis.device = 1 << 6; // LBA mode
-            cmd_fis.countl = (count & 0xFF) as u8;
-            cmd_fis.counth = ((count >> 8) & 0xFF) as u8;
+    pub fn read(&mut self, pos: u64, count: u32, buf: &mut [u8]) -> bool {
+        match self.issue_read(pos, count, buf) {
+            Some(command) => command.wait(),
+            None => false,
+        }
+    }
 
-            let cmd_table_addr = Box::into_raw(cmd_table) as u64;
-            cmd_header.ctba = unsafe { translate_addr(cmd_table_addr).unwrap() };
+    pub fn write(&mut self, pos: u64, count: u32, buf: &[u8]) -> bool {
+        match self.issue_write(pos, count, buf) {
+            Some(command) => command.wait(),
+            None => false,
+        }
+    }
 
-            // The below loop waits until the port is no longer busy before issuing a new command
-            while (self.tfd.read() & (ATA_DEV_BUSY | ATA_DEV_DRQ) as u32) > 0 && spin < 1000000 {
-                spin += 1;
-            }
-            if spin == 1000000 {
-                debugln!("Port is hung");
-                let _ = unsafe { Box::from_raw(cmd_table_addr as *mut HbaCmdTbl) };
-                false
-            } else {
-                self.ci.update(|x| *x |= 1 << slot); // 签发命令
-
-                // Wait for completion
-                loop {
-                    // In some longer duration reads, it may be helpful to spin on the DPS bit
-                    // in the PxIS port field as well (1 << 5)
-                    if self.ci.read() & (1 << slot) == 0 {
-                        break;
-                    }
-                    if self.is.read() & HBA_PxIS_TFES > 0 {
-                        debugln!("Write disk error");
-                        let _ = unsafe { Box::from_raw(cmd_table_addr as *mut HbaCmdTbl) };
-                        return false;
-                    }
-                }
+    pub fn get_max_sectors(&mut self) -> Option<u64> {
+        let mut buffer = [0u16; 256];
+        let command = self.issue(ATA_CMD_IDENTIFY_DEVICE, 0, 0, buffer.as_mut_ptr() as u64, 512, false)?;
+        if !command.wait() {
+            debugln!("Identify disk error");
+            return None;
+        }
 
-                let _ = unsafe { Box::from_raw(cmd_table_addr as *mut HbaCmdTbl) };
+        if buffer[83] & (1 << 10) > 0 {
+            let lba48_max_sectors =
+                ((buffer[103] as u64) << 48) | ((buffer[102] as u64) << 32) | ((buffer[101] as u64) << 16) | (buffer[100] as u64);
+            Some(lba48_max_sectors)
+        } else {
+            let max_sectors = ((buffer[60] as u64) << 16) | (buffer[61] as u64);
+            Some(max_sectors)
+        }
+    }
+}
 
-                // Check again
-                if self.is.read() & HBA_PxIS_TFES > 0 {
-                    debugln!("Write disk error");
-                    false
-                } else {
-                    true
-                }
+/// 依次签发命令并等待全部完成，同时在队列中的命令不超过命令槽数量
+fn run_queued<'a>(mut commands: impl Iterator<Item = Option<Command<'a>>>) -> Result<(), ()> {
+    let mut pending = VecDeque::new();
+    let mut ok = true;
+    loop {
+        if pending.len() == COMMAND_SLOTS {
+            let command: Command = pending.pop_front().unwrap();
+            ok &= command.wait();
+        }
+        match commands.next() {
+            Some(Some(command)) => pending.push_back(command),
+            Some(None) => {
+                ok = false;
+                break;
             }
-        } else {
-            false
+            None => break,
         }
     }
+    for command in pending {
+        ok &= command.wait();
+    }
+    if ok {
+        Ok(())
+    } else {
+        Err(())
+    }
+}
 
-    pub fn get_max_sectors(&mut self) -> Option<u64> {
-        self.is.write((-1i32) as u32);
-        let mut spin = 0;
-        if let Some(slot) = self.find_cmdslot() {
-            let cmd_header = unsafe { &mut *((self.clb.read() - AHCI_PHYSIC_BASE + AHCI_BASE) as *mut HbaCmdHeader).add(slot as usize) };
-            cmd_header.set_cfl((mem::size_of::<FisRegH2d>() / mem::size_of::<u32>()) as u8); // Command FIS size
-            cmd_header.set_w(false); // Read from device
-            cmd_header.prdtl = 1;
+/// 扇区大小
+pub const SECTOR_SIZE: usize = 512;
+/// 一条命令最多读写的扇区数（32KB）
+const MAX_SECTORS_PER_COMMAND: usize = 64;
 
-            let mut cmd_table = HbaCmdTbl::with_entries(1);
-            let prdt_entry = cmd_table.prdt_entries_mut(1);
+/// AHCI端口上的SATA磁盘
+pub struct AhciDrive {
+    pub port_no: usize,
+    sectors: u64,
+}
 
-            let mut buffer = [0u16; 256];
-            prdt_entry[0].dba = unsafe { translate_addr(buffer.as_mut_ptr() as u64).unwrap() };
-            prdt_entry[0].set_dbc(511);
-            prdt_entry[0].set_i(true);
+impl AhciDrive {
+    /// 初始化端口并读取磁盘容量
+    pub fn open(port_no: usize) -> Option<Self> {
+        let port = get_port(port_no)?;
+        if !port_rebase(port, port_no) {
+            return None;
+        }
+        let sectors = port.get_max_sectors()?;
+        Some(Self { port_no, sectors })
+    }
 
-            let cmd_fis = unsafe { &mut *(cmd_table.cfis.as_ptr() as *mut FisRegH2d) };
-            cmd_fis.fis_type = FisType::FisTypeRegH2d as u8;
-            cmd_fis.set_c(1); // Command
-            cmd_fis.command = ATA_CMD_IDENTIFY_DEVICE;
+    fn port(&self) -> &'static mut HbaPort {
+        get_port(self.port_no).expect("AHCI端口不可用")
+    }
+}
 
-            cmd_fis.device = 1 << 6; // LBA mode
+impl BlockDevice for AhciDrive {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
 
-            let cmd_table_addr = Box::into_raw(cmd_table) as u64;
-            cmd_header.ctba = unsafe { translate_addr(cmd_table_addr).unwrap() };
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
 
-            // The below loop waits until the port is no longer busy before issuing a new command
-            while (self.tfd.read() & (ATA_DEV_BUSY | ATA_DEV_DRQ) as u32) > 0 && spin < 1000000 {
-                spin += 1;
-            }
-            if spin == 1000000 {
-                debugln!("Port is hung");
-                let _ = unsafe { Box::from_raw(cmd_table_addr as *mut HbaCmdTbl) };
-                None
-            } else {
-                self.ci.update(|x| *x |= 1 << slot); // 签发命令
-
-                // Wait for completion
-                loop {
-                    // In some longer duration reads, it may be helpful to spin on the DPS bit
-                    // in the PxIS port field as well (1 << 5)
-                    if self.ci.read() & (1 << slot) == 0 {
-                        break;
-                    }
-                    if self.is.read() & HBA_PxIS_TFES > 0 {
-                        debugln!("Identify disk error");
-                        let _ = unsafe { Box::from_raw(cmd_table_addr as *mut HbaCmdTbl) };
-                        return None;
-                    }
-                }
+    fn read_blocks(&mut self, start: u64, buf: &mut [u8]) -> Result<(), ()> {
+        block::check_range(self, start, buf.len())?;
+        let port = self.port();
+        run_queued(buf.chunks_mut(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE).enumerate().map(|(i, chunk)| {
+            let pos = start + (i * MAX_SECTORS_PER_COMMAND) as u64;
+            port.issue_read(pos, (chunk.len() / SECTOR_SIZE) as u32, chunk)
+        }))
+    }
 
-                let _ = unsafe { Box::from_raw(cmd_table_addr as *mut HbaCmdTbl) };
-
-                // Check again
-                if self.is.read() & HBA_PxIS_TFES > 0 {
-                    debugln!("Identify disk error");
-                    None
-                } else {
-                    if buffer[83] & (1 << 10) > 0 {
-                        let lba48_max_sectors =
-                            ((buffer[103] as u64) << 48) | ((buffer[102] as u64) << 32) | ((buffer[101] as u64) << 16) | (buffer[100] as u64);
-                        Some(lba48_max_sectors)
-                    } else {
-                        let max_sectors = ((buffer[60] as u64) << 16) | (buffer[61] as u64);
-                        Some(max_sectors)
-                    }
-                }
-            }
-        } else {
-            None
-        }
+    fn write_blocks(&mut self, start: u64, buf: &[u8]) -> Result<(), ()> {
+        block::check_range(self, start, buf.len())?;
+        let port = self.port();
+        run_queued(buf.chunks(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE).enumerate().map(|(i, chunk)| {
+            let pos = start + (i * MAX_SECTORS_PER_COMMAND) as u64;
+            port.issue_write(pos, (chunk.len() / SECTOR_SIZE) as u32, chunk)
+        }))
     }
 }
@@ -7,6 +7,7 @@ use lazy_static::lazy_static;
 use spin::Mutex;
 use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
 
+use crate::syskrnl::io::block::{self, BlockDevice};
 use crate::{debugln, syskrnl};
 
 /// ATA设备的块大小
@@ -360,6 +361,32 @@ impl Drive {
     }
 }
 
+impl BlockDevice for Drive {
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.blocks as u64
+    }
+
+    fn read_blocks(&mut self, start: u64, buf: &mut [u8]) -> Result<(), ()> {
+        block::check_range(self, start, buf.len())?;
+        for (i, chunk) in buf.chunks_mut(BLOCK_SIZE).enumerate() {
+            read(self.bus, self.dsk, (start + i as u64) as u32, chunk)?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, start: u64, buf: &[u8]) -> Result<(), ()> {
+        block::check_range(self, start, buf.len())?;
+        for (i, chunk) in buf.chunks(BLOCK_SIZE).enumerate() {
+            write(self.bus, self.dsk, (start + i as u64) as u32, chunk)?;
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Display for Drive {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let (size, unit) = self.humanized_size();
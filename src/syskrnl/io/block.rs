@@ -0,0 +1,85 @@
+//! 块设备抽象
+//!
+//! AHCI、ATA等磁盘驱动都实现[`BlockDevice`]，文件系统经过`fs::cache`块缓存在其上读写，
+//! 而不必关心具体是哪一种磁盘。
+
+#[cfg(test)]
+pub use ram_disk::RamDisk;
+
+/// 按块读写的存储设备
+pub trait BlockDevice: Send {
+    /// 每块的字节数
+    fn block_size(&self) -> usize;
+
+    /// 设备的总块数
+    fn block_count(&self) -> u64;
+
+    /// 从第`start`块开始读取，`buf`的长度必须是块大小的整数倍
+    fn read_blocks(&mut self, start: u64, buf: &mut [u8]) -> Result<(), ()>;
+
+    /// 从第`start`块开始写入，`buf`的长度必须是块大小的整数倍
+    fn write_blocks(&mut self, start: u64, buf: &[u8]) -> Result<(), ()>;
+
+    /// 把设备内部缓存的数据写入介质
+    fn flush(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+/// 检查一次读写是否在设备范围内，返回涉及的块数
+pub fn check_range<D: BlockDevice + ?Sized>(device: &D, start: u64, len: usize) -> Result<u64, ()> {
+    let block_size = device.block_size();
+    if len % block_size != 0 {
+        return Err(());
+    }
+    let count = (len / block_size) as u64;
+    if start + count > device.block_count() {
+        return Err(());
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod ram_disk {
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+
+    use spin::Mutex;
+
+    use super::{check_range, BlockDevice};
+
+    /// 内存中的块设备，供测试使用，数据可以从外面检查
+    pub struct RamDisk {
+        data: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl RamDisk {
+        pub fn new(data: Arc<Mutex<Vec<u8>>>) -> Self {
+            Self { data }
+        }
+    }
+
+    impl BlockDevice for RamDisk {
+        fn block_size(&self) -> usize {
+            512
+        }
+
+        fn block_count(&self) -> u64 {
+            (self.data.lock().len() / 512) as u64
+        }
+
+        fn read_blocks(&mut self, start: u64, buf: &mut [u8]) -> Result<(), ()> {
+            check_range(self, start, buf.len())?;
+            let start = start as usize * 512;
+            buf.copy_from_slice(&self.data.lock()[start..start + buf.len()]);
+            Ok(())
+        }
+
+        fn write_blocks(&mut self, start: u64, buf: &[u8]) -> Result<(), ()> {
+            check_range(self, start, buf.len())?;
+            let start = start as usize * 512;
+            self.data.lock()[start..start + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+    }
+}
@@ -5,9 +5,14 @@ use spin::Mutex;
 
 pub mod ahci;
 pub mod ata;
+pub mod block;
 pub mod mouse;
 pub mod pci;
 pub mod qemu;
+pub mod uart;
+pub mod virtio;
+pub mod virtio_blk;
+pub mod virtio_net;
 
 pub enum VideoMode {
     Text,
@@ -27,6 +32,9 @@ impl VideoMode {
     }
 }
 
+/// 以`headless`特性编译时为真：不进入图形模式，也不启动GUI
+pub const HEADLESS: bool = cfg!(feature = "headless");
+
 lazy_static! {
     pub static ref VIDEO_MODE: Mutex<VideoMode> = Mutex::new(VideoMode::Text);
 }
@@ -1,5 +1,17 @@
+//! PCI总线
+//!
+//! 第一次访问时从0号总线开始递归扫描（沿着PCI-PCI桥进入下级总线），记录所有设备的配置和BAR。
+//! 驱动通过[`register_driver`]注册，按厂商/设备号或类别匹配设备，匹配成功时调用驱动的`probe`。
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use lazy_static::lazy_static;
+use spin::Mutex;
 use x86::io::{inl, outl};
 
+use crate::debugln;
+
 const PCI_CONFIG_ADDRESS: u16 = 0xCF8;
 const PCI_CONFIG_DATA: u16 = 0xCFC;
 
@@ -11,38 +23,415 @@ pub fn pci_config_read_u32(bus: u8, device: u8, function: u8, offset: u8) -> u32
     };
 }
 
-pub fn pci_find_device_by_device_id(device_id: u16, vendor_id: u16) -> (u8, u8, u8) {
-    let target = ((device_id as u32) << 16) + vendor_id as u32;
-    for bus in 0..=255 {
-        for device in 0..32 {
+pub fn pci_config_write_u32(bus: u8, device: u8, function: u8, offset: u8, value: u32) {
+    let addr: u32 = ((bus as u32) << 16) | ((device as u32) << 11) | ((function as u32) << 8) | ((offset as u32) & 0xFC) | 0x8000_0000u32;
+    unsafe {
+        outl(PCI_CONFIG_ADDRESS, addr);
+        outl(PCI_CONFIG_DATA, value);
+    }
+}
+
+const PCI_COMMAND: u8 = 0x04;
+pub const PCI_COMMAND_IO: u16 = 1 << 0;
+pub const PCI_COMMAND_MEMORY: u16 = 1 << 1;
+pub const PCI_COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const PCI_COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+const HEADER_TYPE_GENERAL: u8 = 0x00;
+const HEADER_TYPE_BRIDGE: u8 = 0x01;
+const HEADER_TYPE_MULTIFUNCTION: u8 = 0x80;
+
+/// 基址寄存器（BAR）描述的一段地址空间
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    /// 内存映射区域
+    Memory { addr: u64, size: u64, prefetchable: bool, is_64: bool },
+    /// IO端口区域
+    Io { port: u16, size: u32 },
+}
+
+impl Bar {
+    /// 内存区域的物理地址，IO区域返回`None`
+    pub fn memory_addr(&self) -> Option<u64> {
+        match self {
+            Bar::Memory { addr, .. } => Some(*addr),
+            Bar::Io { .. } => None,
+        }
+    }
+
+    /// IO区域的起始端口，内存区域返回`None`
+    pub fn io_port(&self) -> Option<u16> {
+        match self {
+            Bar::Io { port, .. } => Some(*port),
+            Bar::Memory { .. } => None,
+        }
+    }
+}
+
+/// 根据BAR的原始值和写入全1后读回的值解码
+///
+/// `value`和`mask`对64位BAR是两个寄存器拼起来的值，对32位BAR只有低32位有意义。
+fn decode_bar(value: u64, mask: u64) -> Option<Bar> {
+    if value & 1 == 1 {
+        let mask = (mask as u32) & !0x3;
+        // 有的设备只实现了低16位
+        let size = (!(mask | 0xFFFF_0000)).wrapping_add(1);
+        if mask == 0 {
+            return None;
+        }
+        Some(Bar::Io { port: (value as u32 & !0x3) as u16, size })
+    } else {
+        let is_64 = (value >> 1) & 0x3 == 0x2;
+        let mask = if is_64 { mask & !0xF } else { (mask & 0xFFFF_FFF0) | 0xFFFF_FFFF_0000_0000 };
+        if mask & 0xFFFF_FFFF == 0 && !is_64 {
+            return None;
+        }
+        let size = (!mask).wrapping_add(1);
+        if size == 0 {
+            return None;
+        }
+        let addr = if is_64 { value & !0xF } else { value & 0xFFFF_FFF0 };
+        Some(Bar::Memory {
+            addr,
+            size,
+            prefetchable: value & 0x8 != 0,
+            is_64,
+        })
+    }
+}
+
+/// PCI总线上的一个功能
+#[derive(Debug, Clone)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    /// 头部类型，不含多功能位
+    pub header_type: u8,
+    pub multifunction: bool,
+    /// 中断线（接到PIC上的IRQ号），`0xFF`表示没有连接
+    pub interrupt_line: u8,
+    /// 中断引脚，`0`表示不使用中断，1~4对应INTA#~INTD#
+    pub interrupt_pin: u8,
+    bars: [Option<Bar>; 6],
+}
+
+impl PciDevice {
+    fn probe(bus: u8, device: u8, function: u8) -> Option<Self> {
+        let id = pci_config_read_u32(bus, device, function, 0x00);
+        if id & 0xFFFF == 0xFFFF {
+            return None;
+        }
+        let class = pci_config_read_u32(bus, device, function, 0x08);
+        let header = pci_config_read_u32(bus, device, function, 0x0C);
+        let interrupt = pci_config_read_u32(bus, device, function, 0x3C);
+        let header_type = (header >> 16) as u8;
+        let mut result = Self {
+            bus,
+            device,
+            function,
+            vendor_id: id as u16,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            header_type: header_type & !HEADER_TYPE_MULTIFUNCTION,
+            multifunction: header_type & HEADER_TYPE_MULTIFUNCTION != 0,
+            interrupt_line: interrupt as u8,
+            interrupt_pin: (interrupt >> 8) as u8,
+            bars: [None; 6],
+        };
+        result.probe_bars();
+        Some(result)
+    }
+
+    /// 头部中BAR的个数：普通设备6个，PCI-PCI桥2个
+    fn bar_count(&self) -> u8 {
+        match self.header_type {
+            HEADER_TYPE_GENERAL => 6,
+            HEADER_TYPE_BRIDGE => 2,
+            _ => 0,
+        }
+    }
+
+    /// 探测每个BAR的类型和大小
+    ///
+    /// 写入全1再读回得到地址掩码，探测期间关闭设备的IO和内存译码，结束后恢复。
+    fn probe_bars(&mut self) {
+        let command = self.read_u16(PCI_COMMAND);
+        self.write_u16(PCI_COMMAND, command & !(PCI_COMMAND_IO | PCI_COMMAND_MEMORY));
+
+        let mut index = 0;
+        while index < self.bar_count() {
+            let offset = 0x10 + index * 4;
+            let low = self.read_u32(offset);
+            self.write_u32(offset, 0xFFFF_FFFF);
+            let low_mask = self.read_u32(offset);
+            self.write_u32(offset, low);
+
+            let is_64 = low & 1 == 0 && (low >> 1) & 0x3 == 0x2 && index + 1 < self.bar_count();
+            let (value, mask) = if is_64 {
+                let high = self.read_u32(offset + 4);
+                self.write_u32(offset + 4, 0xFFFF_FFFF);
+                let high_mask = self.read_u32(offset + 4);
+                self.write_u32(offset + 4, high);
+                ((high as u64) << 32 | low as u64, (high_mask as u64) << 32 | low_mask as u64)
+            } else {
+                (low as u64, low_mask as u64)
+            };
+
+            self.bars[index as usize] = decode_bar(value, mask);
+            // 64位BAR占用两个寄存器
+            index += if is_64 { 2 } else { 1 };
+        }
+
+        self.write_u16(PCI_COMMAND, command);
+    }
+
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        pci_config_read_u32(self.bus, self.device, self.function, offset)
+    }
+
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        pci_config_write_u32(self.bus, self.device, self.function, offset, value)
+    }
+
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    /// 写入16位配置寄存器，同一个双字中的另一半保持不变
+    pub fn write_u16(&self, offset: u8, value: u16) {
+        let shift = (offset & 2) * 8;
+        let dword = self.read_u32(offset) & !(0xFFFF << shift);
+        self.write_u32(offset, dword | (value as u32) << shift);
+    }
+
+    /// 第`index`个BAR，64位BAR的高半部分和未实现的BAR返回`None`
+    pub fn bar(&self, index: usize) -> Option<Bar> {
+        self.bars.get(index).copied().flatten()
+    }
+
+    /// 打开命令寄存器中的位
+    pub fn enable(&self, flags: u16) {
+        let command = self.read_u16(PCI_COMMAND);
+        self.write_u16(PCI_COMMAND, command | flags);
+    }
+
+    /// 允许设备主动发起DMA，同时打开内存和IO译码
+    pub fn enable_bus_mastering(&self) {
+        self.enable(PCI_COMMAND_BUS_MASTER | PCI_COMMAND_MEMORY | PCI_COMMAND_IO);
+    }
+
+    /// 允许设备通过中断线发出中断
+    pub fn enable_intx(&self) {
+        let command = self.read_u16(PCI_COMMAND);
+        self.write_u16(PCI_COMMAND, command & !PCI_COMMAND_INTX_DISABLE);
+    }
+
+    /// 连接到PIC的IRQ号，没有连接时返回`None`
+    pub fn irq(&self) -> Option<u8> {
+        if self.interrupt_pin == 0 || self.interrupt_line >= 16 {
+            None
+        } else {
+            Some(self.interrupt_line)
+        }
+    }
+
+    /// 设备位置（总线，设备，功能）
+    pub fn location(&self) -> (u8, u8, u8) {
+        (self.bus, self.device, self.function)
+    }
+}
+
+/// 驱动匹配规则，为`None`的字段匹配任意值
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PciMatch {
+    pub vendor_id: Option<u16>,
+    pub device_id: Option<u16>,
+    pub class: Option<u8>,
+    pub subclass: Option<u8>,
+}
+
+impl PciMatch {
+    /// 按厂商号和设备号匹配
+    pub const fn id(vendor_id: u16, device_id: u16) -> Self {
+        Self {
+            vendor_id: Some(vendor_id),
+            device_id: Some(device_id),
+            class: None,
+            subclass: None,
+        }
+    }
+
+    /// 按类别和子类别匹配
+    pub const fn class(class: u8, subclass: u8) -> Self {
+        Self {
+            vendor_id: None,
+            device_id: None,
+            class: Some(class),
+            subclass: Some(subclass),
+        }
+    }
+
+    pub fn matches(&self, device: &PciDevice) -> bool {
+        self.vendor_id.map_or(true, |x| x == device.vendor_id)
+            && self.device_id.map_or(true, |x| x == device.device_id)
+            && self.class.map_or(true, |x| x == device.class)
+            && self.subclass.map_or(true, |x| x == device.subclass)
+    }
+}
+
+/// PCI驱动
+pub struct PciDriver {
+    pub name: &'static str,
+    /// 满足其中任意一条规则的设备会交给驱动
+    pub matches: &'static [PciMatch],
+    /// 初始化设备，返回`true`表示接管了设备，之后不会再交给其他驱动
+    pub probe: fn(&PciDevice) -> bool,
+}
+
+lazy_static! {
+    static ref PCI_DEVICES: Vec<PciDevice> = enumerate();
+    /// 已被驱动接管的设备及驱动名
+    static ref BOUND_DEVICES: Mutex<BTreeMap<(u8, u8, u8), &'static str>> = Mutex::new(BTreeMap::new());
+}
+
+fn enumerate() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+    match PciDevice::probe(0, 0, 0) {
+        Some(host) if host.multifunction => {
+            // 有多个主桥，每个功能负责一条总线
             for function in 0..8 {
-                // qemu_print(format!("{},{},{}", bus, device, function).as_str());
-                if pci_config_read_u32(bus, device, function, 0) == target {
-                    return (bus, device, function);
+                if PciDevice::probe(0, 0, function).is_some() {
+                    scan_bus(function, &mut devices);
                 }
             }
         }
+        _ => scan_bus(0, &mut devices),
     }
-
-    // 找不到，找不到
-    (0xFF, 0xFF, 0xFF)
+    devices
 }
 
-pub fn pci_find_device_by_class_code(class_code: u8, subclass: u8) -> (u8, u8, u8) {
-    let target = ((class_code as u32) << 24) + ((subclass as u32) << 16);
-    // debugln!("TARGET: {}", target);
-    let mask = 0xFFFF0000u32;
-    for bus in 0..=255 {
-        for device in 0..32 {
-            for function in 0..8 {
-                // qemu_print(format!("{},{},{}", bus, device, function).as_str());
-                if pci_config_read_u32(bus, device, function, 8) & mask == target {
-                    return (bus, device, function);
+fn scan_bus(bus: u8, devices: &mut Vec<PciDevice>) {
+    for device in 0..32 {
+        let first = match PciDevice::probe(bus, device, 0) {
+            Some(first) => first,
+            None => continue,
+        };
+        let functions = if first.multifunction { 8 } else { 1 };
+        for function in 0..functions {
+            if let Some(found) = PciDevice::probe(bus, device, function) {
+                // PCI-PCI桥：进入下级总线
+                if found.class == 0x06 && found.subclass == 0x04 {
+                    let secondary = (found.read_u32(0x18) >> 8) as u8;
+                    if secondary > bus {
+                        scan_bus(secondary, devices);
+                    }
                 }
+                devices.push(found);
             }
         }
     }
+}
+
+/// 所有PCI设备
+pub fn devices() -> &'static [PciDevice] {
+    PCI_DEVICES.as_slice()
+}
+
+/// 按厂商号和设备号查找
+pub fn find_by_id(vendor_id: u16, device_id: u16) -> Option<&'static PciDevice> {
+    devices().iter().find(|x| x.vendor_id == vendor_id && x.device_id == device_id)
+}
+
+/// 按类别和子类别查找第一个设备
+pub fn find_by_class(class: u8, subclass: u8) -> Option<&'static PciDevice> {
+    devices().iter().find(|x| x.class == class && x.subclass == subclass)
+}
+
+/// 注册驱动，并立即把已经枚举到的、尚未被接管的匹配设备交给它
+pub fn register_driver(driver: &'static PciDriver) {
+    for device in devices() {
+        if BOUND_DEVICES.lock().contains_key(&device.location()) {
+            continue;
+        }
+        if driver.matches.iter().any(|x| x.matches(device)) && (driver.probe)(device) {
+            debugln!(
+                "PCI {:02x}:{:02x}.{} {:04x}:{:04x} bound to {}",
+                device.bus,
+                device.device,
+                device.function,
+                device.vendor_id,
+                device.device_id,
+                driver.name
+            );
+            BOUND_DEVICES.lock().insert(device.location(), driver.name);
+        }
+    }
+}
+
+/// 接管设备的驱动名
+pub fn driver_of(device: &PciDevice) -> Option<&'static str> {
+    BOUND_DEVICES.lock().get(&device.location()).copied()
+}
+
+/// 枚举总线并注册内核自带的驱动
+pub fn init() {
+    for device in devices() {
+        debugln!(
+            "PCI {:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}.{:02x}",
+            device.bus,
+            device.device,
+            device.function,
+            device.vendor_id,
+            device.device_id,
+            device.class,
+            device.subclass
+        );
+    }
+    register_driver(&super::ahci::PCI_DRIVER);
+    register_driver(&super::virtio_blk::PCI_DRIVER);
+    register_driver(&super::virtio_net::PCI_DRIVER);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_bar, Bar};
 
-    // 找不到，找不到
-    (0xFF, 0xFF, 0xFF)
+    #[test_case]
+    fn test_decode_bar() {
+        // 32位内存BAR，4KB
+        assert_eq!(
+            decode_bar(0xFEBF_1000, 0xFFFF_F000),
+            Some(Bar::Memory {
+                addr: 0xFEBF_1000,
+                size: 0x1000,
+                prefetchable: false,
+                is_64: false
+            })
+        );
+        // 64位可预取内存BAR，16KB
+        assert_eq!(
+            decode_bar(0x0000_0080_0000_000C, 0xFFFF_FFFF_FFFF_C00C),
+            Some(Bar::Memory {
+                addr: 0x80_0000_0000,
+                size: 0x4000,
+                prefetchable: true,
+                is_64: true
+            })
+        );
+        // IO BAR，32个端口
+        assert_eq!(decode_bar(0xC041, 0xFFFF_FFE1), Some(Bar::Io { port: 0xC040, size: 32 }));
+        // 未实现的BAR
+        assert_eq!(decode_bar(0, 0), None);
+        println!("[ok]  PCI BAR decoding")
+    }
 }
@@ -5,6 +5,10 @@ use spin::Mutex;
 use x86::io::outb;
 use x86_64::instructions::interrupts;
 
+use cinea_os_sysapi::fs::FileIO;
+
+use crate::syskrnl::io::uart;
+
 #[repr(u16)]
 enum IoPort {
     Com1 = 0x3F8,
@@ -38,3 +42,23 @@ pub fn _qemu_print(args: fmt::Arguments) {
         QEMU_WRITER.lock().write_fmt(args).unwrap();
     })
 }
+
+/// 串口设备，`/dev/serial0`
+///
+/// 读取串口收到的原始字节，不经过解码，也不和`getch`、`/dev/stdin`争抢输入；没有数据时不会阻塞。
+pub struct SerialDevice;
+
+impl FileIO for SerialDevice {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        Ok(uart::read(buf))
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        interrupts::without_interrupts(|| {
+            for ch in buf {
+                unsafe { outb(IoPort::Com1 as u16, *ch) };
+            }
+        });
+        Ok(buf.len())
+    }
+}
@@ -0,0 +1,98 @@
+//! 16550 UART串口驱动
+//!
+//! COM1在QEMU中对应`-serial stdio`。输出仍由[`qemu`](super::qemu)直接写数据寄存器；
+//! 输入使用接收中断（IRQ4），中断里只取出字节：原始字节暂存给`/dev/serial0`，
+//! 同时送去解码成键盘输入，解码见`task::serial`。
+
+use alloc::collections::VecDeque;
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86::io::{inb, outb};
+use x86_64::instructions::interrupts;
+
+use crate::syskrnl;
+use crate::syskrnl::task::serial::add_byte;
+
+const COM1: u16 = 0x3F8;
+const COM1_IRQ: u8 = 4;
+
+// 相对于端口基址的寄存器偏移
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+/// 线路控制寄存器：允许访问波特率除数
+const LINE_CONTROL_DLAB: u8 = 0x80;
+/// 线路控制寄存器：8位数据、无校验、1位停止位
+const LINE_CONTROL_8N1: u8 = 0x03;
+/// FIFO控制寄存器：启用并清空FIFO，收到14字节时触发中断
+const FIFO_ENABLE_CLEAR_14: u8 = 0xC7;
+/// 调制解调器控制寄存器：DTR、RTS和OUT2，OUT2打开后中断才会送到PIC
+const MODEM_DTR_RTS_OUT2: u8 = 0x0B;
+/// 中断使能寄存器：收到数据时中断
+const INTERRUPT_DATA_AVAILABLE: u8 = 0x01;
+/// 线路状态寄存器：接收缓冲区有数据
+const LINE_STATUS_DATA_READY: u8 = 0x01;
+
+/// 115200 / 3 = 38400波特
+const BAUD_DIVISOR: u16 = 3;
+
+const RX_BUFFER_SIZE: usize = 4096;
+
+lazy_static! {
+    /// 还没有从`/dev/serial0`读走的原始字节，满了以后丢弃最早的字节
+    static ref RX_BUFFER: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::with_capacity(RX_BUFFER_SIZE));
+}
+
+/// 串口接收中断处理函数，取出FIFO中所有的字节
+fn uart_interrupt_handler() {
+    let mut buffer = RX_BUFFER.lock();
+    while unsafe { inb(COM1 + LINE_STATUS) } & LINE_STATUS_DATA_READY != 0 {
+        let byte = unsafe { inb(COM1 + DATA) };
+        if buffer.len() >= RX_BUFFER_SIZE {
+            buffer.pop_front();
+        }
+        buffer.push_back(byte);
+        add_byte(byte);
+    }
+}
+
+/// 取出收到的原始字节，没有数据时返回0，不会阻塞
+pub fn read(buf: &mut [u8]) -> usize {
+    interrupts::without_interrupts(|| {
+        let mut buffer = RX_BUFFER.lock();
+        let len = buf.len().min(buffer.len());
+        for (dst, src) in buf.iter_mut().zip(buffer.drain(..len)) {
+            *dst = src;
+        }
+        len
+    })
+}
+
+/// 配置COM1并打开接收中断
+pub fn init() {
+    interrupts::without_interrupts(|| {
+        unsafe {
+            outb(COM1 + INTERRUPT_ENABLE, 0x00);
+            outb(COM1 + LINE_CONTROL, LINE_CONTROL_DLAB);
+            outb(COM1 + DATA, (BAUD_DIVISOR & 0xFF) as u8);
+            outb(COM1 + INTERRUPT_ENABLE, (BAUD_DIVISOR >> 8) as u8);
+            outb(COM1 + LINE_CONTROL, LINE_CONTROL_8N1);
+            outb(COM1 + FIFO_CONTROL, FIFO_ENABLE_CLEAR_14);
+            outb(COM1 + MODEM_CONTROL, MODEM_DTR_RTS_OUT2);
+        }
+        syskrnl::interrupts::set_irq_handler(COM1_IRQ, uart_interrupt_handler);
+        unsafe {
+            outb(COM1 + INTERRUPT_ENABLE, INTERRUPT_DATA_AVAILABLE);
+            // 丢弃配置前残留的字节
+            while inb(COM1 + LINE_STATUS) & LINE_STATUS_DATA_READY != 0 {
+                inb(COM1 + DATA);
+            }
+        }
+        syskrnl::interrupts::clear_irq_mask(COM1_IRQ);
+    });
+}
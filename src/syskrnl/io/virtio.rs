@@ -0,0 +1,331 @@
+//! virtio设备的传统（legacy）PCI接口
+//!
+//! QEMU的virtio设备默认是过渡设备，同时提供传统接口和新接口。这里只用传统接口：
+//! 寄存器在BAR0的IO端口中，每个virtqueue是一段物理连续的内存，把页帧号写给设备即可。
+//!
+//! 具体设备的驱动（如[`super::virtio_blk`]）用[`VirtioDevice`]完成初始化，再通过[`Virtqueue`]收发请求。
+
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, Ordering};
+use core::{mem, ptr};
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86::io::{inb, inl, inw, outb, outl, outw};
+use x86_64::instructions::interrupts;
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::syskrnl;
+use crate::syskrnl::io::pci::PciDevice;
+use crate::syskrnl::memory;
+
+/// virtio设备的PCI厂商号
+pub const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+
+const REG_DEVICE_FEATURES: u16 = 0x00;
+const REG_GUEST_FEATURES: u16 = 0x04;
+const REG_QUEUE_PFN: u16 = 0x08;
+const REG_QUEUE_SIZE: u16 = 0x0C;
+const REG_QUEUE_SELECT: u16 = 0x0E;
+const REG_QUEUE_NOTIFY: u16 = 0x10;
+const REG_DEVICE_STATUS: u16 = 0x12;
+const REG_ISR_STATUS: u16 = 0x13;
+/// 设备专用配置的起始位置（未启用MSI-X时）
+const REG_DEVICE_CONFIG: u16 = 0x14;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FAILED: u8 = 0x80;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+const PAGE_SIZE: usize = 4096;
+
+lazy_static! {
+    /// 已启用中断的设备的IO基址，中断处理函数逐个读取它们的ISR
+    static ref IRQ_DEVICES: Mutex<Vec<u16>> = Mutex::new(Vec::new());
+}
+
+/// 使用传统接口的virtio设备
+pub struct VirtioDevice {
+    io_base: u16,
+    irq_enabled: bool,
+}
+
+impl VirtioDevice {
+    /// 复位设备并告知设备已找到驱动，BAR0不是IO区域时返回`None`
+    pub fn new(device: &PciDevice) -> Option<Self> {
+        let io_base = device.bar(0)?.io_port()?;
+        device.enable_bus_mastering();
+        let result = Self { io_base, irq_enabled: false };
+        result.set_status(0);
+        result.set_status(STATUS_ACKNOWLEDGE);
+        result.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        Some(result)
+    }
+
+    fn status(&self) -> u8 {
+        unsafe { inb(self.io_base + REG_DEVICE_STATUS) }
+    }
+
+    fn set_status(&self, status: u8) {
+        unsafe { outb(self.io_base + REG_DEVICE_STATUS, status) }
+    }
+
+    /// 设备支持的特性位
+    pub fn device_features(&self) -> u32 {
+        unsafe { inl(self.io_base + REG_DEVICE_FEATURES) }
+    }
+
+    /// 告诉设备驱动使用的特性，只能是[`Self::device_features`]的子集
+    pub fn set_features(&self, features: u32) {
+        unsafe { outl(self.io_base + REG_GUEST_FEATURES, features) }
+    }
+
+    pub fn config_u8(&self, offset: u16) -> u8 {
+        unsafe { inb(self.io_base + REG_DEVICE_CONFIG + offset) }
+    }
+
+    pub fn config_u32(&self, offset: u16) -> u32 {
+        unsafe { inl(self.io_base + REG_DEVICE_CONFIG + offset) }
+    }
+
+    pub fn config_u64(&self, offset: u16) -> u64 {
+        self.config_u32(offset) as u64 | (self.config_u32(offset + 4) as u64) << 32
+    }
+
+    /// 为第`index`个队列分配内存并交给设备，队列不存在或分配不到内存时返回`None`
+    pub fn setup_queue(&self, index: u16) -> Option<Virtqueue> {
+        unsafe { outw(self.io_base + REG_QUEUE_SELECT, index) };
+        let size = unsafe { inw(self.io_base + REG_QUEUE_SIZE) };
+        if size == 0 {
+            return None;
+        }
+        let layout = QueueLayout::new(size);
+        let phys = memory::allocate_contiguous_frames(layout.total / PAGE_SIZE)?;
+        let base = memory::phys_to_virt(phys);
+        unsafe {
+            ptr::write_bytes(base.as_mut_ptr::<u8>(), 0, layout.total);
+            outl(self.io_base + REG_QUEUE_PFN, (phys.as_u64() / PAGE_SIZE as u64) as u32);
+        }
+        Some(Virtqueue::new(index, size, base, layout))
+    }
+
+    /// 初始化完成，设备开始处理队列
+    pub fn driver_ok(&self) {
+        self.set_status(self.status() | STATUS_DRIVER_OK);
+    }
+
+    /// 初始化失败，设备停止工作
+    pub fn fail(&self) {
+        self.set_status(self.status() | STATUS_FAILED);
+    }
+
+    /// 通知设备队列中有新的请求
+    pub fn notify(&self, queue: &Virtqueue) {
+        fence(Ordering::SeqCst);
+        unsafe { outw(self.io_base + REG_QUEUE_NOTIFY, queue.index) }
+    }
+
+    /// 把设备的PCI中断线接到PIC上，没有中断线时等待队列只能轮询
+    pub fn enable_interrupts(&mut self, device: &PciDevice) {
+        let irq = match device.irq() {
+            Some(irq) => irq,
+            None => return,
+        };
+        device.enable_intx();
+        interrupts::without_interrupts(|| {
+            IRQ_DEVICES.lock().push(self.io_base);
+            syskrnl::interrupts::add_shared_irq_handler(irq, virtio_interrupt_handler);
+        });
+        syskrnl::interrupts::clear_irq_mask(irq);
+        self.irq_enabled = true;
+    }
+
+    /// 等待下一个中断，中断未启用时退化为忙等
+    pub fn idle(&self) {
+        if self.irq_enabled {
+            // 系统调用中中断是关闭的，临时打开以便收到完成中断
+            let enabled = interrupts::are_enabled();
+            interrupts::enable_and_hlt();
+            if !enabled {
+                interrupts::disable();
+            }
+        } else {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// virtio中断处理函数：读取ISR以清除中断，已完成的请求由等待者从队列中取出
+fn virtio_interrupt_handler() {
+    for io_base in IRQ_DEVICES.lock().iter() {
+        unsafe { inb(io_base + REG_ISR_STATUS) };
+    }
+}
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// 传统接口要求的队列内存布局：描述符表和可用环相连，已用环从下一页开始
+struct QueueLayout {
+    avail: usize,
+    used: usize,
+    total: usize,
+}
+
+impl QueueLayout {
+    fn new(size: u16) -> Self {
+        let size = size as usize;
+        let avail = mem::size_of::<Descriptor>() * size;
+        let used = align_up(avail + 6 + 2 * size, PAGE_SIZE);
+        let total = align_up(used + 6 + mem::size_of::<UsedElem>() * size, PAGE_SIZE);
+        Self { avail, used, total }
+    }
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) / align * align
+}
+
+/// 交给设备的一段缓冲区
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    pub addr: PhysAddr,
+    pub len: u32,
+    /// 设备向缓冲区写入（读请求的数据区和状态字节）
+    pub device_writable: bool,
+}
+
+/// 把一段内核虚拟内存拆成物理连续的若干段，在页边界处断开
+pub fn buffers(addr: VirtAddr, len: usize, device_writable: bool) -> Option<Vec<Buffer>> {
+    let mut result = Vec::new();
+    let mut done = 0;
+    while done < len {
+        let start = addr + done as u64;
+        let count = (PAGE_SIZE - (start.as_u64() as usize % PAGE_SIZE)).min(len - done);
+        result.push(Buffer {
+            addr: memory::virt_to_phys(start)?,
+            len: count as u32,
+            device_writable,
+        });
+        done += count;
+    }
+    Some(result)
+}
+
+/// 一个virtqueue
+///
+/// 驱动把由若干缓冲区组成的请求放入可用环，设备处理完后放入已用环，并写回写入的字节数。
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    desc: *mut Descriptor,
+    avail: *mut u16,
+    used: *mut u8,
+    /// 空闲的描述符
+    free: Vec<u16>,
+    /// 下一个要取出的已用环位置
+    last_used: u16,
+}
+
+// 队列内存只通过持有`Virtqueue`的一方访问
+unsafe impl Send for Virtqueue {}
+
+impl Virtqueue {
+    fn new(index: u16, size: u16, base: VirtAddr, layout: QueueLayout) -> Self {
+        let base = base.as_u64() as usize;
+        Self {
+            index,
+            size,
+            desc: base as *mut Descriptor,
+            avail: (base + layout.avail) as *mut u16,
+            used: (base + layout.used) as *mut u8,
+            free: (0..size).rev().collect(),
+            last_used: 0,
+        }
+    }
+
+    /// 队列能容纳的描述符数
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    /// 空闲的描述符数，一个请求的每段缓冲区占用一个
+    pub fn free_descriptors(&self) -> usize {
+        self.free.len()
+    }
+
+    /// 把请求放入可用环，返回请求的编号（首个描述符）；描述符不够时返回`None`
+    ///
+    /// 放入后还需要[`VirtioDevice::notify`]设备。缓冲区在请求完成前不能被释放。
+    pub fn push(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > self.free.len() {
+            return None;
+        }
+        let ids: Vec<u16> = (0..buffers.len()).map(|_| self.free.pop().unwrap()).collect();
+        for (i, buffer) in buffers.iter().enumerate() {
+            let mut flags = if buffer.device_writable { DESC_F_WRITE } else { 0 };
+            let next = ids.get(i + 1).copied().unwrap_or(0);
+            if i + 1 < ids.len() {
+                flags |= DESC_F_NEXT;
+            }
+            let descriptor = Descriptor {
+                addr: buffer.addr.as_u64(),
+                len: buffer.len,
+                flags,
+                next,
+            };
+            unsafe { ptr::write_volatile(self.desc.add(ids[i] as usize), descriptor) };
+        }
+
+        unsafe {
+            // 可用环：flags, idx, ring[size]
+            let idx = ptr::read_volatile(self.avail.add(1));
+            ptr::write_volatile(self.avail.add(2 + (idx % self.size) as usize), ids[0]);
+            fence(Ordering::SeqCst);
+            ptr::write_volatile(self.avail.add(1), idx.wrapping_add(1));
+        }
+        Some(ids[0])
+    }
+
+    /// 取出一个已完成的请求，返回请求编号和设备写入的字节数
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        // 已用环：flags, idx, ring[size]
+        let idx = unsafe { ptr::read_volatile((self.used as *const u16).add(1)) };
+        if idx == self.last_used {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let elem = unsafe {
+            let ring = self.used.add(4) as *const UsedElem;
+            ptr::read_volatile(ring.add((self.last_used % self.size) as usize))
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        // 回收整条描述符链
+        let mut id = elem.id as u16;
+        loop {
+            self.free.push(id);
+            let descriptor = unsafe { ptr::read_volatile(self.desc.add(id as usize)) };
+            if descriptor.flags & DESC_F_NEXT == 0 {
+                break;
+            }
+            id = descriptor.next;
+        }
+        Some((elem.id as u16, elem.len))
+    }
+}
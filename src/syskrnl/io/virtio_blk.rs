@@ -0,0 +1,257 @@
+//! virtio块设备驱动
+//!
+//! QEMU中用`-drive if=virtio`添加的磁盘。每个请求由三部分组成：请求头、数据区和设备写回的状态字节，
+//! 所有请求都放在0号队列中；一次读写拆成多个请求同时签发，全部完成后返回。
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ptr;
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+use crate::debugln;
+use crate::syskrnl::io::block::{self, BlockDevice};
+use crate::syskrnl::io::pci::{PciDevice, PciDriver, PciMatch};
+use crate::syskrnl::io::virtio::{self, Buffer, VirtioDevice, Virtqueue, VIRTIO_VENDOR_ID};
+use crate::syskrnl::memory;
+
+/// 传统接口的virtio块设备的设备号
+const VIRTIO_BLK_DEVICE_ID: u16 = 0x1001;
+
+const VIRTIO_BLK_F_RO: u32 = 1 << 5;
+const VIRTIO_BLK_F_FLUSH: u32 = 1 << 9;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+
+/// 扇区大小，请求中的扇区号和容量都以它为单位
+pub const SECTOR_SIZE: usize = 512;
+/// 一个请求最多读写的扇区数（32KB）
+const MAX_SECTORS_PER_REQUEST: usize = 64;
+
+/// virtio块设备驱动
+pub static PCI_DRIVER: PciDriver = PciDriver {
+    name: "virtio-blk",
+    matches: &[PciMatch::id(VIRTIO_VENDOR_ID, VIRTIO_BLK_DEVICE_ID)],
+    probe,
+};
+
+lazy_static! {
+    static ref DISKS: Mutex<Vec<VirtioBlk>> = Mutex::new(Vec::new());
+}
+
+/// 请求头和状态字节
+///
+/// 按32字节对齐，整个结构不会跨页，两部分都可以用同一页的物理地址交给设备。请求头只由设备读取。
+#[allow(dead_code)]
+#[repr(C, align(32))]
+struct Request {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+    status: u8,
+}
+
+impl Request {
+    fn new(kind: u32, sector: u64) -> Box<Self> {
+        Box::new(Self {
+            kind,
+            reserved: 0,
+            sector,
+            status: 0xFF,
+        })
+    }
+
+    /// 请求头和状态字节对应的缓冲区，`data`放在两者之间
+    fn buffers(&self, data: Vec<Buffer>) -> Option<Vec<Buffer>> {
+        let header = memory::virt_to_phys(VirtAddr::from_ptr(self))?;
+        let mut result = vec![Buffer {
+            addr: header,
+            len: 16,
+            device_writable: false,
+        }];
+        result.extend(data);
+        result.push(Buffer {
+            addr: header + 16u64,
+            len: 1,
+            device_writable: true,
+        });
+        Some(result)
+    }
+
+    fn succeeded(&self) -> bool {
+        unsafe { ptr::read_volatile(&self.status) == VIRTIO_BLK_S_OK }
+    }
+}
+
+struct VirtioBlk {
+    device: VirtioDevice,
+    queue: Virtqueue,
+    sectors: u64,
+    read_only: bool,
+    flush: bool,
+}
+
+impl VirtioBlk {
+    /// 从`start`扇区开始读写`len`字节，数据在`addr`处；请求尽量同时签发，全部完成后返回
+    fn transfer(&mut self, kind: u32, start: u64, addr: VirtAddr, len: usize) -> Result<(), ()> {
+        let mut pending: BTreeMap<u16, Box<Request>> = BTreeMap::new();
+        let mut ok = true;
+        let mut offset = 0;
+        while offset < len || !pending.is_empty() {
+            let mut issued = false;
+            while offset < len {
+                let count = (MAX_SECTORS_PER_REQUEST * SECTOR_SIZE).min(len - offset);
+                let request = Request::new(kind, start + (offset / SECTOR_SIZE) as u64);
+                let buffers = virtio::buffers(addr + offset as u64, count, kind == VIRTIO_BLK_T_IN)
+                    .and_then(|data| request.buffers(data));
+                let buffers = match buffers {
+                    Some(buffers) => buffers,
+                    None => {
+                        // 缓冲区没有映射，不再签发，等已签发的请求完成
+                        ok = false;
+                        offset = len;
+                        break;
+                    }
+                };
+                match self.queue.push(&buffers) {
+                    Some(id) => {
+                        pending.insert(id, request);
+                        offset += count;
+                        issued = true;
+                    }
+                    None if pending.is_empty() => {
+                        ok = false;
+                        offset = len;
+                    }
+                    None => break,
+                }
+            }
+            if issued {
+                self.device.notify(&self.queue);
+            }
+
+            match self.queue.pop_used() {
+                Some((id, _)) => {
+                    if let Some(request) = pending.remove(&id) {
+                        ok &= request.succeeded();
+                    }
+                }
+                None if !pending.is_empty() => self.device.idle(),
+                None => {}
+            }
+        }
+        if ok {
+            Ok(())
+        } else {
+            debugln!("virtio-blk request failed at sector {}", start);
+            Err(())
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), ()> {
+        if !self.flush {
+            return Ok(());
+        }
+        let request = Request::new(VIRTIO_BLK_T_FLUSH, 0);
+        let buffers = request.buffers(Vec::new()).ok_or(())?;
+        self.queue.push(&buffers).ok_or(())?;
+        self.device.notify(&self.queue);
+        while self.queue.pop_used().is_none() {
+            self.device.idle();
+        }
+        if request.succeeded() {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// 初始化virtio块设备：协商特性、设置队列并读取容量
+fn probe(pci: &PciDevice) -> bool {
+    let mut device = match VirtioDevice::new(pci) {
+        Some(device) => device,
+        None => {
+            debugln!("virtio-blk device has no IO BAR");
+            return false;
+        }
+    };
+    let features = device.device_features();
+    device.set_features(features & (VIRTIO_BLK_F_RO | VIRTIO_BLK_F_FLUSH));
+    let queue = match device.setup_queue(0) {
+        Some(queue) => queue,
+        None => {
+            debugln!("Cannot set up the virtio-blk request queue");
+            device.fail();
+            return false;
+        }
+    };
+    // 设备配置的前8字节是容量（扇区数）
+    let sectors = device.config_u64(0);
+    device.enable_interrupts(pci);
+    device.driver_ok();
+
+    DISKS.lock().push(VirtioBlk {
+        device,
+        queue,
+        sectors,
+        read_only: features & VIRTIO_BLK_F_RO != 0,
+        flush: features & VIRTIO_BLK_F_FLUSH != 0,
+    });
+    true
+}
+
+/// virtio磁盘
+pub struct VirtioBlkDrive {
+    /// 按被探测到的顺序编号
+    pub index: usize,
+    sectors: u64,
+}
+
+/// 所有virtio磁盘，按PCI总线上的顺序
+pub fn list() -> Vec<VirtioBlkDrive> {
+    DISKS
+        .lock()
+        .iter()
+        .enumerate()
+        .map(|(index, disk)| VirtioBlkDrive { index, sectors: disk.sectors })
+        .collect()
+}
+
+impl BlockDevice for VirtioBlkDrive {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_blocks(&mut self, start: u64, buf: &mut [u8]) -> Result<(), ()> {
+        block::check_range(self, start, buf.len())?;
+        let addr = VirtAddr::from_ptr(buf.as_mut_ptr());
+        DISKS.lock()[self.index].transfer(VIRTIO_BLK_T_IN, start, addr, buf.len())
+    }
+
+    fn write_blocks(&mut self, start: u64, buf: &[u8]) -> Result<(), ()> {
+        block::check_range(self, start, buf.len())?;
+        let mut disks = DISKS.lock();
+        let disk = &mut disks[self.index];
+        if disk.read_only {
+            return Err(());
+        }
+        disk.transfer(VIRTIO_BLK_T_OUT, start, VirtAddr::from_ptr(buf.as_ptr()), buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), ()> {
+        DISKS.lock()[self.index].flush()
+    }
+}
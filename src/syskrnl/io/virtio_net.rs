@@ -0,0 +1,258 @@
+//! virtio网卡驱动
+//!
+//! 0号队列接收、1号队列发送。收发缓冲区都是物理帧中的2KB槽，每个槽开头是virtio网络头，之后是以太网帧；
+//! 接收槽一开始全部交给设备，取走数据后立即重新放回。驱动不解析帧的内容，协议栈见`syskrnl::net`。
+//! 网卡中断只唤醒协议栈的轮询任务，收发都在任务里进行。
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Context;
+
+use futures_util::task::AtomicWaker;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::FrameAllocator;
+use x86_64::PhysAddr;
+
+use crate::debugln;
+use crate::syskrnl;
+use crate::syskrnl::io::pci::{PciDevice, PciDriver, PciMatch};
+use crate::syskrnl::io::virtio::{Buffer, VirtioDevice, Virtqueue, VIRTIO_VENDOR_ID};
+use crate::syskrnl::memory;
+
+/// 传统接口的virtio网卡的设备号
+const VIRTIO_NET_DEVICE_ID: u16 = 0x1000;
+
+const VIRTIO_NET_F_MAC: u32 = 1 << 5;
+
+/// 不协商任何卸载特性时的网络头长度
+const NET_HEADER_SIZE: usize = 10;
+/// 每个缓冲槽的大小，能放下网络头和最长的以太网帧
+const SLOT_SIZE: usize = 2048;
+/// 以太网帧的最大长度（不含FCS）
+pub const MAX_FRAME_SIZE: usize = 1514;
+
+const RX_SLOTS: usize = 32;
+const TX_SLOTS: usize = 16;
+/// 发送一个帧占用的描述符数：网络头和帧各一个
+const TX_DESCRIPTORS: usize = 2;
+
+/// virtio网卡驱动
+pub static PCI_DRIVER: PciDriver = PciDriver {
+    name: "virtio-net",
+    matches: &[PciMatch::id(VIRTIO_VENDOR_ID, VIRTIO_NET_DEVICE_ID)],
+    probe,
+};
+
+lazy_static! {
+    /// 探测到、还没有交给协议栈的网卡
+    static ref NICS: Mutex<Vec<VirtioNet>> = Mutex::new(Vec::new());
+}
+
+/// 自上次检查以来是否收到过网卡中断
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// 网卡中断处理函数：唤醒协议栈的轮询任务
+///
+/// 共享中断线上其他设备的中断也会走到这里，多余的唤醒只会让协议栈多轮询一次。
+fn virtio_net_interrupt_handler() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    WAKER.wake();
+}
+
+/// 登记`cx`的任务在网卡中断时被唤醒，返回自上次检查以来是否收到过中断
+pub fn poll_interrupt(cx: &Context) -> bool {
+    WAKER.register(cx.waker());
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+/// 分配`count`个缓冲槽
+fn allocate_slots(count: usize) -> Option<Vec<PhysAddr>> {
+    let mut allocator = memory::frame_allocator();
+    let mut slots = Vec::with_capacity(count);
+    while slots.len() < count {
+        let frame = allocator.allocate_frame()?.start_address();
+        for i in 0..(4096 / SLOT_SIZE).min(count - slots.len()) {
+            slots.push(frame + (i * SLOT_SIZE) as u64);
+        }
+    }
+    Some(slots)
+}
+
+fn slot_mut(addr: PhysAddr) -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(memory::phys_to_virt(addr).as_mut_ptr::<u8>(), SLOT_SIZE) }
+}
+
+pub struct VirtioNet {
+    device: VirtioDevice,
+    rx: Virtqueue,
+    tx: Virtqueue,
+    mac: [u8; 6],
+    rx_slots: Vec<PhysAddr>,
+    /// 交给设备的接收请求编号到槽号
+    rx_pending: BTreeMap<u16, usize>,
+    tx_slots: Vec<PhysAddr>,
+    tx_free: Vec<usize>,
+    /// 交给设备的发送请求编号到槽号
+    tx_pending: BTreeMap<u16, usize>,
+}
+
+impl VirtioNet {
+    pub fn mac(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    /// 把接收槽交给设备
+    fn post_rx(&mut self, slot: usize) {
+        let buffer = Buffer {
+            addr: self.rx_slots[slot],
+            len: SLOT_SIZE as u32,
+            device_writable: true,
+        };
+        if let Some(id) = self.rx.push(&[buffer]) {
+            self.rx_pending.insert(id, slot);
+        }
+    }
+
+    /// 取出一个收到的以太网帧
+    pub fn receive(&mut self) -> Option<Vec<u8>> {
+        let (id, len) = self.rx.pop_used()?;
+        let slot = self.rx_pending.remove(&id)?;
+        let len = (len as usize).clamp(NET_HEADER_SIZE, SLOT_SIZE);
+        let frame = Vec::from(&slot_mut(self.rx_slots[slot])[NET_HEADER_SIZE..len]);
+        self.post_rx(slot);
+        self.device.notify(&self.rx);
+        Some(frame)
+    }
+
+    /// 回收发送完的槽
+    fn reclaim_tx(&mut self) {
+        while let Some((id, _)) = self.tx.pop_used() {
+            if let Some(slot) = self.tx_pending.remove(&id) {
+                self.tx_free.push(slot);
+            }
+        }
+    }
+
+    /// 是否有空闲的发送槽和足够的描述符
+    pub fn can_transmit(&mut self) -> bool {
+        self.reclaim_tx();
+        !self.tx_free.is_empty() && self.tx.free_descriptors() >= TX_DESCRIPTORS
+    }
+
+    /// 发送一个长度为`len`的帧，帧的内容由`fill`填写
+    ///
+    /// 没有空闲的发送槽或描述符时返回`None`，这时不会调用`fill`。
+    pub fn transmit<R>(&mut self, len: usize, fill: impl FnOnce(&mut [u8]) -> R) -> Option<R> {
+        if !self.can_transmit() {
+            return None;
+        }
+        let len = len.min(MAX_FRAME_SIZE);
+        let slot = self.tx_free.pop()?;
+        let addr = self.tx_slots[slot];
+        let data = slot_mut(addr);
+        data[..NET_HEADER_SIZE].fill(0);
+        let result = fill(&mut data[NET_HEADER_SIZE..NET_HEADER_SIZE + len]);
+
+        // 传统接口要求网络头单独占一个描述符
+        let buffers = [
+            Buffer {
+                addr,
+                len: NET_HEADER_SIZE as u32,
+                device_writable: false,
+            },
+            Buffer {
+                addr: addr + NET_HEADER_SIZE as u64,
+                len: len as u32,
+                device_writable: false,
+            },
+        ];
+        // 上面已经检查过描述符，这里不会失败
+        let id = match self.tx.push(&buffers) {
+            Some(id) => id,
+            None => {
+                self.tx_free.push(slot);
+                return None;
+            }
+        };
+        self.tx_pending.insert(id, slot);
+        self.device.notify(&self.tx);
+        Some(result)
+    }
+}
+
+/// 初始化virtio网卡：协商特性、设置收发队列并读取MAC地址
+fn probe(pci: &PciDevice) -> bool {
+    let mut device = match VirtioDevice::new(pci) {
+        Some(device) => device,
+        None => {
+            debugln!("virtio-net device has no IO BAR");
+            return false;
+        }
+    };
+    let features = device.device_features();
+    device.set_features(features & VIRTIO_NET_F_MAC);
+    let (rx, tx) = match (device.setup_queue(0), device.setup_queue(1)) {
+        (Some(rx), Some(tx)) => (rx, tx),
+        _ => {
+            debugln!("Cannot set up the virtio-net queues");
+            device.fail();
+            return false;
+        }
+    };
+    let (rx_slots, tx_slots) = match (allocate_slots(RX_SLOTS.min(rx.size())), allocate_slots(TX_SLOTS)) {
+        (Some(rx_slots), Some(tx_slots)) => (rx_slots, tx_slots),
+        _ => {
+            debugln!("Cannot allocate virtio-net buffers");
+            device.fail();
+            return false;
+        }
+    };
+
+    let mut mac = [0u8; 6];
+    if features & VIRTIO_NET_F_MAC != 0 {
+        for (i, byte) in mac.iter_mut().enumerate() {
+            *byte = device.config_u8(i as u16);
+        }
+    } else {
+        // 本地管理的单播地址
+        mac = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+    }
+
+    device.enable_interrupts(pci);
+    if let Some(irq) = pci.irq() {
+        interrupts::without_interrupts(|| syskrnl::interrupts::add_shared_irq_handler(irq, virtio_net_interrupt_handler));
+    }
+    let mut nic = VirtioNet {
+        device,
+        rx,
+        tx,
+        mac,
+        rx_slots,
+        rx_pending: BTreeMap::new(),
+        tx_free: (0..tx_slots.len()).collect(),
+        tx_slots,
+        tx_pending: BTreeMap::new(),
+    };
+    for slot in 0..nic.rx_slots.len() {
+        nic.post_rx(slot);
+    }
+    nic.device.driver_ok();
+    nic.device.notify(&nic.rx);
+
+    NICS.lock().push(nic);
+    true
+}
+
+/// 取走第一块网卡，没有网卡时返回`None`
+pub fn take() -> Option<VirtioNet> {
+    let mut nics = NICS.lock();
+    if nics.is_empty() {
+        None
+    } else {
+        Some(nics.remove(0))
+    }
+}
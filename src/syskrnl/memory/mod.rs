@@ -4,6 +4,7 @@ use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
 use bootloader::BootInfo;
+use spin::Mutex;
 use x86_64::instructions::interrupts;
 use x86_64::registers::control::Cr3;
 use x86_64::structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PhysFrame, Size4KiB, Translate};
@@ -19,11 +20,23 @@ pub static mut MAPPER: Option<OffsetPageTable<'static>> = None;
 
 pub static MEMORY_SIZE: AtomicU64 = AtomicU64::new(0);
 static ALLOCATED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+/// 归还的物理帧，分配时优先使用
+static FREE_FRAMES: Mutex<Vec<PhysFrame>> = Mutex::new(Vec::new());
 
 pub fn memory_size() -> u64 {
     MEMORY_SIZE.load(Ordering::Relaxed)
 }
 
+/// 已经分配出去的物理帧数
+pub fn allocated_frames() -> usize {
+    ALLOCATED_FRAMES.load(Ordering::Relaxed) - FREE_FRAMES.lock().len()
+}
+
+/// 内存映射中可用的物理帧总数
+pub fn usable_frames() -> usize {
+    frame_allocator().usable_frames().count()
+}
+
 pub fn init(bootinfo: &'static BootInfo) {
     interrupts::without_interrupts(|| {
         let mut memory_size = 0;
@@ -46,10 +59,9 @@ pub fn init(bootinfo: &'static BootInfo) {
 
         syskrnl::allocator::init_heap(mapper, &mut frame_allocator).expect("heap initialization failed");
 
-        syskrnl::graphic::enter_wide_mode(&mut mapper, &mut frame_allocator); // 因为需要分配显存，就放在这里了
-
-        syskrnl::io::ahci::create_abar_memory_mapping(&mut mapper, &mut frame_allocator);
-        syskrnl::io::ahci::create_ahci_memory_mapping(&mut mapper, &mut frame_allocator);
+        if !syskrnl::io::HEADLESS {
+            syskrnl::graphic::enter_wide_mode(&mut mapper, &mut frame_allocator); // 因为需要分配显存，就放在这里了
+        }
     });
 }
 
@@ -187,6 +199,16 @@ impl BootInfoFrameAllocator {
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if let Some(frame) = FREE_FRAMES.lock().pop() {
+            return Some(frame);
+        }
+        self.next_frame()
+    }
+}
+
+impl BootInfoFrameAllocator {
+    /// 按顺序取出下一个从未分配过的帧
+    fn next_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
         let next = ALLOCATED_FRAMES.fetch_add(1, Ordering::SeqCst);
         //debug!("Allocate frame {} / {}", next, self.usable_frames().count());
 
@@ -198,6 +220,28 @@ pub fn frame_allocator() -> BootInfoFrameAllocator {
     unsafe { BootInfoFrameAllocator::init(MEMORY_MAP.unwrap()) }
 }
 
+/// 分配`count`个物理地址连续的帧，返回第一帧的地址
+///
+/// 帧按顺序分配，通常本来就是连续的；跨过不可用区域时把已分配的部分归还，重新开始。
+pub fn allocate_contiguous_frames(count: usize) -> Option<PhysAddr> {
+    let mut allocator = frame_allocator();
+    let mut frames: Vec<PhysFrame> = Vec::with_capacity(count);
+    while frames.len() < count {
+        let frame = match allocator.next_frame() {
+            Some(frame) => frame,
+            None => {
+                FREE_FRAMES.lock().append(&mut frames);
+                return None;
+            }
+        };
+        if frames.last().map_or(false, |last| *last + 1 != frame) {
+            FREE_FRAMES.lock().append(&mut frames);
+        }
+        frames.push(frame);
+    }
+    frames.first().map(|frame| frame.start_address())
+}
+
 /// 帧分配器，返回BootLoader的内存映射中的可用帧
 pub struct HeapedBootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
@@ -235,6 +279,9 @@ impl HeapedBootInfoFrameAllocator {
 
 unsafe impl FrameAllocator<Size4KiB> for HeapedBootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if let Some(frame) = FREE_FRAMES.lock().pop() {
+            return Some(frame);
+        }
         let next = ALLOCATED_FRAMES.fetch_add(1, Ordering::SeqCst);
         //debug!("Allocate frame {} / {}", next, self.usable_frames().count());
 
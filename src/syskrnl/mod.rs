@@ -13,8 +13,11 @@ pub mod graphic;
 pub mod gui;
 pub mod interrupts;
 pub mod memory;
+pub mod net;
 pub mod proc;
+pub mod random;
 pub mod schedule;
+pub mod sync;
 pub mod task;
 pub mod time;
 pub mod vga_buffer;
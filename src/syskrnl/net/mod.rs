@@ -0,0 +1,195 @@
+//! 网络协议栈
+//!
+//! 在virtio网卡上使用smoltcp，提供IPv4、ARP、ICMP（回应ping）、UDP和TCP。地址是QEMU用户模式网络的默认配置：
+//! 本机`10.0.2.15/24`，网关`10.0.2.2`，从网关可以访问宿主机的回环地址。
+//!
+//! smoltcp只在轮询时收发数据包。套接字操作前后各轮询一次；其余时间由0号进程执行器中的[`net_handler`]任务轮询，
+//! 网卡中断或smoltcp要求的下一次轮询时间（[`Interface::poll_delay`]，由时钟中断计时）到了时唤醒它。
+
+mod socket;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::Poll;
+
+use futures_util::future::poll_fn;
+use futures_util::task::AtomicWaker;
+use lazy_static::lazy_static;
+use smoltcp::iface::{Config, Interface, SocketSet};
+use smoltcp::phy::{self, DeviceCapabilities, Medium};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{EthernetAddress, HardwareAddress, IpCidr, Ipv4Address};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::syskrnl::io::virtio_net::{self, VirtioNet, MAX_FRAME_SIZE};
+use crate::syskrnl::{random, time};
+
+pub use socket::*;
+
+const LOCAL_ADDR: Ipv4Address = Ipv4Address::new(10, 0, 2, 15);
+const PREFIX_LEN: u8 = 24;
+const GATEWAY: Ipv4Address = Ipv4Address::new(10, 0, 2, 2);
+
+/// 把网卡包装成smoltcp的设备
+struct NetDevice(VirtioNet);
+
+struct RxToken(Vec<u8>);
+
+struct TxToken<'a>(&'a mut VirtioNet);
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.0)
+    }
+}
+
+impl phy::TxToken for TxToken<'_> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        match self.0.transmit(len, f) {
+            Some(result) => result,
+            // 只有在有空闲的发送槽和描述符时才会发出令牌，这里不会发生
+            None => f(&mut vec![0u8; len]),
+        }
+    }
+}
+
+impl phy::Device for NetDevice {
+    type RxToken<'a> = RxToken where Self: 'a;
+    type TxToken<'a> = TxToken<'a> where Self: 'a;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        if !self.0.can_transmit() {
+            return None;
+        }
+        let frame = self.0.receive()?;
+        Some((RxToken(frame), TxToken(&mut self.0)))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        if self.0.can_transmit() {
+            Some(TxToken(&mut self.0))
+        } else {
+            None
+        }
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ethernet;
+        caps.max_transmission_unit = MAX_FRAME_SIZE;
+        caps
+    }
+}
+
+pub struct NetStack {
+    device: NetDevice,
+    iface: Interface,
+    sockets: SocketSet<'static>,
+    /// 进程使用的套接字
+    handles: SocketTable,
+}
+
+impl NetStack {
+    /// 收发数据包、推进TCP状态，并安排下一次轮询
+    fn poll(&mut self) {
+        self.iface.poll(now(), &mut self.device, &mut self.sockets);
+        self.reap_closed();
+        let delay = self.iface.poll_delay(now(), &self.sockets);
+        schedule_poll(delay);
+    }
+}
+
+lazy_static! {
+    static ref STACK: Mutex<Option<NetStack>> = Mutex::new(None);
+}
+
+const NO_POLL: usize = usize::MAX;
+
+/// 下一次需要轮询的时钟节拍
+static NEXT_POLL: AtomicUsize = AtomicUsize::new(NO_POLL);
+/// 时钟中断标记需要轮询
+static POLL_DUE: AtomicBool = AtomicBool::new(false);
+static POLL_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// 在`delay`之后轮询，`None`表示只等网卡中断
+fn schedule_poll(delay: Option<Duration>) {
+    let next = match delay {
+        Some(delay) => time::ticks() + delay.total_millis() as usize * time::TICKS_PER_SECOND / 1000,
+        None => NO_POLL,
+    };
+    NEXT_POLL.store(next, Ordering::SeqCst);
+}
+
+fn now() -> Instant {
+    Instant::from_millis((time::uptime() * 1000.0) as i64)
+}
+
+/// 取出探测到的网卡并配置协议栈，没有网卡时什么也不做
+pub fn init() {
+    let nic = match virtio_net::take() {
+        Some(nic) => nic,
+        None => {
+            println!("没有找到网卡，网络不可用");
+            return;
+        }
+    };
+    let mac = nic.mac();
+    let mut device = NetDevice(nic);
+
+    let mut config = Config::new(HardwareAddress::Ethernet(EthernetAddress(mac)));
+    config.random_seed = random::get_u64();
+    let mut iface = Interface::new(config, &mut device, now());
+    iface.update_ip_addrs(|addrs| {
+        addrs.push(IpCidr::new(LOCAL_ADDR.into(), PREFIX_LEN)).unwrap();
+    });
+    iface.routes_mut().add_default_ipv4_route(GATEWAY).unwrap();
+
+    println!(
+        "网络：{} MAC {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        LOCAL_ADDR, mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+    );
+    *STACK.lock() = Some(NetStack {
+        device,
+        iface,
+        sockets: SocketSet::new(Vec::new()),
+        handles: SocketTable::new(),
+    });
+}
+
+/// 到了下一次轮询的时间时唤醒轮询任务，在时钟中断中调用
+pub fn tick() {
+    if time::ticks() >= NEXT_POLL.load(Ordering::SeqCst) && !POLL_DUE.swap(true, Ordering::SeqCst) {
+        POLL_WAKER.wake();
+    }
+}
+
+/// 轮询协议栈的内核任务
+///
+/// 系统调用也会使用协议栈，轮询时关闭中断，不会在持有锁时被切换走。
+pub async fn net_handler() {
+    loop {
+        poll_fn(|cx| {
+            POLL_WAKER.register(cx.waker());
+            let due = POLL_DUE.swap(false, Ordering::SeqCst);
+            if virtio_net::poll_interrupt(cx) | due {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        interrupts::without_interrupts(|| {
+            if let Some(stack) = STACK.lock().as_mut() {
+                stack.poll();
+            }
+        });
+    }
+}
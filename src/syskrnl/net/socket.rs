@@ -0,0 +1,288 @@
+//! 进程的套接字
+//!
+//! 套接字句柄属于创建它的进程，其他进程无法使用；进程退出时关闭它的所有套接字。
+//! 所有操作都不等待，没有数据可读或发送缓冲区已满时返回[`NetError::WouldBlockError`]。
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::{tcp, udp};
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+
+use cinea_os_sysapi::net::{NetError, SocketAddr, SocketStatus, SocketType, TcpState};
+
+use crate::syskrnl::proc;
+
+use super::{NetStack, STACK};
+
+const TCP_BUFFER_SIZE: usize = 16 * 1024;
+const UDP_BUFFER_SIZE: usize = 16 * 1024;
+/// UDP接收和发送缓冲区中最多的数据报个数
+const UDP_PACKETS: usize = 16;
+/// 主动连接时使用的本地端口从这里开始轮流分配
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+struct SocketEntry {
+    pid: usize,
+    kind: SocketType,
+    handle: SocketHandle,
+}
+
+pub(super) struct SocketTable {
+    entries: BTreeMap<usize, SocketEntry>,
+    /// 已关闭、正在断开连接的TCP套接字，断开后从协议栈中移除
+    closing: Vec<SocketHandle>,
+    next_id: usize,
+    next_port: u16,
+}
+
+impl SocketTable {
+    pub(super) fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            closing: Vec::new(),
+            next_id: 1,
+            next_port: EPHEMERAL_PORT_START,
+        }
+    }
+
+    /// 当前进程的套接字
+    fn get(&self, id: usize) -> Result<(SocketType, SocketHandle), NetError> {
+        match self.entries.get(&id) {
+            Some(entry) if entry.pid == proc::id() => Ok((entry.kind, entry.handle)),
+            _ => Err(NetError::InvalidHandleError),
+        }
+    }
+
+    fn ephemeral_port(&mut self) -> u16 {
+        let port = self.next_port;
+        self.next_port = if port == u16::MAX { EPHEMERAL_PORT_START } else { port + 1 };
+        port
+    }
+}
+
+impl NetStack {
+    /// 关闭套接字：TCP连接在后台正常断开，UDP套接字立即移除
+    fn close_socket(&mut self, kind: SocketType, handle: SocketHandle) {
+        match kind {
+            SocketType::Tcp => {
+                self.sockets.get_mut::<tcp::Socket>(handle).close();
+                self.handles.closing.push(handle);
+            }
+            SocketType::Udp => {
+                self.sockets.remove(handle);
+            }
+        }
+    }
+
+    /// 移除已经断开的TCP套接字
+    pub(super) fn reap_closed(&mut self) {
+        let sockets = &mut self.sockets;
+        self.handles.closing.retain(|handle| {
+            let closed = matches!(sockets.get::<tcp::Socket>(*handle).state(), tcp::State::Closed | tcp::State::TimeWait);
+            if closed {
+                sockets.remove(*handle);
+            }
+            !closed
+        });
+    }
+}
+
+/// 在协议栈上执行操作，前后各轮询一次，让数据尽快收发
+fn with_stack<T>(f: impl FnOnce(&mut NetStack) -> Result<T, NetError>) -> Result<T, NetError> {
+    let mut lock = STACK.lock();
+    let stack = lock.as_mut().ok_or(NetError::NoDeviceError)?;
+    stack.poll();
+    let result = f(stack);
+    stack.poll();
+    result
+}
+
+fn endpoint(addr: SocketAddr) -> IpEndpoint {
+    IpEndpoint::new(IpAddress::Ipv4(Ipv4Address(addr.ip)), addr.port)
+}
+
+fn socket_addr(endpoint: IpEndpoint) -> SocketAddr {
+    let IpAddress::Ipv4(ip) = endpoint.addr;
+    SocketAddr::new(ip.0, endpoint.port)
+}
+
+/// 创建套接字
+pub fn socket(kind: SocketType) -> Result<usize, NetError> {
+    with_stack(|stack| {
+        let handle = match kind {
+            SocketType::Tcp => {
+                let rx = tcp::SocketBuffer::new(vec![0u8; TCP_BUFFER_SIZE]);
+                let tx = tcp::SocketBuffer::new(vec![0u8; TCP_BUFFER_SIZE]);
+                stack.sockets.add(tcp::Socket::new(rx, tx))
+            }
+            SocketType::Udp => {
+                let rx = udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; UDP_PACKETS], vec![0u8; UDP_BUFFER_SIZE]);
+                let tx = udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; UDP_PACKETS], vec![0u8; UDP_BUFFER_SIZE]);
+                stack.sockets.add(udp::Socket::new(rx, tx))
+            }
+        };
+        let id = stack.handles.next_id;
+        stack.handles.next_id += 1;
+        stack.handles.entries.insert(id, SocketEntry { pid: proc::id(), kind, handle });
+        Ok(id)
+    })
+}
+
+/// 把UDP套接字绑定到本地端口
+pub fn bind(id: usize, port: u16) -> Result<(), NetError> {
+    with_stack(|stack| match stack.handles.get(id)? {
+        (SocketType::Udp, handle) => stack.sockets.get_mut::<udp::Socket>(handle).bind(port).map_err(|err| match err {
+            udp::BindError::Unaddressable => NetError::UnaddressableError,
+            udp::BindError::InvalidState => NetError::InvalidStateError,
+        }),
+        (SocketType::Tcp, _) => Err(NetError::InvalidStateError),
+    })
+}
+
+/// 让TCP套接字在本地端口上等待连接
+pub fn listen(id: usize, port: u16) -> Result<(), NetError> {
+    with_stack(|stack| match stack.handles.get(id)? {
+        (SocketType::Tcp, handle) => stack.sockets.get_mut::<tcp::Socket>(handle).listen(port).map_err(|err| match err {
+            tcp::ListenError::Unaddressable => NetError::UnaddressableError,
+            tcp::ListenError::InvalidState => NetError::InvalidStateError,
+        }),
+        (SocketType::Udp, _) => Err(NetError::InvalidStateError),
+    })
+}
+
+/// 开始建立TCP连接，不等待握手完成
+pub fn connect(id: usize, addr: SocketAddr) -> Result<(), NetError> {
+    with_stack(|stack| match stack.handles.get(id)? {
+        (SocketType::Tcp, handle) => {
+            let local_port = stack.handles.ephemeral_port();
+            let cx = stack.iface.context();
+            let socket = stack.sockets.get_mut::<tcp::Socket>(handle);
+            socket.connect(cx, endpoint(addr), local_port).map_err(|err| match err {
+                tcp::ConnectError::Unaddressable => NetError::UnaddressableError,
+                tcp::ConnectError::InvalidState => NetError::InvalidStateError,
+            })
+        }
+        (SocketType::Udp, _) => Err(NetError::InvalidStateError),
+    })
+}
+
+/// 把数据放入发送缓冲区，返回放入的字节数；UDP套接字需要目的地址`to`
+pub fn send(id: usize, data: &[u8], to: Option<SocketAddr>) -> Result<usize, NetError> {
+    with_stack(|stack| match stack.handles.get(id)? {
+        (SocketType::Tcp, handle) => {
+            let socket = stack.sockets.get_mut::<tcp::Socket>(handle);
+            if !socket.may_send() {
+                return match socket.state() {
+                    tcp::State::SynSent | tcp::State::SynReceived => Err(NetError::WouldBlockError),
+                    _ => Err(NetError::ConnectionClosedError),
+                };
+            }
+            if !socket.can_send() {
+                return Err(NetError::WouldBlockError);
+            }
+            socket.send_slice(data).map_err(|_| NetError::ConnectionClosedError)
+        }
+        (SocketType::Udp, handle) => {
+            let to = to.ok_or(NetError::UnaddressableError)?;
+            let socket = stack.sockets.get_mut::<udp::Socket>(handle);
+            match socket.send_slice(data, endpoint(to)) {
+                Ok(()) => Ok(data.len()),
+                Err(udp::SendError::BufferFull) => Err(NetError::WouldBlockError),
+                Err(udp::SendError::Unaddressable) => Err(NetError::UnaddressableError),
+            }
+        }
+    })
+}
+
+/// 取出至多`max_len`字节收到的数据；UDP同时返回发送方
+///
+/// 对方关闭了TCP连接并且数据已读完时返回空数据。
+pub fn recv(id: usize, max_len: usize) -> Result<(Vec<u8>, Option<SocketAddr>), NetError> {
+    with_stack(|stack| match stack.handles.get(id)? {
+        (SocketType::Tcp, handle) => {
+            let socket = stack.sockets.get_mut::<tcp::Socket>(handle);
+            if socket.can_recv() {
+                let mut buf = vec![0u8; max_len];
+                let len = socket.recv_slice(&mut buf).map_err(|_| NetError::ConnectionClosedError)?;
+                buf.truncate(len);
+                return Ok((buf, None));
+            }
+            match socket.state() {
+                tcp::State::Listen | tcp::State::SynSent | tcp::State::SynReceived => Err(NetError::WouldBlockError),
+                _ if socket.may_recv() => Err(NetError::WouldBlockError),
+                _ => Ok((Vec::new(), None)),
+            }
+        }
+        (SocketType::Udp, handle) => {
+            let socket = stack.sockets.get_mut::<udp::Socket>(handle);
+            let mut buf = vec![0u8; max_len];
+            match socket.recv_slice(&mut buf) {
+                Ok((len, meta)) => {
+                    buf.truncate(len);
+                    Ok((buf, Some(socket_addr(meta.endpoint))))
+                }
+                Err(udp::RecvError::Exhausted) => Err(NetError::WouldBlockError),
+            }
+        }
+    })
+}
+
+/// 套接字的状态
+pub fn status(id: usize) -> Result<SocketStatus, NetError> {
+    with_stack(|stack| match stack.handles.get(id)? {
+        (SocketType::Tcp, handle) => {
+            let socket = stack.sockets.get::<tcp::Socket>(handle);
+            let state = match socket.state() {
+                tcp::State::Closed | tcp::State::TimeWait => TcpState::Closed,
+                tcp::State::Listen => TcpState::Listen,
+                tcp::State::SynSent | tcp::State::SynReceived => TcpState::Connecting,
+                tcp::State::Established => TcpState::Established,
+                _ => TcpState::Closing,
+            };
+            Ok(SocketStatus {
+                state,
+                can_send: socket.can_send(),
+                can_recv: socket.can_recv(),
+                may_recv: socket.may_recv(),
+            })
+        }
+        (SocketType::Udp, handle) => {
+            let socket = stack.sockets.get::<udp::Socket>(handle);
+            Ok(SocketStatus {
+                state: TcpState::Closed,
+                can_send: socket.can_send(),
+                can_recv: socket.can_recv(),
+                may_recv: true,
+            })
+        }
+    })
+}
+
+/// 关闭套接字
+pub fn close(id: usize) -> Result<(), NetError> {
+    with_stack(|stack| {
+        let (kind, handle) = stack.handles.get(id)?;
+        stack.handles.entries.remove(&id);
+        stack.close_socket(kind, handle);
+        Ok(())
+    })
+}
+
+/// 关闭当前进程的所有套接字，在进程退出时调用
+pub fn close_all() {
+    let mut lock = STACK.lock();
+    let stack = match lock.as_mut() {
+        Some(stack) => stack,
+        None => return,
+    };
+    let pid = proc::id();
+    let ids: Vec<usize> = stack.handles.entries.iter().filter(|(_, entry)| entry.pid == pid).map(|(id, _)| *id).collect();
+    for id in ids {
+        if let Some(entry) = stack.handles.entries.remove(&id) {
+            stack.close_socket(entry.kind, entry.handle);
+        }
+    }
+}
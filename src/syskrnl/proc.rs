@@ -2,8 +2,10 @@ use alloc::boxed::Box;
 use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::arch::asm;
+use core::fmt::{Debug, Formatter};
 use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 use lazy_static::lazy_static;
@@ -14,6 +16,7 @@ use x86_64::structures::idt::InterruptStackFrameValue;
 use x86_64::structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame};
 use x86_64::VirtAddr;
 
+use cinea_os_sysapi::fs::OpenFlags;
 use cinea_os_sysapi::ExitCode;
 
 use crate::syskrnl::allocator::linked_list::LinkedListAllocator;
@@ -27,6 +30,8 @@ use crate::{debugln, syskrnl};
 /// 最大进程数，先写2个，后面再改
 const MAX_PROCS: usize = 16;
 const MAX_PROC_SIZE: usize = 10 << 20;
+/// 每个进程的内核栈大小
+const KERNEL_STACK_SIZE: usize = 64 * 1024;
 #[allow(dead_code)]
 const MAX_FILE_HANDLES: usize = 64;
 
@@ -77,6 +82,29 @@ pub struct Registers {
     pub rax: usize,
 }
 
+/// 进程的内核栈
+///
+/// 进程在系统调用中等待磁盘时，内核中的上下文留在自己的栈上，其他进程进入内核时不会覆盖它。
+#[derive(Clone)]
+struct KernelStack(Arc<Vec<u8>>);
+
+impl KernelStack {
+    fn new() -> Self {
+        Self(Arc::new(vec![0u8; KERNEL_STACK_SIZE]))
+    }
+
+    /// 栈顶，按16字节对齐
+    fn top(&self) -> VirtAddr {
+        (VirtAddr::from_ptr(self.0.as_ptr()) + self.0.len()).align_down(16u64)
+    }
+}
+
+impl Debug for KernelStack {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "KernelStack({:?})", self.top())
+    }
+}
+
 const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
 const BIN_MAGIC: [u8; 4] = [0x7F, b'B', b'I', b'N'];
 
@@ -90,9 +118,10 @@ pub struct Process {
     stack_frame: InterruptStackFrameValue,
     registers: Registers,
     data: ProcessData,
-    #[allow(unused)]
     parent: usize,
     allocator: Arc<Locked<LinkedListAllocator>>,
+    /// 0号进程运行在启动时的栈上，没有单独的内核栈
+    kernel_stack: Option<KernelStack>,
 }
 
 impl ProcessData {
@@ -100,18 +129,7 @@ impl ProcessData {
         let env = BTreeMap::new();
         let dir = dir.to_string();
         let user = user.map(String::from);
-        let file_handles = Arc::new(Mutex::new(BTreeMap::new()));
-        let lock = file_handles.clone();
-        let mut lock = lock.lock();
-        lock.insert(
-            0,
-            OpenFileHandle {
-                id: 0,
-                path: "/dev/stdout".to_string(),
-                write: true,
-                device: true,
-            },
-        );
+        let file_handles = Self::standard_file_handles();
         // let mut file_handles = [(); MAX_FILE_HANDLES].map(|_| None);
         // file_handles[0] = Some(Box::new(Resource::Device(Device::Console(Console::new())))); // stdin
         // file_handles[1] = Some(Box::new(Resource::Device(Device::Console(Console::new())))); // stdout
@@ -124,6 +142,13 @@ impl ProcessData {
             file_handles,
         }
     }
+
+    /// 新进程的句柄表，只包含标准输出
+    fn standard_file_handles() -> Arc<Mutex<BTreeMap<usize, OpenFileHandle>>> {
+        let mut handles = BTreeMap::new();
+        handles.insert(0, OpenFileHandle::new(0, "/dev/stdout".to_string(), OpenFlags::WRITE));
+        Arc::new(Mutex::new(handles))
+    }
 }
 
 impl Process {
@@ -146,6 +171,7 @@ impl Process {
             data: ProcessData::new("/", None),
             parent: 0,
             allocator: Arc::new(Locked::new(LinkedListAllocator::new())),
+            kernel_stack: None,
         }
     }
 }
@@ -195,6 +221,13 @@ pub fn set_env(key: &str, val: &str) {
     proc.data.env.insert(key.into(), val.into());
 }
 
+/// 删除当前进程的环境变量
+pub fn remove_env(key: &str) {
+    let mut table = PROCESS_TABLE.write();
+    let proc = &mut table[id()];
+    proc.data.env.remove(key);
+}
+
 /// 设置当前进程的工作目录
 pub fn set_dir(dir: &str) {
     let mut table = PROCESS_TABLE.write();
@@ -261,6 +294,13 @@ pub fn set_stack_frame(stack_frame: InterruptStackFrameValue) {
     proc.stack_frame = stack_frame;
 }
 
+/// 当前进程内核栈的栈顶
+pub fn kernel_stack_top() -> Option<VirtAddr> {
+    let table = PROCESS_TABLE.read();
+    let proc = &table[id()];
+    proc.kernel_stack.as_ref().map(KernelStack::top)
+}
+
 pub unsafe fn page_table_frame() -> PhysFrame {
     let table = PROCESS_TABLE.read();
     let proc = &table[id()];
@@ -301,8 +341,50 @@ pub fn file_handles() -> Arc<Mutex<BTreeMap<usize, OpenFileHandle>>> {
     proc.data.file_handles.clone()
 }
 
+/// 进程信息快照
+#[derive(Clone, Debug)]
+pub struct ProcessInfo {
+    pub id: usize,
+    pub parent: usize,
+    pub dir: String,
+    pub user: Option<String>,
+    pub code_addr: u64,
+    pub heap_size: usize,
+    pub heap_allocated: usize,
+    pub file_handles: Vec<OpenFileHandle>,
+}
+
+/// 获取所有存活进程的信息，PID 0为内核
+pub fn processes() -> Vec<ProcessInfo> {
+    let free = PID_POOL.lock().clone();
+    let table = PROCESS_TABLE.read();
+    table
+        .iter()
+        .enumerate()
+        .filter(|(pid, _)| !free.contains(pid))
+        .map(|(pid, proc)| {
+            let (heap_size, heap_allocated) = {
+                let allocator = proc.allocator.lock();
+                (allocator.size(), allocator.allocated())
+            };
+            ProcessInfo {
+                id: pid,
+                parent: proc.parent,
+                dir: proc.data.dir.clone(),
+                user: proc.data.user.clone(),
+                code_addr: proc.code_addr,
+                heap_size,
+                heap_allocated,
+                file_handles: proc.data.file_handles.lock().values().cloned().collect(),
+            }
+        })
+        .collect()
+}
+
 /// 进程退出
 pub fn exit() -> usize {
+    syskrnl::fs::close_all();
+    syskrnl::net::close_all();
     let table = PROCESS_TABLE.read();
     let proc = &table[id()];
     syskrnl::allocator::dealloc_pages(proc.code_addr, MAX_PROC_SIZE);
@@ -330,9 +412,23 @@ pub fn init_process_addr(addr: u64) {
 impl Process {
     /// 创建进程
     pub fn spawn(bin: &[u8], args_ptr: usize, args_len: usize, args_cap: usize) -> Result<(), ExitCode> {
+        Self::spawn_with_env(bin, None, args_ptr, args_len, args_cap)
+    }
+
+    /// 创建进程，`env`不为空时替换从父进程继承的环境变量
+    pub fn spawn_with_env(
+        bin: &[u8],
+        env: Option<BTreeMap<String, String>>,
+        args_ptr: usize,
+        args_len: usize,
+        args_cap: usize,
+    ) -> Result<(), ExitCode> {
         if let Ok(id) = Self::create(bin) {
             let mut proc = {
-                let table = PROCESS_TABLE.read();
+                let mut table = PROCESS_TABLE.write();
+                if let Some(env) = env {
+                    table[id].data.env = env;
+                }
                 table[id].clone()
             };
             proc.exec(args_ptr, args_len, args_cap);
@@ -416,7 +512,9 @@ impl Process {
             table[id()].clone()
         };
 
-        let data = parent.data.clone();
+        // 子进程继承环境变量和工作目录，但不继承父进程打开的文件
+        let mut data = parent.data.clone();
+        data.file_handles = ProcessData::standard_file_handles();
         let registers = parent.registers;
         let stack_frame = parent.stack_frame;
         let parent = parent.id;
@@ -446,6 +544,7 @@ impl Process {
                 parent,
                 allocator,
                 page_table_frame,
+                kernel_stack: Some(KernelStack::new()),
             };
 
             let mut table = PROCESS_TABLE.write();
@@ -514,6 +613,9 @@ impl Process {
         syskrnl::interrupts::SCHEDULE.store(true, Ordering::SeqCst);
 
         debugln!("LAUNCH");
+        if let Some(stack) = &self.kernel_stack {
+            syskrnl::gdt::set_kernel_stack(stack.top());
+        }
         set_id(self.id); // 要换咯！
                          // 发射！
         unsafe {
@@ -0,0 +1,146 @@
+//! 随机数生成
+//!
+//! CPU支持RDRAND时直接使用硬件随机数，否则使用以TSC和RTC为种子的ChaCha20生成器。
+
+use core::arch::x86_64::{__cpuid, _rdrand64_step};
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use cinea_os_sysapi::fs::FileIO;
+
+use crate::syskrnl::time;
+use crate::syskrnl::time::tsc::rdtsc;
+
+lazy_static! {
+    static ref HAS_RDRAND: bool = unsafe { __cpuid(1).ecx & (1 << 30) != 0 };
+    static ref CHACHA: Mutex<ChaCha20> = Mutex::new(ChaCha20::from_entropy());
+}
+
+/// ChaCha20流密码，用作伪随机数生成器
+struct ChaCha20 {
+    state: [u32; 16],
+    block: [u32; 16],
+    index: usize,
+}
+
+impl ChaCha20 {
+    fn new(key: [u32; 8]) -> Self {
+        let mut state = [0u32; 16];
+        // "expand 32-byte k"
+        state[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+        state[4..12].copy_from_slice(&key);
+        Self { state, block: [0; 16], index: 16 }
+    }
+
+    /// 以TSC和RTC时间为种子，TSC在不同的启动之间几乎不会重复
+    fn from_entropy() -> Self {
+        let rtc = time::raw_time();
+        let mut key = [0u32; 8];
+        for (i, word) in key.iter_mut().enumerate() {
+            let tsc = rdtsc();
+            *word = (tsc as u32) ^ ((tsc >> 32) as u32).rotate_left(i as u32 * 4);
+        }
+        key[7] ^= rtc.second as u32 | (rtc.minute as u32) << 8 | (rtc.hour as u32) << 16 | (rtc.day as u32) << 24;
+        Self::new(key)
+    }
+
+    fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+        x[a] = x[a].wrapping_add(x[b]);
+        x[d] = (x[d] ^ x[a]).rotate_left(16);
+        x[c] = x[c].wrapping_add(x[d]);
+        x[b] = (x[b] ^ x[c]).rotate_left(12);
+        x[a] = x[a].wrapping_add(x[b]);
+        x[d] = (x[d] ^ x[a]).rotate_left(8);
+        x[c] = x[c].wrapping_add(x[d]);
+        x[b] = (x[b] ^ x[c]).rotate_left(7);
+    }
+
+    fn refill(&mut self) {
+        let mut x = self.state;
+        for _ in 0..10 {
+            Self::quarter_round(&mut x, 0, 4, 8, 12);
+            Self::quarter_round(&mut x, 1, 5, 9, 13);
+            Self::quarter_round(&mut x, 2, 6, 10, 14);
+            Self::quarter_round(&mut x, 3, 7, 11, 15);
+            Self::quarter_round(&mut x, 0, 5, 10, 15);
+            Self::quarter_round(&mut x, 1, 6, 11, 12);
+            Self::quarter_round(&mut x, 2, 7, 8, 13);
+            Self::quarter_round(&mut x, 3, 4, 9, 14);
+        }
+        for (out, (x, s)) in self.block.iter_mut().zip(x.iter().zip(self.state.iter())) {
+            *out = x.wrapping_add(*s);
+        }
+        // 64位块计数器
+        self.state[12] = self.state[12].wrapping_add(1);
+        if self.state[12] == 0 {
+            self.state[13] = self.state[13].wrapping_add(1);
+        }
+        self.index = 0;
+    }
+
+    /// 把数据混入密钥，丢弃已生成但还没用完的块
+    fn mix(&mut self, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            let word = 4 + (i / 4) % 8;
+            self.state[word] = self.state[word].rotate_left(8) ^ *byte as u32;
+        }
+        self.index = 16;
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        if self.index >= 16 {
+            self.refill();
+        }
+        self.index += 1;
+        self.block[self.index - 1]
+    }
+}
+
+#[target_feature(enable = "rdrand")]
+unsafe fn rdrand() -> Option<u64> {
+    let mut value = 0u64;
+    // 硬件熵池可能暂时耗尽，按Intel的建议重试10次
+    for _ in 0..10 {
+        if _rdrand64_step(&mut value) == 1 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// 获取一个64位随机数
+pub fn get_u64() -> u64 {
+    if *HAS_RDRAND {
+        if let Some(value) = unsafe { rdrand() } {
+            return value;
+        }
+    }
+    let mut chacha = CHACHA.lock();
+    (chacha.next_u32() as u64) << 32 | chacha.next_u32() as u64
+}
+
+/// 用随机数填满缓冲区
+pub fn fill(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        let bytes = get_u64().to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
+
+/// 随机数设备，`/dev/random`
+///
+/// 写入的数据混入ChaCha20的密钥，不会使输出变得可预测。
+pub struct RandomDevice;
+
+impl FileIO for RandomDevice {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        fill(buf);
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        CHACHA.lock().mix(buf);
+        Ok(buf.len())
+    }
+}
@@ -0,0 +1,112 @@
+//! 等待磁盘时可以持有的锁
+//!
+//! 进程在系统调用中等待磁盘命令时会让出CPU，这时它持有的锁不能是自旋锁：系统调用中中断是关闭的，
+//! 其他进程在自旋锁上空转就再也切换不回来了。[`Mutex`]被占用时，能被调度的进程在`EVENT_QUEUE`上等待，
+//! 锁释放时被唤醒后重试；0号进程不能停止调度，只能等待下一个中断后重试。
+//!
+//! 只有可能在持有期间等待磁盘的锁（块缓存、文件系统、设备表）需要用它，中断处理函数里不能使用。
+
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use x86_64::instructions::interrupts;
+
+use crate::syskrnl;
+use crate::syskrnl::event::{EVENT_QUEUE, KERNEL_EID_START};
+use crate::syskrnl::proc::{self, SCHEDULER};
+use crate::syskrnl::time;
+
+/// 等待锁的进程都在这个事件上等待，任何一把锁释放时全部唤醒
+const LOCK_EVENT: usize = KERNEL_EID_START;
+
+/// 当前进程能否停止调度、在`EVENT_QUEUE`上等待
+///
+/// 0号进程（内核自己，运行执行器）不能停止调度；调度开始之前也只有它在运行。
+pub fn can_block() -> bool {
+    syskrnl::interrupts::SCHEDULE.load(Ordering::SeqCst) && proc::id() != 0
+}
+
+/// 等待时让出CPU的互斥锁
+pub struct Mutex<T: ?Sized> {
+    /// 正在等待这把锁的进程数
+    waiters: AtomicUsize,
+    inner: spin::Mutex<T>,
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    lock: &'a Mutex<T>,
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            waiters: AtomicUsize::new(0),
+            inner: spin::Mutex::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// 获取锁，被占用时等待
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        loop {
+            // 检查和登记等待之间中断是关闭的，释放锁的一方不会在这之间唤醒
+            let result = interrupts::without_interrupts(|| {
+                if let Some(guard) = self.try_lock() {
+                    return Ok(guard);
+                }
+                if !can_block() {
+                    return Err(None);
+                }
+                self.waiters.fetch_add(1, Ordering::SeqCst);
+                Err(Some(EVENT_QUEUE.lock().wait_for(LOCK_EVENT)))
+            });
+            match result {
+                Ok(guard) => return guard,
+                Err(Some(next_pid)) => {
+                    syskrnl::interrupts::yield_to(next_pid);
+                    self.waiters.fetch_sub(1, Ordering::SeqCst);
+                }
+                Err(None) => time::halt(),
+            }
+        }
+    }
+
+    /// 尝试获取锁，被占用时返回`None`
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.inner.try_lock().map(|guard| MutexGuard {
+            lock: self,
+            guard: ManuallyDrop::new(guard),
+        })
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.lock.waiters.load(Ordering::SeqCst) > 0 {
+            interrupts::without_interrupts(|| {
+                let mut queue = EVENT_QUEUE.lock();
+                while let Some(pid) = queue.wakeup(LOCK_EVENT) {
+                    SCHEDULER.lock().wakeup(pid);
+                }
+            });
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
@@ -39,11 +39,20 @@ pub fn dispatcher(syscall_id: usize, arg1: usize, arg2: usize, arg3: usize, arg4
         TEST_SERDE => service::test_serde(arg1),
         LIST => service::list(arg1),
         OPEN => service::open(arg1),
+        CLOSE => service::close(arg1),
         WRITE_ALL => service::write_all(arg1),
         READ => service::read(arg1),
         WRITE_PATH => service::write_path(arg1),
         READ_PATH => service::read_path(arg1),
         SPAWN_FROM_PATH => service::spawn_from_path(arg1),
+        WRITE => service::write(arg1),
+        SEEK => service::seek(arg1),
+        FTRUNCATE => service::ftruncate(arg1),
+        READ_ALL => service::read_all(arg1),
+        CREATE => service::create(arg1),
+        MKDIR => service::mkdir(arg1),
+        RENAME => service::rename(arg1),
+        TRUNCATE => service::truncate(arg1),
         CREATE_WINDOW => service::create_window(arg1),
         DISPLAY_FONT_STRING => service::display_font_string(arg1),
         LOAD_FONT => service::load_font(arg1),
@@ -52,6 +61,27 @@ pub fn dispatcher(syscall_id: usize, arg1: usize, arg2: usize, arg3: usize, arg4
         GUI_SUBSCRIBE_TIME_UPDATE => service::gui_time_update_register(),
         READ_TIME => service::read_time(),
         GUI_SUBSCRIBE_KEYBOARD => service::gui_time_update_register(),
+        CHDIR => service::chdir(arg1),
+        GETCWD => service::getcwd(),
+        GETENV => service::getenv(arg1),
+        SETENV => {
+            service::setenv(arg1);
+            0
+        }
+        ENVIRON => service::environ(),
+        FLOCK => service::flock(arg1),
+        OPENDIR => service::open_dir(arg1),
+        READDIR => service::read_dir(arg1),
+        CLOSEDIR => service::close_dir(arg1),
+        SYNC => service::sync(),
+        SOCKET => service::socket(arg1),
+        BIND => service::bind(arg1),
+        LISTEN => service::listen(arg1),
+        CONNECT => service::connect(arg1),
+        SEND => service::send(arg1),
+        RECV => service::recv(arg1),
+        SOCKET_STATUS => service::socket_status(arg1),
+        CLOSE_SOCKET => service::close_socket(arg1),
         _ => panic!("unknown syscall id: {}", syscall_id),
     })
 }
@@ -1,3 +1,4 @@
+use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::vec::Vec;
 use core::ptr::slice_from_raw_parts_mut;
@@ -6,8 +7,9 @@ use core::sync::atomic::Ordering;
 use embedded_graphics::pixelcolor::raw::RawU24;
 use embedded_graphics::pixelcolor::Rgb888;
 
-use cinea_os_sysapi::fs::read_all_from_path;
+use cinea_os_sysapi::fs::{LockMode, OpenFlags, SeekFrom};
 use cinea_os_sysapi::gui::WindowGraphicMemory;
+use cinea_os_sysapi::net::{SocketAddr, SocketType};
 use cinea_os_sysapi::syscall::PanicInfo;
 use cinea_os_sysapi::time::{Date, DateTime, Time};
 use cinea_os_sysapi::ExitCode;
@@ -47,14 +49,14 @@ pub fn spawn(number: usize, args_ptr: usize, args_len: usize, args_cap: usize) -
 }
 
 pub fn spawn_from_path(ptr: usize) -> usize {
-    let obj: (String, Vec<String>) = syscall_deserialize!(ptr);
+    let obj: (String, Vec<String>, Option<BTreeMap<String, String>>) = syscall_deserialize!(ptr);
 
-    if let Ok(program_bytes) = read_all_from_path(obj.0.as_str()) {
+    if let Ok(program_bytes) = syskrnl::fs::read_all(obj.0.as_str()) {
         // 为了兼容旧代码，姑且做一层转换吧
         let trans_args: Vec<_> = obj.1.iter().map(|x| (x.as_ptr() as usize, x.len())).collect();
         let (a, b, c) = trans_args.into_raw_parts();
 
-        if let Err(_) = Process::spawn(program_bytes.as_slice(), a as usize, b, c) {
+        if let Err(_) = Process::spawn_with_env(program_bytes.as_slice(), obj.2, a as usize, b, c) {
             syscall_serialized_ret!(&false)
         } else {
             syscall_serialized_ret!(&true)
@@ -145,12 +147,94 @@ pub fn list(ptr: usize) -> usize {
 }
 
 pub fn open(ptr: usize) -> usize {
-    let obj: (String, bool) = syscall_deserialize!(ptr);
+    let obj: (String, OpenFlags) = syscall_deserialize!(ptr);
 
     let ptr_back = syscall_serialized_ret!(&syskrnl::fs::open(obj.0.as_str(), obj.1));
     ptr_back
 }
 
+pub fn close(ptr: usize) -> usize {
+    let obj: usize = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::close(obj));
+    ptr_back
+}
+
+pub fn flock(ptr: usize) -> usize {
+    let obj: (usize, LockMode) = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::flock(obj.0, obj.1));
+    ptr_back
+}
+
+pub fn open_dir(ptr: usize) -> usize {
+    let obj: String = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::open_dir(obj.as_str()));
+    ptr_back
+}
+
+pub fn read_dir(ptr: usize) -> usize {
+    let obj: (usize, usize) = syscall_deserialize!(ptr); // 参数1：句柄，2：最多读出的项数
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::read_dir(obj.0, obj.1));
+    ptr_back
+}
+
+pub fn close_dir(ptr: usize) -> usize {
+    let obj: usize = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::close_dir(obj));
+    ptr_back
+}
+
+pub fn sync() -> usize {
+    syscall_serialized_ret!(&syskrnl::fs::sync())
+}
+
+pub fn socket(ptr: usize) -> usize {
+    let obj: SocketType = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::net::socket(obj));
+    ptr_back
+}
+
+pub fn bind(ptr: usize) -> usize {
+    let obj: (usize, u16) = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::net::bind(obj.0, obj.1));
+    ptr_back
+}
+
+pub fn listen(ptr: usize) -> usize {
+    let obj: (usize, u16) = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::net::listen(obj.0, obj.1));
+    ptr_back
+}
+
+pub fn connect(ptr: usize) -> usize {
+    let obj: (usize, SocketAddr) = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::net::connect(obj.0, obj.1));
+    ptr_back
+}
+
+pub fn send(ptr: usize) -> usize {
+    let obj: (usize, Vec<u8>, Option<SocketAddr>) = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::net::send(obj.0, obj.1.as_slice(), obj.2));
+    ptr_back
+}
+
+pub fn recv(ptr: usize) -> usize {
+    let obj: (usize, usize) = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::net::recv(obj.0, obj.1));
+    ptr_back
+}
+
+pub fn socket_status(ptr: usize) -> usize {
+    let obj: usize = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::net::status(obj));
+    ptr_back
+}
+
+pub fn close_socket(ptr: usize) -> usize {
+    let obj: usize = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::net::close(obj));
+    ptr_back
+}
+
 pub fn info(ptr: usize) -> usize {
     let obj: String = syscall_deserialize!(ptr);
 
@@ -164,11 +248,30 @@ pub fn write_all(ptr: usize) -> usize {
     ptr_back
 }
 
+pub fn write(ptr: usize) -> usize {
+    let obj: (usize, Vec<u8>) = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::write(obj.0, obj.1.as_slice()));
+    ptr_back
+}
+
+pub fn seek(ptr: usize) -> usize {
+    let obj: (usize, SeekFrom) = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::seek(obj.0, obj.1));
+    ptr_back
+}
+
+pub fn ftruncate(ptr: usize) -> usize {
+    let obj: (usize, u64) = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::ftruncate(obj.0, obj.1));
+    ptr_back
+}
+
 pub fn read(ptr: usize) -> usize {
     // 这个有点复杂了
     let obj: (usize, usize, usize) = syscall_deserialize!(ptr); // 参数1：句柄，2：地址，3：长度
     let slice = unsafe { &mut *slice_from_raw_parts_mut(obj.1 as *mut u8, obj.2) };
-    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::read(obj.0, slice));
+    let result = syskrnl::fs::read(obj.0, slice).and_then(|len| Ok((len, syskrnl::fs::remaining(obj.0)?)));
+    let ptr_back = syscall_serialized_ret!(&result);
     ptr_back
 }
 
@@ -186,6 +289,63 @@ pub fn read_path(ptr: usize) -> usize {
     ptr_back
 }
 
+pub fn read_all(ptr: usize) -> usize {
+    let obj: String = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::read_all(obj.as_str()));
+    ptr_back
+}
+
+pub fn create(ptr: usize) -> usize {
+    let obj: String = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::create(obj.as_str()));
+    ptr_back
+}
+
+pub fn mkdir(ptr: usize) -> usize {
+    let obj: String = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::create_dir(obj.as_str()));
+    ptr_back
+}
+
+pub fn rename(ptr: usize) -> usize {
+    let obj: (String, String) = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::rename(obj.0.as_str(), obj.1.as_str()));
+    ptr_back
+}
+
+pub fn truncate(ptr: usize) -> usize {
+    let obj: (String, u64) = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::truncate(obj.0.as_str(), obj.1));
+    ptr_back
+}
+
+pub fn chdir(ptr: usize) -> usize {
+    let obj: String = syscall_deserialize!(ptr);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::change_dir(obj.as_str()));
+    ptr_back
+}
+
+pub fn getcwd() -> usize {
+    syscall_serialized_ret!(&syskrnl::fs::current_dir())
+}
+
+pub fn getenv(ptr: usize) -> usize {
+    let obj: String = syscall_deserialize!(ptr);
+    syscall_serialized_ret!(&proc::env(obj.as_str()))
+}
+
+pub fn setenv(ptr: usize) {
+    let obj: (String, Option<String>) = syscall_deserialize!(ptr);
+    match obj.1 {
+        Some(val) => proc::set_env(obj.0.as_str(), val.as_str()),
+        None => proc::remove_env(obj.0.as_str()),
+    }
+}
+
+pub fn environ() -> usize {
+    syscall_serialized_ret!(&proc::envs())
+}
+
 pub fn panic(ptr: usize) -> usize {
     let obj: PanicInfo = syscall_deserialize!(ptr);
     println!("{:?}", obj);
@@ -1,3 +1,5 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
 use alloc::vec::Vec;
 use core::pin::Pin;
 use core::task::{Context, Poll};
@@ -13,6 +15,7 @@ use x86::io::inb;
 use x86_64::instructions::interrupts;
 
 use cinea_os_sysapi::event::*;
+use cinea_os_sysapi::fs::FileIO;
 
 use crate::syskrnl;
 use crate::syskrnl::clock::GUI_TIME_UPDATE_EVENT_NEEDER;
@@ -32,6 +35,7 @@ pub fn init() {
     interrupts::without_interrupts(|| {
         syskrnl::interrupts::set_irq_handler(1, keyboard_interrupt_handler);
     });
+    syskrnl::fs::device::register_device("stdin", Box::new(StdInDevice)).unwrap();
 }
 
 pub(crate) fn add_scancode(scancode: u8) {
@@ -113,8 +117,44 @@ fn undk_handler(ch: KeyCode) {
     }
 }
 
-fn key_event_handler(ch: char) {
+pub(crate) fn key_event_handler(ch: char) {
     if let Some(pid) = event::EVENT_QUEUE.lock().wakeup_with_ret(KEYBOARD_INPUT, ch as u32 as usize) {
         SCHEDULER.lock().wakeup(pid);
+    } else {
+        // 没有进程在等待按键，暂存给`/dev/stdin`
+        let mut buffer = STDIN_BUFFER.lock();
+        let mut bytes = [0u8; 4];
+        for byte in ch.encode_utf8(&mut bytes).as_bytes() {
+            if buffer.len() >= STDIN_BUFFER_SIZE {
+                buffer.pop_front();
+            }
+            buffer.push_back(*byte);
+        }
+    }
+}
+
+const STDIN_BUFFER_SIZE: usize = 256;
+
+lazy_static! {
+    static ref STDIN_BUFFER: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::with_capacity(STDIN_BUFFER_SIZE));
+}
+
+/// 标准输入设备，`/dev/stdin`
+///
+/// 读取不会阻塞，只返回没有被`getch`取走的按键（UTF-8编码），缓冲区满时丢弃最早的输入。写入的数据被丢弃。
+pub struct StdInDevice;
+
+impl FileIO for StdInDevice {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        let mut buffer = STDIN_BUFFER.lock();
+        let len = buf.len().min(buffer.len());
+        for (dst, src) in buf.iter_mut().zip(buffer.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        Ok(buf.len())
     }
 }
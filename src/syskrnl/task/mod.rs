@@ -1,6 +1,7 @@
 pub mod executor;
 pub mod keyboard;
 pub mod mouse;
+pub mod serial;
 
 use alloc::boxed::Box;
 use core::future::Future;
@@ -0,0 +1,135 @@
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use conquer_once::spin::OnceCell;
+use crossbeam::queue::ArrayQueue;
+use futures_util::task::AtomicWaker;
+use futures_util::{Stream, StreamExt};
+
+use crate::syskrnl::task::keyboard::key_event_handler;
+
+static SERIAL_BYTE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+pub(crate) fn add_byte(byte: u8) {
+    if let Ok(queue) = SERIAL_BYTE_QUEUE.try_get() {
+        if let Err(_) = queue.push(byte) {
+            debugln!("警告：串口输入队列已满; 正在丢弃串口输入");
+        } else {
+            WAKER.wake();
+        }
+    }
+    // 队列初始化之前收到的字节直接丢弃：这时还没有进程能读取输入
+}
+
+pub struct SerialByteStream {
+    _private: (),
+}
+
+impl SerialByteStream {
+    pub fn new() -> Self {
+        SERIAL_BYTE_QUEUE
+            .try_init_once(|| ArrayQueue::new(256))
+            .expect("SerialByteStream::new 只应当被调用一次哦");
+        SerialByteStream { _private: () }
+    }
+}
+
+impl Stream for SerialByteStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let queue = SERIAL_BYTE_QUEUE.try_get().expect("未初始化");
+
+        if let Some(byte) = queue.pop() {
+            return Poll::Ready(Some(byte));
+        }
+
+        WAKER.register(&cx.waker());
+        match queue.pop() {
+            Some(byte) => {
+                WAKER.take();
+                Poll::Ready(Some(byte))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// 把串口收到的字节解码成和键盘一样的字符
+///
+/// 终端按回车发送`\r`，脚本通常发送`\n`或`\r\n`，都当作一次回车；退格键发送的DEL当作键盘的退格`\x08`。
+/// 多字节的UTF-8字符凑齐后才输出，无效的字节被丢弃。
+struct SerialDecoder {
+    pending: [u8; 4],
+    len: usize,
+    last_cr: bool,
+}
+
+impl SerialDecoder {
+    fn new() -> Self {
+        Self {
+            pending: [0; 4],
+            len: 0,
+            last_cr: false,
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> Option<char> {
+        let last_cr = core::mem::replace(&mut self.last_cr, byte == b'\r');
+        let byte = match byte {
+            b'\n' if last_cr => return None,
+            b'\r' => b'\n',
+            0x7F => 0x08,
+            byte => byte,
+        };
+        self.pending[self.len] = byte;
+        self.len += 1;
+        match core::str::from_utf8(&self.pending[..self.len]) {
+            Ok(s) => {
+                self.len = 0;
+                s.chars().next()
+            }
+            Err(e) => {
+                if e.error_len().is_some() || self.len == self.pending.len() {
+                    self.len = 0;
+                }
+                None
+            }
+        }
+    }
+}
+
+/// 串口输入：和键盘输入一样交给等待`KEYBOARD_INPUT`的进程，或者暂存给`/dev/stdin`
+pub async fn serial_input_handler() {
+    let mut bytes = SerialByteStream::new();
+    let mut decoder = SerialDecoder::new();
+
+    while let Some(byte) = bytes.next().await {
+        if let Some(ch) = decoder.push(byte) {
+            key_event_handler(ch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+
+    use super::SerialDecoder;
+
+    fn decode(bytes: &[u8]) -> String {
+        let mut decoder = SerialDecoder::new();
+        bytes.iter().filter_map(|byte| decoder.push(*byte)).collect()
+    }
+
+    #[test_case]
+    fn test_serial_decoder() {
+        assert_eq!(decode(b"ls\r"), "ls\n");
+        assert_eq!(decode(b"ls\r\ncd\n"), "ls\ncd\n");
+        assert_eq!(decode(b"a\x7f"), "a\x08");
+        assert_eq!(decode("塔菲".as_bytes()), "塔菲");
+        assert_eq!(decode(b"\xffok"), "ok");
+        println!("[ok]  Serial input decoding")
+    }
+}
@@ -72,6 +72,7 @@ pub fn init() {
     cmos::init();
     tsc::init();
     sleep::init();
+    crate::syskrnl::fs::device::register_device("uptime", alloc::boxed::Box::new(UpTimeDevice)).unwrap();
 }
 
 pub struct UpTimeDevice;
@@ -80,8 +81,9 @@ impl FileIO for UpTimeDevice {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
         let uptime = uptime();
         let slice = uptime.to_le_bytes();
-        buf.copy_from_slice(&slice);
-        Ok(8)
+        let len = buf.len().min(slice.len());
+        buf[..len].copy_from_slice(&slice[..len]);
+        Ok(len)
     }
 
     fn write(&mut self, _buf: &[u8]) -> Result<usize, ()> {
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::String;
+
+use cinea_os_sysapi::net::{SocketAddr, TcpStream};
+use cinea_os_sysapi::{allocator, entry_point};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// 用法：`nc <ip>:<port> <消息>...`，连接后发送消息，打印收到的数据直到对方关闭连接
+fn main(args: &[&str]) {
+    if args.is_empty() {
+        print!("usage: nc <ip>:<port> <message>...\n");
+        return;
+    }
+    let addr = match SocketAddr::parse(args[0]) {
+        Some(addr) => addr,
+        None => {
+            print!("nc: invalid address: {}\n", args[0]);
+            return;
+        }
+    };
+    let mut stream = match TcpStream::connect(addr) {
+        Ok(stream) => stream,
+        Err(e) => {
+            print!("nc: {}: {:?}\n", args[0], e);
+            return;
+        }
+    };
+
+    let mut message = String::new();
+    for (i, arg) in args[1..].iter().enumerate() {
+        if i > 0 {
+            message.push(' ');
+        }
+        message.push_str(arg);
+    }
+    message.push('\n');
+    if let Err(e) = stream.write_all(message.as_bytes()) {
+        print!("nc: {:?}\n", e);
+        return;
+    }
+
+    let mut buf = [0u8; 512];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => print!("{}", String::from_utf8_lossy(&buf[..len]).as_ref()),
+            Err(e) => {
+                print!("nc: {:?}\n", e);
+                break;
+            }
+        }
+    }
+}
@@ -5,10 +5,10 @@ extern crate alloc;
 
 use alloc::string::String;
 use alloc::vec::Vec;
-use core::ops::Add;
 
 use cinea_os_sysapi::{allocator, entry_point};
-use cinea_os_sysapi::fs::spawn_from_path;
+use cinea_os_sysapi::env;
+use cinea_os_sysapi::fs::{chdir, getcwd, info, path_combine, spawn_from_path};
 use cinea_os_sysapi::stdin::get_line_string;
 use cinea_os_sysapi::syscall::spawn;
 use cinea_os_userspace::print;
@@ -22,6 +22,9 @@ static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
 
 //const VERSION:&str = "v0.1.0";
 
+/// 未设置`PATH`时查找程序的目录
+const DEFAULT_PATH: &str = "/bin";
+
 #[inline(always)]
 fn is_blank_character(ch: char) -> bool {
     match ch {
@@ -104,17 +107,58 @@ fn resolve_command(command: &str) -> Result<Vec<String>, ResolveError> {
     Ok(result)
 }
 
+/// 内建命令，返回是否已处理
+fn builtin(resolved: &[String]) -> bool {
+    match resolved[0].as_str() {
+        "cd" => {
+            let target = resolved.get(1).map(|x| x.as_str()).unwrap_or("/");
+            if let Err(e) = chdir(target) {
+                print!("cd: {}: {:?}\n", target, e);
+            }
+            true
+        }
+        "pwd" => {
+            print!("{}\n", getcwd().as_str());
+            true
+        }
+        "export" => {
+            for assignment in resolved[1..].iter() {
+                match assignment.split_once('=') {
+                    Some((key, val)) => env::set_var(key, val),
+                    None => print!("export: 格式应为KEY=VALUE\n"),
+                }
+            }
+            true
+        }
+        _ => false
+    }
+}
+
+/// 在`PATH`中查找程序，含有`/`的命令直接作为路径使用
+fn find_program(command: &str) -> Option<String> {
+    if command.contains('/') {
+        return Some(String::from(command));
+    }
+    let path = env::var("PATH").unwrap_or_else(|| String::from(DEFAULT_PATH));
+    path.split(':')
+        .filter(|dir| dir.len() > 0)
+        .map(|dir| path_combine(dir, command))
+        .find(|exec_path| info(exec_path.as_str()).map(|x| x.is_file()).unwrap_or(false))
+}
+
 fn main(_args: &[&str]) {
-    let nowdir = String::from("~");
+    if env::var("PATH").is_none() {
+        env::set_var("PATH", DEFAULT_PATH);
+    }
 
     loop {
-        print!("{} $ ", nowdir.as_str());
+        print!("{} $ ", getcwd().as_str());
 
         let cmd = get_line_string(false);
         match resolve_command(cmd.as_str()) {
             Err(ResolveError::BrokenQuote) => { print!("不合法的引号\n") },
             Ok(resolved) => {
-                if resolved.len() == 0 {
+                if resolved.len() == 0 || builtin(resolved.as_slice()) {
                     continue;
                 }
                 print!("---Debug Message---\n");
@@ -125,8 +169,11 @@ fn main(_args: &[&str]) {
                     print!("{} ", resolved[i].as_str())
                 }
                 print!("\n-------------------\n");
-                let exec_path = String::from("/bin/").add(resolved[0].as_str());
-                if !spawn_from_path(exec_path.as_str(), resolved.as_slice()[1..].iter().cloned().collect()) {
+                let spawned = match find_program(resolved[0].as_str()) {
+                    Some(exec_path) => spawn_from_path(exec_path.as_str(), resolved.as_slice()[1..].iter().cloned().collect()),
+                    None => false
+                };
+                if !spawned {
                     print!("程序\"{}\"没有找到", resolved[0].as_str());
                 }
             }
@@ -1,8 +1,18 @@
 import os
 import sys
 
-FS = "datadisk.img"
+# Format of the data disk: "fat" is built by fs-compiler, "ext2" by mke2fs from e2fsprogs
+FS_TYPE = os.environ.get("CINEA_FS_TYPE", "fat")
+FS = "datadisk.img" if FS_TYPE == "fat" else "datadisk-" + FS_TYPE + ".img"
 FS_SOURCE = "dsk"
+EXT2_SIZE = "64M"
+# Controller of the data disk: "ahci", "virtio" for a virtio-blk disk, or "ide" for QEMU's default -drive configuration
+DISK_BUS = os.environ.get("CINEA_DISK_BUS", "ahci")
+# Extra raw images attached after the data disk on the same bus, separated by commas;
+# they show up as /dev/sdb, /dev/sdc... on AHCI, /dev/vdb, /dev/vdc... on virtio and /dev/hdc, /dev/hdd on IDE
+EXTRA_DISKS = [x for x in os.environ.get("CINEA_EXTRA_DISKS", "").split(",") if x]
+# Set to 1 when the kernel was built with `--features headless`: QEMU runs without a window and the console is the serial port
+HEADLESS = os.environ.get("CINEA_HEADLESS", "0") == "1"
 ALWAYS_FETCH_TOOLS = False
 ALWAYS_RECOMPILE_TOOLS = False
 ALWAYS_RECOMPILE = False
@@ -40,29 +50,30 @@ if len(argv) < 2:
 
 BOOT_IMAGE = argv[1]
 
-print("Checking need for compile FS Helper...")
 FS_COMPLIER = EXE_PREFIX + "fs-compiler" + EXE_SUFFIX
-if ALWAYS_FETCH_TOOLS:
-    os.system("git submodule update --remote")
-if ALWAYS_RECOMPILE_TOOLS or not os.path.exists(FS_COMPLIER):
-    os.makedirs("../tmp-compiling", exist_ok=True)
-    TOOL_DIR = "../tmp-compiling"
-
-    if os.path.exists("../tmp-compiling"):
+if FS_TYPE == "fat":
+    print("Checking need for compile FS Helper...")
+    if ALWAYS_FETCH_TOOLS:
+        os.system("git submodule update --remote")
+    if ALWAYS_RECOMPILE_TOOLS or not os.path.exists(FS_COMPLIER):
+        os.makedirs("../tmp-compiling", exist_ok=True)
+        TOOL_DIR = "../tmp-compiling"
+
+        if os.path.exists("../tmp-compiling"):
+            shutil.rmtree("../tmp-compiling", ignore_errors=True)
+
+        if not os.path.exists("src/tools/fs-compiler"):
+            print("Cloning fs-compiler...")
+            os.system("git clone git@github.com:Cinea4678/TJU-cinea-os-tools.git ../tmp-compiling")
+        else:
+            shutil.copytree("src/tools/fs-compiler", "../tmp-compiling/fs-compiler", symlinks=True)
+
+        os.chdir(TOOL_DIR + "/fs-compiler")
+        os.system("cargo build --release")
+        os.chdir(PWD)
+        shutil.copy(TOOL_DIR + "/fs-compiler/target/release/" + FS_COMPLIER, FS_COMPLIER)
         shutil.rmtree("../tmp-compiling", ignore_errors=True)
 
-    if not os.path.exists("src/tools/fs-compiler"):
-        print("Cloning fs-compiler...")
-        os.system("git clone git@github.com:Cinea4678/TJU-cinea-os-tools.git ../tmp-compiling")
-    else:
-        shutil.copytree("src/tools/fs-compiler", "../tmp-compiling/fs-compiler", symlinks=True)
-
-    os.chdir(TOOL_DIR + "/fs-compiler")
-    os.system("cargo build --release")
-    os.chdir(PWD)
-    shutil.copy(TOOL_DIR + "/fs-compiler/target/release/" + FS_COMPLIER, FS_COMPLIER)
-    shutil.rmtree("../tmp-compiling", ignore_errors=True)
-
 print("Checking need for recompile the file system...")
 re_compile = False
 if not os.path.exists(FS):
@@ -73,12 +84,37 @@ elif get_latest_modified_time(FS_SOURCE) > os.path.getmtime(FS):
     re_compile = True
 if ALWAYS_RECOMPILE or re_compile:
     print("Recompiling the file system...")
-    os.system(FS_COMPLIER + " " + FS_SOURCE + " " + FS)
+    if FS_TYPE == "ext2":
+        if os.path.exists(FS):
+            os.remove(FS)
+        os.system(f"mke2fs -q -F -t ext2 -b 1024 -d {FS_SOURCE} {FS} {EXT2_SIZE}")
+    else:
+        os.system(FS_COMPLIER + " " + FS_SOURCE + " " + FS)
 else:
     print("File System is already newest.")
 
+# The AHCI controller has 6 ports and the IDE bus 4 slots, the first of which holds the boot disk
+MAX_EXTRA_DISKS = {"virtio": len(EXTRA_DISKS), "ide": 2}.get(DISK_BUS, 5)
+if len(EXTRA_DISKS) > MAX_EXTRA_DISKS:
+    print(f"At most {MAX_EXTRA_DISKS} extra disks can be attached to the {DISK_BUS} bus")
+    exit(1)
+
+if DISK_BUS == "ide":
+    EXTRA_DRIVES = "".join(f" -drive format=raw,file={disk},index={i}" for i, disk in enumerate(EXTRA_DISKS, start=2))
+    DATA_DRIVE = f"-drive format=raw,file={FS},index=1{EXTRA_DRIVES}"
+elif DISK_BUS == "virtio":
+    EXTRA_DRIVES = "".join(f" -drive format=raw,file={disk},if=virtio" for disk in EXTRA_DISKS)
+    DATA_DRIVE = f"-drive format=raw,file={FS},if=virtio{EXTRA_DRIVES}"
+else:
+    EXTRA_DRIVES = "".join(
+        f" -drive id=disk{i},format=raw,file={disk},if=none -device ide-hd,drive=disk{i},bus=ahci.{i}"
+        for i, disk in enumerate(EXTRA_DISKS, start=1))
+    DATA_DRIVE = f"-drive id=data_disk,format=raw,file={FS},if=none \
+          -device ahci,id=ahci -device ide-hd,drive=data_disk,bus=ahci.0{EXTRA_DRIVES}"
+
+DISPLAY = "-display none" if HEADLESS else ""
+
 print("Starting QEMU...", flush=True)
 os.system(f"qemu-system-x86_64 -drive format=raw,file={BOOT_IMAGE} -serial \
           stdio -m 1G -monitor telnet:localhost:4444,server,nowait \
-          -drive id=data_disk,format=raw,file=datadisk.img,if=none \
-          -device ahci,id=ahci -device ide-hd,drive=data_disk,bus=ahci.0")
+          {DATA_DRIVE} -nic user,model=virtio-net-pci {DISPLAY}")